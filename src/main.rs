@@ -1,21 +1,164 @@
-use clap::{Args, Parser, Subcommand};
+use anstream::{eprint, eprintln, println};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use fs2::FileExt;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::ErrorKind;
+use std::io::{BufRead, BufReader, ErrorKind, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use urlencoding::encode;
 
 const APP_NAME: &str = "bippi";
 const CONFIG_FILENAME: &str = "config.json";
+const CONFIG_LOCK_FILENAME: &str = "config.lock";
+const CONFIG_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const ARCHIVE_DIRNAME: &str = "archives";
 const MUSICBRAINZ_BASE_URL: &str = "https://musicbrainz.org/ws/2";
-const MUSICBRAINZ_USER_AGENT: &str = "bippi/0.1.0 (https://github.com/landonrogers/bippi)";
+// Release links always point at musicbrainz.org, even when a mirror is queried.
+const MUSICBRAINZ_RELEASE_URL: &str = "https://musicbrainz.org/release";
+const COVER_ART_ARCHIVE_URL: &str = "https://coverartarchive.org";
+const DEFAULT_MUSICBRAINZ_TIMEOUT_SECS: u64 = 15;
+const DEFAULT_MIN_FREE_SPACE: u64 = 500 * 1024 * 1024;
+// Appended to YouTube album playlist searches; `config set-album-keyword`
+// changes or removes it.
+const DEFAULT_ALBUM_KEYWORD: &str = "album";
+// Stays under Windows' 260-character MAX_PATH with room for yt-dlp's ".part".
+const DEFAULT_MAX_PATH_LENGTH: usize = 250;
+// Room for the longest audio extension (".opus", ".flac").
+const MAX_EXTENSION_LENGTH: usize = 5;
+const MIN_FILENAME_LENGTH: usize = 16;
+// --nest names playlist folders after a title only yt-dlp knows, so the folder
+// is cut to this length and that much is kept free of the file name budget.
+const PLAYLIST_FOLDER_LENGTH: usize = 80;
+const VARIOUS_ARTISTS: &str = "Various Artists";
+const DEFAULT_SINGLE_TEMPLATE: &str = "{title}";
+const DEFAULT_ALBUM_TEMPLATE: &str = "{track} - {title}";
+// Plain playlist downloads have always been saved under the video title alone.
+const DEFAULT_PLAYLIST_TEMPLATE: &str = "{title}";
+// MusicBrainz allows one anonymous request per second; token holders are usually
+// querying their own mirror or an agreed higher limit.
+const MUSICBRAINZ_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+const MUSICBRAINZ_AUTHENTICATED_INTERVAL: Duration = Duration::from_millis(200);
+const DEFAULT_MIX_LIMIT: u32 = 25;
+const SEARCH_PROBE_RESULTS: usize = 10;
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "m4a", "opus", "ogg", "flac", "wav", "aac"];
+const DEFAULT_TARGET_LUFS: f64 = -14.0;
+const TRUE_PEAK_LIMIT_DB: f64 = -1.5;
+const LOUDNESS_RANGE_TARGET: f64 = 11.0;
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+// Opus R128 gain tags are relative to EBU R128's -23 LUFS (RFC 7845).
+const R128_REFERENCE_LUFS: f64 = -23.0;
+const DEFAULT_SILENCE_THRESHOLD_DB: f64 = -60.0;
+const SILENCE_PADDING_SECS: f64 = 0.25;
+const DEFAULT_VERIFY_TOLERANCE_SECS: f64 = 5.0;
+const TRACK_SEARCH_RESULTS: usize = 5;
+const RELEASE_CANDIDATES: usize = 5;
+const UNCERTAIN_MATCH_SCORE: u32 = 90;
+const ARTIST_ALIAS_CANDIDATES: usize = 3;
+const ARTIST_ALIAS_MIN_SCORE: u32 = 90;
+const RECORDING_CANDIDATES: usize = 5;
+const DEFAULT_MATCH_THRESHOLD: f64 = 0.7;
+// `clean` and --restart leave partial files this fresh alone, since a download may own them.
+const PARTIAL_IN_USE_WINDOW: Duration = Duration::from_secs(10 * 60);
+const VIDEO_TITLE_NOISE: &[&str] = &[
+    "official",
+    "video",
+    "audio",
+    "lyrics",
+    "lyric",
+    "music",
+    "hd",
+    "hq",
+    "4k",
+    "remastered",
+    "remaster",
+    "visualizer",
+];
+
+// Bracketed groups or " - " suffixes containing one of these are dropped by
+// --clean-titles; `config title-noise` replaces this list.
+const DEFAULT_TITLE_NOISE: &[&str] = &[
+    "official audio",
+    "official video",
+    "official music video",
+    "lyric video",
+    "lyrics",
+    "audio",
+    "hd",
+    "hq",
+    "4k",
+    "remastered",
+    "remaster",
+    "visualizer",
+];
+// With --prefer-album-version, search results mentioning one of these are
+// avoided unless the album track's own title does; `config version-excludes`
+// replaces this list.
+const DEFAULT_VERSION_EXCLUDES: &[&str] = &["live", "remix", "cover"];
+// Words that mark a trailing "(...)" or "[...]" of an album title as an edition.
+const DEFAULT_ALBUM_SUFFIXES: &[&str] = &[
+    "deluxe",
+    "remaster",
+    "remastered",
+    "expanded edition",
+    "special edition",
+    "anniversary edition",
+    "legacy edition",
+    "bonus track version",
+];
+// Lower-cased by --case title unless they start or end a title or phrase.
+const TITLE_SMALL_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "nor", "of", "on", "or", "the",
+    "to", "vs", "with",
+];
+// Kept in capitals by --case title even when the whole title is upper-case.
+const TITLE_ACRONYMS: &[&str] = &[
+    "bbc", "cd", "dj", "ep", "lp", "mtv", "nyc", "tv", "uk", "usa",
+];
 
 type Result<T> = std::result::Result<T, AppError>;
 
+// Set when stdout carries downloaded audio, so progress messages must not mix with it.
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Colors are dropped by anstream when the stream is not a terminal, NO_COLOR is
+// set or --no-color is passed.
+#[derive(Clone, Copy, Debug)]
+enum Tone {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Tone {
+    fn paint(self, text: impl std::fmt::Display) -> String {
+        let color = match self {
+            Tone::Info => anstyle::AnsiColor::Cyan,
+            Tone::Success => anstyle::AnsiColor::Green,
+            Tone::Warning => anstyle::AnsiColor::Yellow,
+            Tone::Error => anstyle::AnsiColor::Red,
+        };
+        let style = anstyle::Style::new().fg_color(Some(color.into()));
+        format!("{style}{text}{style:#}")
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum AppError {
     #[error("{0}")]
@@ -26,26 +169,94 @@ enum AppError {
     MissingConfigDir,
     #[error("Failed to parse config: {0}")]
     ConfigParse(#[from] serde_json::Error),
+    #[error("Invalid config file {}: {detail}", path.display())]
+    InvalidConfig { path: PathBuf, detail: String },
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
-    #[error("MusicBrainz did not return any release for '{0}'")]
-    MusicBrainzNotFound(String),
+    #[error("{backend} did not return any release for '{query}'")]
+    AlbumNotFound {
+        backend: &'static str,
+        query: String,
+    },
+    #[error(
+        "MusicBrainz did not answer within {0}s; raise the limit with --mb-timeout or `config musicbrainz set timeout`"
+    )]
+    MusicBrainzTimeout(u64),
+    #[error("MusicBrainz sent a response that could not be read: {0}")]
+    MusicBrainzResponse(String),
+    #[error("MusicBrainz release '{0}' does not contain any tracks")]
+    EmptyRelease(String),
+    #[error("{backend} lookup failed: {detail}")]
+    MetadataLookupFailed {
+        backend: &'static str,
+        detail: String,
+    },
+    #[error("yt-dlp exited with status {0}")]
+    YtDlpFailed(i32),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
 }
 
 fn main() {
     if let Err(err) = run() {
-        eprintln!("error: {err}");
+        eprintln!("{} {err}", Tone::Error.paint("error:"));
         std::process::exit(1);
     }
 }
 
 fn run() -> Result<()> {
     let cli = Cli::parse();
-    let mut config = AppConfig::load()?;
+    if cli.no_color {
+        anstream::ColorChoice::Never.write_global();
+    }
+    // Commands that may save the config hold the lock across load-modify-save so
+    // concurrent writers serialize instead of dropping each other's changes.
+    let _lock = match cli.command {
+        // Probing every alias can take a while and never writes the config.
+        Commands::Alias {
+            command: AliasCommand::Test(_),
+        } => None,
+        Commands::Alias { .. } | Commands::Config { .. } => Some(ConfigLock::acquire()?),
+        Commands::Single(_)
+        | Commands::Album(_)
+        | Commands::Sync(_)
+        | Commands::Verify(_)
+        | Commands::Retag(_)
+        | Commands::Prune(_)
+        | Commands::Clean(_)
+        | Commands::UpdateYtdlp
+        | Commands::Version(_) => None,
+    };
+    let mut config = match cli.command {
+        // Never read, so a broken config cannot get in the way of updating
+        // yt-dlp or collecting versions for a bug report.
+        Commands::UpdateYtdlp | Commands::Version(_) => AppConfig::default(),
+        // `config` is how a broken field gets fixed, so it must still load;
+        // `clean` only needs the destination and checks that one itself.
+        Commands::Config { .. } | Commands::Clean(_) => AppConfig::load_lenient()?,
+        _ => AppConfig::load()?,
+    };
+    // Resolved up front but only checked by commands that talk to MusicBrainz, so a
+    // bad stored value can still be fixed with `config musicbrainz`.
+    let musicbrainz = MusicBrainzSettings::resolve(&cli, &config.musicbrainz);
 
     match cli.command {
-        Commands::Single(args) => handle_download(args, &config, DownloadMode::Single),
-        Commands::Album(args) => handle_download(args, &config, DownloadMode::Album),
+        Commands::Single(args) => {
+            download_with_notification(args, &config, &musicbrainz?, DownloadMode::Single)
+        }
+        Commands::Album(args) => {
+            download_with_notification(args, &config, &musicbrainz?, DownloadMode::Album)
+        }
+        Commands::Sync(args) => handle_sync(args, &config, &musicbrainz?),
+        Commands::Verify(args) => handle_verify(args, &musicbrainz?),
+        Commands::Retag(args) => handle_retag(args, &musicbrainz?),
+        Commands::Prune(args) => handle_prune(args, &config, &musicbrainz?),
+        Commands::Clean(args) => handle_clean(args, &config),
+        Commands::UpdateYtdlp => handle_update_ytdlp(),
+        Commands::Version(args) => {
+            handle_version(args);
+            Ok(())
+        }
         Commands::Alias { command } => {
             let changed = handle_alias(command, &mut config)?;
             if changed {
@@ -54,6 +265,15 @@ fn run() -> Result<()> {
             Ok(())
         }
         Commands::Config { command } => {
+            for (field, detail) in &config.invalid_fields {
+                eprintln!(
+                    "{}",
+                    Tone::Warning.paint(format!(
+                        "ignoring invalid config field '{}' ({}); saving any config change resets it to the default",
+                        field, detail
+                    ))
+                );
+            }
             let changed = handle_config(command, &mut config)?;
             if changed {
                 config.save()?;
@@ -63,970 +283,10372 @@ fn run() -> Result<()> {
     }
 }
 
+fn download_with_notification(
+    args: DownloadArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+    mode: DownloadMode,
+) -> Result<()> {
+    let notify = args.notify;
+    let result = handle_download(args, config, musicbrainz, mode);
+    if notify {
+        let (summary, body) = describe_notification(&result);
+        send_notification(&summary, &body);
+    }
+    result.map(drop)
+}
+
+fn describe_notification(result: &Result<Vec<PathBuf>>) -> (String, String) {
+    match result {
+        Ok(files) => {
+            let body = match files.first().and_then(|file| file.parent()) {
+                Some(dir) => format!(
+                    "{} track{} saved to {}",
+                    files.len(),
+                    if files.len() == 1 { "" } else { "s" },
+                    dir.display()
+                ),
+                None => "no new tracks were saved".to_string(),
+            };
+            (format!("{APP_NAME}: download finished"), body)
+        }
+        Err(err) => (format!("{APP_NAME}: download failed"), err.to_string()),
+    }
+}
+
+// Best effort: without a notification daemon (headless machines, SSH sessions)
+// nothing is shown and the download result stands.
+fn send_notification(summary: &str, body: &str) {
+    let _ = notify_rust::Notification::new()
+        .appname(APP_NAME)
+        .summary(summary)
+        .body(body)
+        .show();
+}
+
 #[derive(Clone, Copy, Debug)]
 enum DownloadMode {
     Single,
     Album,
 }
 
-fn handle_download(args: DownloadArgs, config: &AppConfig, mode: DownloadMode) -> Result<()> {
+// Tags an existing file from a MusicBrainz recording lookup; nothing is downloaded.
+fn write_tags_only(
+    args: DownloadArgs,
+    musicbrainz: &MusicBrainzSettings,
+    mode: DownloadMode,
+) -> Result<Vec<PathBuf>> {
+    if matches!(mode, DownloadMode::Album) {
+        return Err(AppError::Message(
+            "--write-tags-only tags one file; use the single command".to_string(),
+        ));
+    }
+    let Some(file) = args.file else {
+        return Err(AppError::Message(
+            "--write-tags-only needs --file <PATH>".to_string(),
+        ));
+    };
+    if !file.is_file() {
+        return Err(AppError::Message(format!(
+            "'{}' is not a file",
+            file.display()
+        )));
+    }
+    let ext = lowercase_extension(&file);
+    if !AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+        return Err(AppError::Message(format!(
+            "'{}' is not an audio file (expected {})",
+            file.display(),
+            AUDIO_EXTENSIONS.join(", ")
+        )));
+    }
+    let query = args.target.join(" ");
+    let query = query.trim();
+    if query.is_empty() || query == "-" {
+        return Err(AppError::Message(
+            "--write-tags-only needs the track to look up, e.g. \"Artist - Title\"".to_string(),
+        ));
+    }
+
+    let resolver = args.metadata_source.resolver(musicbrainz)?;
+    status!("searching {} for track '{}'", resolver.name(), query);
+    let Some(mut album) = resolver.find_recording(query)? else {
+        return Err(AppError::Message(format!(
+            "{} did not return any recording for '{}'",
+            resolver.name(),
+            query
+        )));
+    };
+    recase_album_titles(&mut album, args.case);
+    let track = &album.tracks[0];
+    status!(
+        "found track {:02} '{} - {}' on {}",
+        track.overall_index,
+        track_artist(&album, track),
+        track.title,
+        album.title
+    );
+    let tags = track_metadata_tags(
+        &album,
+        track,
+        album.track_total,
+        &ext,
+        TagOptions {
+            compilation: args.compilation,
+            date_source: args.date_source,
+            numbering_offset: 0,
+            embed_source_url: args.embed_source_url,
+        },
+    );
+    write_tags(&file, &tags)?;
+    status!(
+        "{}",
+        Tone::Success.paint(format!("tagged {}", file.display()))
+    );
+    Ok(vec![file])
+}
+
+// Returns the audio files that were written, in track order for MusicBrainz
+// albums; streaming to stdout and --simulate write none.
+fn handle_download(
+    mut args: DownloadArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+    mode: DownloadMode,
+) -> Result<Vec<PathBuf>> {
+    route_status_output(&args);
+    validate_download_args(&args, mode)?;
+    if let Some(path) = args.since_file.take() {
+        return download_since_file(&path, args, config, musicbrainz, mode);
+    }
+    if args.write_tags_only {
+        return write_tags_only(args, musicbrainz, mode);
+    }
+    if args.target == ["-"] || args.target.is_empty() {
+        if std::io::stdin().is_terminal() {
+            return Err(AppError::Message(
+                "no target given; pass a URL, alias or search query (or '-' to read them from stdin)"
+                    .to_string(),
+            ));
+        }
+        return download_stdin_targets(args, config, musicbrainz, mode);
+    }
     let DownloadArgs {
         target,
         dest,
         format,
+        min_duration,
+        max_duration,
+        max_filesize,
+        strict_allowlist,
+        yes_playlist,
+        no_playlist,
+        playlist_only,
+        video_only,
+        allow_mix,
+        mix_limit,
+        keep_video,
+        subtitles,
+        lyrics_from_subs,
+        write_thumbnail,
+        convert_thumbnails,
+        normalize,
+        normalize_album,
+        normalize_target,
+        replaygain,
+        album_gain_only,
+        trim_silence,
+        silence_threshold,
+        fade_in,
+        fade_out,
+        stdout,
+        format_fallback,
+        since,
+        since_file: _,
+        download_archive,
+        reverse,
+        format_sort,
+        geo_bypass,
+        geo_bypass_country,
+        cookies_from_browser,
+        impersonate,
+        no_impersonate,
+        extractor_args,
+        po_token,
+        match_threshold,
+        quiet_ytdlp,
+        progress_json,
+        no_embed_metadata,
+        write_info_json,
+        write_description,
+        write_playlist_metadata,
+        write_m3u,
+        compilation,
+        embed_source_url,
+        date_source,
+        dedupe,
+        clean_titles,
+        prefer_album_version,
+        no_feat_variants,
+        track_number,
+        nest,
+        thumbnail_as_folder_jpg,
+        cover_name,
+        flatten: _,
+        notify: _,
+        estimate,
+        no_space_check,
+        choose,
+        interactive,
+        summary,
+        tmp_dir,
+        no_continue,
+        restart,
+        output_template,
+        case,
+        limit_filename_length,
+        ytmusic,
+        no_ytmusic,
+        strip_album_suffix,
+        no_strip_album_suffix,
+        disc,
+        flat_numbering,
+        no_sanitize,
+        numbering_start,
+        min_results,
+        zip,
+        zip_only,
+        metadata_source,
+        continue_on_mb_error,
+        mb_only,
+        simulate,
+        max_downloads,
+        write_tags_only: _,
+        file: _,
     } = args;
 
+    let formats = build_format_chain(&format, &format_fallback)?;
+
+    let options = YtDlpOptions {
+        match_filter: build_duration_filter(min_duration, max_duration)?,
+        max_filesize: validate_max_filesize(max_filesize)?,
+        no_continue: no_continue || restart,
+        tmp_dir: tmp_dir
+            .or_else(|| config.tmp_dir.clone())
+            .map(|dir| validate_tmp_dir(&dir))
+            .transpose()?,
+        keep_video,
+        subtitle_langs: validate_subtitle_langs(subtitles)?,
+        lyrics_from_subs,
+        write_thumbnail,
+        convert_thumbnails,
+        date_after: validate_date_after(since)?,
+        download_archive,
+        format_sort: validate_format_sort(format_sort)?,
+        geo_bypass,
+        geo_bypass_country: validate_country_code(geo_bypass_country)?,
+        cookies_from_browser: validate_cookies_from_browser(cookies_from_browser)?,
+        impersonate: if no_impersonate {
+            None
+        } else {
+            impersonate
+                .or_else(|| config.impersonate.clone())
+                .map(validate_impersonate_target)
+                .transpose()?
+        },
+        extractor_args: build_extractor_args(
+            &config.extractor_args,
+            &extractor_args,
+            po_token.as_deref().or(config.po_token.as_deref()),
+        )?,
+        quiet: quiet_ytdlp,
+        stdout_to_stderr: progress_json,
+        no_embed_metadata,
+        write_info_json,
+        write_description,
+        simulate,
+        max_downloads: validate_max_downloads(max_downloads)?,
+    };
+
+    if let Some(target) = &options.impersonate {
+        check_impersonate_support(target)?;
+    }
+
+    let post_process = AudioPostProcess {
+        trim_silence: build_silence_trim(trim_silence, silence_threshold)?,
+        fade_in: validate_fade("--fade-in", fade_in)?,
+        fade_out: validate_fade("--fade-out", fade_out)?,
+        normalize: build_normalization(normalize, normalize_album, normalize_target)?,
+        replaygain,
+        album_gain_only,
+    };
+
     let joined_target = target.join(" ");
     let query = joined_target.trim();
     let query_owned = query.to_string();
+    let album_mode = matches!(mode, DownloadMode::Album);
 
-    let destination = if let Some(dest) = dest {
-        ensure_absolute(&dest)?
-    } else if let Some(config_dest) = &config.default_destination {
-        config_dest.clone()
+    let numbering_offset = numbering_start.map_or(0, |start| start as usize - 1);
+    let cover_name = thumbnail_as_folder_jpg
+        .then(|| validate_cover_name(&cover_name))
+        .transpose()?;
+
+    let destination = resolve_destination(dest.as_deref(), config, &formats[0])?;
+    let single_template = OutputTemplate::parse(
+        output_template
+            .as_deref()
+            .unwrap_or(config.template(TemplateKind::Single)),
+    )?;
+    let album_template = OutputTemplate::parse(
+        output_template
+            .as_deref()
+            .unwrap_or(config.template(TemplateKind::Album)),
+    )?;
+    let playlist_template = OutputTemplate::parse(
+        output_template
+            .as_deref()
+            .or(config.custom_template(TemplateKind::Album))
+            .unwrap_or(DEFAULT_PLAYLIST_TEMPLATE),
+    )?;
+
+    if !stdout {
+        ensure_directory_path(&destination, "destination")?;
+        if !simulate {
+            fs::create_dir_all(&destination)?;
+        }
+    }
+
+    let search_site = if ytmusic || (config.prefer_ytmusic && !no_ytmusic) {
+        SearchSite::YouTubeMusic
     } else {
-        std::env::current_dir()?
+        SearchSite::YouTube
     };
 
-    fs::create_dir_all(&destination)?;
-
     let alias_entry = config.aliases.get(query);
-    let album_mode = matches!(mode, DownloadMode::Album);
+    let release_download = ReleaseDownload {
+        destination: &destination,
+        formats: &formats,
+        options: &options,
+        post_process: &post_process,
+        match_threshold: validate_match_threshold(match_threshold)?,
+        musicbrainz,
+        progress_json,
+        tags: TagOptions {
+            compilation,
+            date_source,
+            numbering_offset,
+            embed_source_url,
+        },
+        dedupe,
+        title_noise: clean_titles.then_some(config.title_noise.as_slice()),
+        feat_variants: !no_feat_variants,
+        version_excludes: if prefer_album_version {
+            &config.version_excludes
+        } else {
+            &[]
+        },
+        album_suffixes: (strip_album_suffix
+            || (config.strip_album_suffix && !no_strip_album_suffix))
+            .then_some(config.album_suffixes.as_slice()),
+        disc,
+        flat_numbering,
+        file_naming: if no_sanitize {
+            FileNaming::Raw
+        } else {
+            FileNaming::Portable
+        },
+        case,
+        nest,
+        cover_name: cover_name.as_deref(),
+        max_path_length: limit_filename_length,
+        min_free_space: (!no_space_check && !simulate).then(|| config.min_free_space()),
+        choose,
+        summary,
+        album_template: &album_template,
+        search_site,
+        metadata_source,
+        zip: zip.as_deref().map(|path| AlbumZip {
+            path,
+            delete_loose: zip_only,
+        }),
+        write_m3u,
+        continue_on_lookup_error: continue_on_mb_error,
+        restart,
+    };
+
+    let offline = musicbrainz.offline;
+    if let Some(alias) = alias_entry
+        && let Some(mbid) = &alias.mbid
+        && !(offline && alias.url.is_some())
+    {
+        if stdout {
+            return Err(AppError::Message(format!(
+                "alias '{}' is a MusicBrainz release; --stdout can only stream a single track",
+                query
+            )));
+        }
+        return download_alias_release(query, alias, mbid, &release_download);
+    }
 
     if album_mode && alias_entry.is_none() && !looks_like_url(query) {
-        match download_album_with_musicbrainz(query, &destination, &format) {
-            Ok(()) => return Ok(()),
-            Err(AppError::MusicBrainzNotFound(_)) => {
-                println!(
-                    "MusicBrainz did not find a matching release; falling back to YouTube search"
+        if offline {
+            return Err(AppError::Message(format!(
+                "--offline disables the MusicBrainz lookup for '{}'; pass a playlist URL or an alias instead",
+                query
+            )));
+        }
+        match download_album_with_metadata(query, &release_download) {
+            Ok(produced) => return Ok(produced),
+            Err(err @ AppError::AlbumNotFound { .. }) if mb_only => return Err(err),
+            Err(AppError::AlbumNotFound { backend, .. }) => {
+                status!(
+                    "{} did not find a matching release; falling back to YouTube search",
+                    backend
+                );
+            }
+            Err(err @ AppError::MetadataLookupFailed { .. }) => {
+                status!(
+                    "{}",
+                    Tone::Warning.paint(format!("{}; falling back to YouTube search", err))
                 );
             }
             Err(err) => return Err(err),
         }
     }
+    if mb_only {
+        return Err(AppError::Message(format!(
+            "--mb-only: '{}' is not a MusicBrainz release; search by artist and album, or use an alias pinned with --mbid",
+            query
+        )));
+    }
+    if disc.is_some() {
+        return Err(AppError::Message(
+            "--disc needs a MusicBrainz release; playlists and single tracks have no discs"
+                .to_string(),
+        ));
+    }
+    if write_m3u {
+        return Err(AppError::Message(
+            "--write-m3u needs a MusicBrainz release for the track order; use --write-playlist-metadata for playlists"
+                .to_string(),
+        ));
+    }
+    if no_sanitize {
+        return Err(AppError::Message(
+            "--no-sanitize applies to MusicBrainz albums; playlist file names come from yt-dlp"
+                .to_string(),
+        ));
+    }
 
-    let (resolved_target, alias_album) = if let Some(alias) = alias_entry {
-        println!("using alias '{}' -> {}", query, alias.url);
-        (alias.url.clone(), alias.album)
+    let alias_target = alias_entry.and_then(|alias| Some((alias.url.clone()?, alias.album)));
+    let (resolved_target, alias_album) = if let Some((url, album)) = alias_target {
+        status!("using alias '{}' -> {}", query, url);
+        (url, album)
     } else if looks_like_url(query) {
         (query_owned.clone(), false)
     } else {
         match mode {
-            DownloadMode::Single => {
-                println!("searching YouTube for '{}' (first match)", query);
-                (build_single_search_query(query), false)
-            }
+            DownloadMode::Single => (
+                resolve_single_query(
+                    query,
+                    config,
+                    strict_allowlist,
+                    interactive,
+                    search_site,
+                    min_results,
+                )?,
+                false,
+            ),
             DownloadMode::Album => {
-                let resolved = resolve_album_query(query)?;
+                let resolved = resolve_album_query(query, config.album_keyword(), min_results)?;
                 (resolved, false)
             }
         }
     };
 
-    let download_album = alias_album || album_mode;
+    let download_album = if yes_playlist {
+        true
+    } else if no_playlist {
+        false
+    } else {
+        alias_album || album_mode
+    };
 
-    let output_template = destination.join("%(title)s.%(ext)s");
-    let output_template = output_template.to_string_lossy().to_string();
+    let (resolved_target, download_album) = match parse_watch_list_url(&resolved_target) {
+        Some(watch) if video_only => (watch.video_url(), false),
+        Some(watch) if playlist_only => (watch.playlist_url(), true),
+        Some(watch) if download_album && !yes_playlist => {
+            if is_mix_playlist_id(&watch.list_id) {
+                status!(
+                    "'{}' is a YouTube mix; downloading only the video (use --playlist-only to override)",
+                    watch.list_id
+                );
+                (watch.video_url(), false)
+            } else {
+                status!(
+                    "downloading playlist '{}' that the video belongs to (use --video-only for just the video)",
+                    watch.list_id
+                );
+                (watch.playlist_url(), true)
+            }
+        }
+        _ => (resolved_target, download_album),
+    };
 
-    let mut command = base_yt_dlp_command(&format, &output_template);
+    // Mixes are generated on the fly and never end, so they are refused as
+    // playlists unless asked for, and then capped.
+    let mix_limit = match playlist_list_id(&resolved_target) {
+        Some(list_id) if download_album && is_mix_playlist_id(&list_id) => {
+            if !allow_mix {
+                return Err(AppError::Message(format!(
+                    "'{list_id}' is a YouTube mix with no fixed end; pass --allow-mix to download its first {mix_limit} items"
+                )));
+            }
+            status!(
+                "'{list_id}' is a YouTube mix; downloading only its first {mix_limit} items (see --mix-limit)"
+            );
+            Some(mix_limit)
+        }
+        _ => None,
+    };
 
-    if download_album {
-        command.arg("--yes-playlist");
-    } else {
-        command.arg("--no-playlist");
+    if stdout {
+        if download_album {
+            return Err(AppError::Message(
+                "--stdout can only stream a single track, but the target is a playlist".to_string(),
+            ));
+        }
+        check_availability(&resolved_target, &options)?;
+        status!("streaming audio to stdout");
+        run_yt_dlp(stdout_yt_dlp_command(&options, &resolved_target))?;
+        return Ok(Vec::new());
     }
 
-    if should_apply_album_metadata(download_album, &resolved_target) {
-        command
-            .arg("--parse-metadata")
-            .arg("%(playlist_title|)s:%(meta_album)s")
-            .arg("--parse-metadata")
-            .arg("%(playlist_index)02d:%(meta_track_number)s");
+    if track_number.is_some() && download_album {
+        return Err(AppError::Message(
+            "--track-number numbers a single track, but the target is a playlist".to_string(),
+        ));
     }
 
-    command.arg(&resolved_target);
-
-    println!("saving audio to {} as {}", destination.display(), format);
-    run_yt_dlp(command)
-}
+    if !download_album && is_direct_url(&resolved_target) {
+        check_availability(&resolved_target, &options)?;
+    }
 
-fn base_yt_dlp_command(format: &str, output_template: &str) -> Command {
-    let mut command = Command::new("yt-dlp");
-    command
-        .arg("--ignore-errors")
-        .arg("--continue")
-        .arg("-x")
-        .arg("--audio-format")
-        .arg(format)
-        .arg("--output")
-        .arg(output_template)
-        .arg("--embed-metadata");
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
-    command
-}
+    let check_space = !no_space_check && !simulate;
+    let write_manifest = write_playlist_metadata && !simulate;
+    let flat_playlist = if download_album
+        && (estimate || check_space || write_manifest || release_download.zip.is_some())
+    {
+        probe_flat_playlist(&resolved_target, None)?
+    } else {
+        None
+    };
+    let playlist_estimate = flat_playlist
+        .as_ref()
+        .map(PlaylistEstimate::from_flat_playlist);
+    if estimate {
+        match &playlist_estimate {
+            Some(playlist) => status!("{}", describe_estimate(playlist, &formats[0])),
+            None if download_album => {
+                status!("could not list the playlist; skipping the size estimate")
+            }
+            None => status!("--estimate only applies to playlist downloads"),
+        }
+    }
+    if download_album && check_space {
+        check_free_space(
+            &destination,
+            playlist_estimate.as_ref(),
+            &formats[0],
+            config.min_free_space(),
+        )?;
+    }
+    if write_manifest {
+        match &flat_playlist {
+            Some(playlist) => {
+                let manifest = PlaylistManifest::from_flat_playlist(&resolved_target, playlist);
+                let path = manifest.write(&destination)?;
+                status!("saved the playlist manifest as {}", path.display());
+            }
+            None if download_album => {
+                status!("could not list the playlist; skipping the playlist manifest")
+            }
+            None => status!("--write-playlist-metadata only applies to playlist downloads"),
+        }
+    }
 
-fn run_yt_dlp(mut command: Command) -> Result<()> {
-    let status = command.status().map_err(map_yt_dlp_error)?;
+    let file_name = if download_album {
+        playlist_template.ytdlp_file_name(Some(&playlist_index_field(reverse, numbering_offset)))?
+    } else if let Some(number) = track_number {
+        album_template.ytdlp_file_name(Some(&format!("{:02}", number)))?
+    } else {
+        single_template.ytdlp_file_name(None)?
+    };
+    let nest_playlist = nest && download_album;
+    let file_template = if nest_playlist {
+        destination
+            .join(format!(
+                "%(playlist_title|Unknown Playlist).{}s",
+                PLAYLIST_FOLDER_LENGTH
+            ))
+            .join(file_name)
+    } else {
+        destination.join(file_name)
+    };
+    let file_template = file_template.to_string_lossy().to_string();
 
-    if status.success() {
-        Ok(())
+    let filename_length = if nest_playlist {
+        filename_budget(
+            &destination,
+            limit_filename_length.saturating_sub(PLAYLIST_FOLDER_LENGTH + 1),
+        )?
     } else {
-        Err(AppError::Message(format!(
-            "yt-dlp exited with status {}",
-            status.code().unwrap_or(-1)
-        )))
-    }
-}
+        filename_budget(&destination, limit_filename_length)?
+    };
 
-fn resolve_album_query(query: &str) -> Result<String> {
-    println!("searching YouTube for album '{}'", query);
+    let produced_files = ProducedFiles::new();
+    let build_command = |format: &str| {
+        let mut command = base_yt_dlp_command(format, &file_template, &options);
+        produced_files.record(&mut command);
+        command
+            .arg("--trim-filenames")
+            .arg(filename_length.to_string());
 
-    match find_album_playlist(query)? {
-        Some(url) => {
-            println!("found playlist match: {}", url);
-            Ok(url)
-        }
-        None => {
-            println!(
-                "no playlist found for '{}'; falling back to first search result",
-                query
-            );
-            Ok(build_single_search_query(query))
+        if download_album {
+            command.arg("--yes-playlist");
+        } else {
+            command.arg("--no-playlist");
         }
-    }
-}
 
-fn find_album_playlist(query: &str) -> Result<Option<String>> {
-    let search_term = format!("ytsearch10:{} album", query);
-    let output = Command::new("yt-dlp")
-        .arg("--flat-playlist")
-        .arg("-J")
-        .arg(&search_term)
-        .stdin(Stdio::null())
-        .output()
-        .map_err(map_yt_dlp_error)?;
+        if let Some(limit) = mix_limit {
+            command.arg("--playlist-end").arg(limit.to_string());
+        }
+        if reverse {
+            command.arg("--playlist-reverse");
+        }
 
-    if !output.status.success() {
-        return Ok(None);
-    }
+        // The parsed fields only reach the file through --embed-metadata.
+        if !options.no_embed_metadata
+            && should_apply_album_metadata(download_album, &resolved_target)
+        {
+            command
+                .arg("--parse-metadata")
+                .arg("%(playlist_title|)s:%(meta_album)s")
+                .arg("--parse-metadata")
+                .arg(track_number_metadata_field(reverse, numbering_offset));
+        }
+        if let Some(number) = track_number
+            && !options.no_embed_metadata
+        {
+            command
+                .arg("--parse-metadata")
+                .arg(format!("{}:%(meta_track_number)s", number));
+        }
 
-    let parsed: serde_json::Value = match serde_json::from_slice(&output.stdout) {
-        Ok(value) => value,
-        Err(_) => return Ok(None),
+        command.arg(&resolved_target);
+        command
     };
 
-    let entries = match parsed.get("entries").and_then(|value| value.as_array()) {
-        Some(entries) => entries,
-        None => return Ok(None),
-    };
+    status!(
+        "saving audio to {} as {}",
+        destination.display(),
+        describe_format_chain(&formats)
+    );
+    if restart {
+        discard_partial_downloads(&destination, options.tmp_dir.as_deref())?;
+    }
+    let started = SystemTime::now();
+    run_yt_dlp_with_fallback(&formats, !download_album, build_command)?;
+    if simulate {
+        status!(
+            "{}",
+            Tone::Success.paint("simulation passed; nothing was written")
+        );
+        return Ok(Vec::new());
+    }
 
-    for entry in entries {
-        if let Some(url) = playlist_url_from_entry(entry) {
-            return Ok(Some(url));
+    let produced = produced_files.paths()?;
+    // With --nest yt-dlp picks the folder names, so they come from the files.
+    let mut output_dirs: Vec<PathBuf> = produced
+        .iter()
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    output_dirs.dedup();
+    if options.lyrics_from_subs {
+        write_lyrics_from_subtitles(&produced)?;
+    }
+    if post_process.is_active() {
+        post_process_audio(&produced, &post_process)?;
+    }
+    let mut covers = Vec::new();
+    if let Some(name) = release_download.cover_name
+        && nest_playlist
+    {
+        for dir in &output_dirs {
+            let mut source = Command::new("yt-dlp");
+            source
+                .arg("--yes-playlist")
+                .arg("--playlist-items")
+                .arg("1")
+                .arg(&resolved_target);
+            covers.extend(save_thumbnail_cover(source, dir, name, &options)?);
         }
     }
+    if summary {
+        DownloadSummary::from_files(&destination, &produced, started)?.report(progress_json);
+    }
+    if let Some(zip) = release_download.zip {
+        // Items left out by the archive, size, date or duration filters or
+        // --max-downloads count as missing, like unmatched album tracks.
+        let expected = if download_album {
+            let Some(playlist) = &playlist_estimate else {
+                return Err(AppError::Message(format!(
+                    "could not list the playlist to check it is complete, so {} was not written; the downloaded files were kept",
+                    zip.path.display()
+                )));
+            };
+            mix_limit.map_or(playlist.tracks, |limit| playlist.tracks.min(limit as usize))
+        } else {
+            1
+        };
+        zip.ensure_complete(produced.len(), expected)?;
+        zip.write(&destination, &produced, &covers)?;
+    }
+    Ok(produced)
+}
 
-    Ok(None)
+// Conflicts between the flags and the command are caught here, before any
+// file is touched or anything is fetched; what depends on the resolved target
+// is checked as the download goes.
+fn validate_download_args(args: &DownloadArgs, mode: DownloadMode) -> Result<()> {
+    let album = matches!(mode, DownloadMode::Album);
+    if args.stdout && album {
+        return Err(AppError::Message(
+            "--stdout can only stream a single track; use the single command".to_string(),
+        ));
+    }
+    if args.interactive && album {
+        return Err(AppError::Message(
+            "--interactive picks a single track; use --choose to pick an album release".to_string(),
+        ));
+    }
+    if args.zip.is_some() && !album {
+        return Err(AppError::Message(
+            "--zip packages an album; use the album command".to_string(),
+        ));
+    }
+    if args.mb_only && !album {
+        return Err(AppError::Message(
+            "--mb-only applies to albums; use the album command".to_string(),
+        ));
+    }
+    if args.write_m3u && !album {
+        return Err(AppError::Message(
+            "--write-m3u lists an album's tracks; use the album command".to_string(),
+        ));
+    }
+    if args.reverse && !album {
+        return Err(AppError::Message(
+            "--reverse orders playlist downloads; use the album command".to_string(),
+        ));
+    }
+    if args.no_sanitize && !album {
+        return Err(AppError::Message(
+            "--no-sanitize applies to MusicBrainz albums; use the album command".to_string(),
+        ));
+    }
+    if args.track_number.is_some() && album {
+        return Err(AppError::Message(
+            "--track-number numbers a single track; use the single command".to_string(),
+        ));
+    }
+    if args.track_number == Some(0) {
+        return Err(AppError::Message(
+            "--track-number must be at least 1".to_string(),
+        ));
+    }
+    if args.numbering_start == Some(0) {
+        return Err(AppError::Message(
+            "--numbering-start must be at least 1".to_string(),
+        ));
+    }
+    if let Some(min) = args.min_results
+        && !(1..=SEARCH_PROBE_RESULTS).contains(&min)
+    {
+        return Err(AppError::Message(format!(
+            "--min-results must be between 1 and {}",
+            SEARCH_PROBE_RESULTS
+        )));
+    }
+    if args.mix_limit == 0 {
+        return Err(AppError::Message(
+            "--mix-limit must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
 }
 
-fn playlist_url_from_entry(entry: &serde_json::Value) -> Option<String> {
-    let entry_type = entry.get("_type").and_then(|v| v.as_str());
-    let ie_key = entry.get("ie_key").and_then(|v| v.as_str());
-    let url = entry.get("url").and_then(|v| v.as_str());
-    let playlist_id = entry.get("playlist_id").and_then(|v| v.as_str());
-    let id = entry.get("id").and_then(|v| v.as_str());
-    let fallback_id = playlist_id.or(id);
+struct DownloadSummary {
+    destination: PathBuf,
+    files: usize,
+    bytes: u64,
+    elapsed: Duration,
+    // Extensions of the files written, which differ from the request after a fallback.
+    formats: Vec<String>,
+}
 
-    if let Some(url) = url {
-        if url.contains("://") && url.contains("list=") {
-            return Some(url.to_string());
+impl DownloadSummary {
+    fn from_files(destination: &Path, files: &[PathBuf], started: SystemTime) -> Result<Self> {
+        let mut bytes = 0;
+        let mut written_formats = Vec::new();
+        for file in files {
+            bytes += fs::metadata(file)?.len();
+            let extension = lowercase_extension(file);
+            if !written_formats.contains(&extension) {
+                written_formats.push(extension);
+            }
         }
+        Ok(Self {
+            destination: destination.to_path_buf(),
+            files: files.len(),
+            bytes,
+            elapsed: started.elapsed().unwrap_or_default(),
+            formats: written_formats,
+        })
+    }
 
-        if matches!(entry_type, Some("playlist"))
-            || matches!(
-                ie_key,
-                Some("YoutubeTab" | "YoutubePlaylist" | "YoutubeMix")
-            )
-        {
-            return Some(normalize_playlist_url(url, fallback_id));
-        }
+    fn report(&self, progress_json: bool) {
+        status!("{}", Tone::Success.paint(self.describe()));
+        emit_progress(
+            progress_json,
+            serde_json::json!({
+                "event": "summary",
+                "files": self.files,
+                "bytes": self.bytes,
+                "elapsed_secs": self.elapsed.as_secs_f64(),
+                "destination": self.destination.display().to_string(),
+                "formats": self.formats,
+            }),
+        );
     }
 
-    if let Some(id) = fallback_id {
-        if id.starts_with("PL") || id.starts_with("OL") || id.starts_with("RD") {
-            return Some(format!("https://www.youtube.com/playlist?list={id}"));
+    fn describe(&self) -> String {
+        let mut line = format!(
+            "{} file{}, {} in {}",
+            self.files,
+            if self.files == 1 { "" } else { "s" },
+            format_size(self.bytes as f64),
+            format_duration(self.elapsed.as_secs_f64())
+        );
+        if !self.formats.is_empty() {
+            line.push_str(&format!(" as {}", self.formats.join(", ")));
         }
+        format!("{} -> {}", line, self.destination.display())
     }
+}
 
-    None
+#[derive(Debug, PartialEq)]
+struct PlaylistEstimate {
+    tracks: usize,
+    // Entries without a duration in the flat listing add nothing to `seconds`.
+    missing_durations: usize,
+    seconds: f64,
 }
 
-fn normalize_playlist_url(url: &str, fallback_id: Option<&str>) -> String {
-    if url.contains("://") {
-        url.to_string()
-    } else if url.starts_with("/playlist?") {
-        format!("https://www.youtube.com{url}")
-    } else if url.starts_with("playlist?") {
-        format!("https://www.youtube.com/{url}")
-    } else if url.starts_with("/watch?") {
-        format!("https://www.youtube.com{url}")
-    } else if url.starts_with("watch?") {
-        format!("https://www.youtube.com/{url}")
-    } else if let Some(id) = fallback_id {
-        format!("https://www.youtube.com/playlist?list={id}")
-    } else {
-        format!("https://www.youtube.com/playlist?list={url}")
+impl PlaylistEstimate {
+    fn from_tracks<'a>(tracks: impl IntoIterator<Item = &'a Track>) -> Self {
+        let durations: Vec<Option<f64>> = tracks.into_iter().map(|track| track.duration).collect();
+        let known: Vec<f64> = durations.iter().flatten().copied().collect();
+        Self {
+            tracks: durations.len(),
+            missing_durations: durations.len() - known.len(),
+            seconds: known.iter().fold(0.0, |total, secs| total + secs),
+        }
     }
-}
 
-fn map_yt_dlp_error(err: std::io::Error) -> AppError {
-    if err.kind() == ErrorKind::NotFound {
-        AppError::Message(
-            "yt-dlp was not found in PATH. Install it from https://github.com/yt-dlp/yt-dlp and try again.".to_string(),
-        )
-    } else {
-        AppError::Io(err)
+    fn bytes(&self, format: &str) -> f64 {
+        self.seconds * typical_bitrate_kbps(format) * 1000.0 / 8.0
     }
-}
-
-fn download_album_with_musicbrainz(query: &str, destination: &Path, format: &str) -> Result<()> {
-    println!("saving audio to {} as {}", destination.display(), format);
-    println!("searching MusicBrainz for album '{}'", query);
-
-    let client = MusicBrainzClient::new()?;
-    let album = match client.find_album(query)? {
-        Some(album) => album,
-        None => return Err(AppError::MusicBrainzNotFound(query.to_string())),
-    };
-
-    println!(
-        "found release: {} - {} ({} track{})",
-        album.artist,
-        album.title,
-        album.tracks.len(),
-        if album.tracks.len() == 1 { "" } else { "s" }
-    );
-
-    let total_tracks = album.tracks.len();
-    for track in &album.tracks {
-        let progress = format!("[{}/{}]", track.overall_index, total_tracks);
-        println!(
-            "{} searching YouTube for '{} - {}'",
-            progress, album.artist, track.title
-        );
-
-        let search_terms = format!("{} {} {}", album.artist, track.title, album.title);
-        let yt_query = build_single_search_query(&search_terms);
-        let output_template = track_output_template(destination, track, album.total_discs);
-        let metadata_args = build_metadata_args(&album, track, total_tracks);
-
-        let mut command = base_yt_dlp_command(format, &output_template);
-        command.arg("--no-playlist");
-        command.arg("--postprocessor-args").arg(metadata_args);
-        command.arg(&yt_query);
 
-        run_yt_dlp(command)?;
+    fn from_flat_playlist(playlist: &serde_json::Value) -> Self {
+        let entries = playlist
+            .get("entries")
+            .and_then(|value| value.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let durations: Vec<f64> = entries
+            .iter()
+            .filter_map(|entry| entry.get("duration").and_then(|value| value.as_f64()))
+            .collect();
+        Self {
+            tracks: entries.len(),
+            missing_durations: entries.len() - durations.len(),
+            seconds: durations.iter().fold(0.0, |total, secs| total + secs),
+        }
     }
+}
 
-    Ok(())
+// What a playlist contained when it was downloaded, for later syncing or diffing.
+#[derive(Debug, Serialize)]
+struct PlaylistManifest {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploader: Option<String>,
+    item_count: usize,
+    downloaded: String,
+    items: Vec<PlaylistManifestItem>,
 }
 
-struct MusicBrainzClient {
-    client: Client,
+#[derive(Debug, Serialize)]
+struct PlaylistManifestItem {
+    title: Option<String>,
+    url: Option<String>,
 }
 
-impl MusicBrainzClient {
-    fn new() -> Result<Self> {
-        let client = Client::builder()
-            .user_agent(MUSICBRAINZ_USER_AGENT)
-            .timeout(Duration::from_secs(15))
-            .build()?;
-        Ok(Self { client })
+impl PlaylistManifest {
+    fn from_flat_playlist(url: &str, playlist: &serde_json::Value) -> Self {
+        let text = |value: &serde_json::Value, key: &str| {
+            value
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+        let items: Vec<PlaylistManifestItem> = playlist
+            .get("entries")
+            .and_then(|value| value.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| PlaylistManifestItem {
+                title: text(entry, "title"),
+                url: text(entry, "url").or_else(|| text(entry, "webpage_url")),
+            })
+            .collect();
+        Self {
+            url: url.to_string(),
+            id: text(playlist, "id"),
+            title: text(playlist, "title"),
+            uploader: text(playlist, "uploader").or_else(|| text(playlist, "channel")),
+            item_count: items.len(),
+            downloaded: format_yyyymmdd(SystemTime::now()),
+            items,
+        }
     }
 
-    fn find_album(&self, query: &str) -> Result<Option<MusicBrainzAlbum>> {
-        let search_query = build_musicbrainz_search_query(query);
-        let search_url = format!(
-            "{}/release/?query={}&fmt=json&limit=1",
-            MUSICBRAINZ_BASE_URL,
-            encode(&search_query)
-        );
+    fn file_name(&self) -> String {
+        let name = self
+            .title
+            .as_deref()
+            .or(self.id.as_deref())
+            .unwrap_or("playlist");
+        format!("{}.playlist.json", sanitize_filename(name))
+    }
 
-        let search_response: MbReleaseSearchResponse = self
-            .client
-            .get(&search_url)
-            .header("Accept", "application/json")
-            .send()?
-            .error_for_status()?
-            .json()?;
+    fn write(&self, destination: &Path) -> Result<PathBuf> {
+        let path = destination.join(self.file_name());
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
 
-        let Some(release) = search_response.releases.into_iter().next() else {
-            return Ok(None);
-        };
+// Rough output bitrates for yt-dlp's default --audio-quality; lossless formats
+// depend on the source, so theirs is a CD-quality ballpark.
+fn typical_bitrate_kbps(format: &str) -> f64 {
+    match format {
+        "flac" | "alac" => 900.0,
+        "wav" => 1411.0,
+        "vorbis" | "ogg" => 160.0,
+        "mp3" => 130.0,
+        _ => 128.0,
+    }
+}
 
-        let release_id = release.id;
-        let detail_url = format!(
-            "{}/release/{}?inc=recordings+artist-credits&fmt=json",
-            MUSICBRAINZ_BASE_URL, release_id
+fn describe_estimate(estimate: &PlaylistEstimate, format: &str) -> String {
+    if estimate.missing_durations == estimate.tracks {
+        return format!(
+            "the playlist listing has no durations for its {} entries; no size estimate",
+            estimate.tracks
+        );
+    }
+    let bytes = estimate.bytes(format);
+    let tracks = format!(
+        "{} track{}",
+        estimate.tracks,
+        if estimate.tracks == 1 { "" } else { "s" }
+    );
+    let mut line = format!(
+        "~{} estimated for {} ({} of audio)",
+        format_size(bytes),
+        tracks,
+        format_duration(estimate.seconds)
+    );
+    if estimate.missing_durations > 0 {
+        line = format!(
+            "at least {}; {} without a known duration",
+            line, estimate.missing_durations
         );
-
-        let detail: MbReleaseDetail = self
-            .client
-            .get(&detail_url)
-            .header("Accept", "application/json")
-            .send()?
-            .error_for_status()?
-            .json()?;
-
-        convert_release_detail(detail).map(Some)
     }
+    line
 }
 
-fn build_musicbrainz_search_query(raw: &str) -> String {
-    if let Some((artist, album)) = split_artist_album(raw) {
-        format!(
-            "release:\"{}\" AND artist:\"{}\"",
-            escape_musicbrainz_query(&album),
-            escape_musicbrainz_query(&artist)
-        )
-    } else {
-        raw.to_string()
+// Fails before anything is downloaded when the estimate plus the configured
+// headroom does not fit, so an album is never left half-finished.
+fn check_free_space(
+    destination: &Path,
+    estimate: Option<&PlaylistEstimate>,
+    format: &str,
+    min_free: u64,
+) -> Result<()> {
+    let available = fs2::available_space(destination)?;
+    let needed = estimate.map_or(0.0, |estimate| estimate.bytes(format)) + min_free as f64;
+    if (available as f64) < needed {
+        return Err(AppError::Message(format!(
+            "only {} free in {}, but ~{} is needed (download estimate plus {} minimum free); free up space, lower it with `config set-min-free` or pass --no-space-check",
+            format_size(available as f64),
+            destination.display(),
+            format_size(needed),
+            format_size(min_free as f64)
+        )));
     }
+    Ok(())
 }
 
-fn split_artist_album(raw: &str) -> Option<(String, String)> {
-    for delimiter in ['-', '\u{2013}', '\u{2014}'] {
-        if let Some((artist, album)) = raw.split_once(delimiter) {
-            let artist = artist.trim();
-            let album = album.trim();
-            if !artist.is_empty() && !album.is_empty() {
-                return Some((artist.to_string(), album.to_string()));
-            }
+// Leftovers of interrupted yt-dlp runs: "<name>.part", fragment parts, ".ytdl"
+// state and the "<name>.temp.<ext>" or ".tmp" files of unfinished post-processing.
+fn is_partial_download(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let intermediate = Path::new(name)
+        .file_stem()
+        .and_then(|stem| Path::new(stem).extension())
+        .is_some_and(|ext| ext == "temp");
+    name.ends_with(".part")
+        || name.contains(".part-Frag")
+        || name.ends_with(".ytdl")
+        || name.ends_with(".tmp")
+        || intermediate
+}
+
+// Walks nested album folders too; symlinked directories are not followed.
+fn find_partial_downloads(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            find_partial_downloads(&path, found)?;
+        } else if file_type.is_file() && is_partial_download(&path) {
+            found.push(path);
         }
     }
-    None
+    Ok(())
 }
 
-fn escape_musicbrainz_query(value: &str) -> String {
-    value.replace('"', "\\\"")
+// Partial files under `dir` except those changed within PARTIAL_IN_USE_WINDOW:
+// downloads take no lock, so a fresh one may belong to another bippi or
+// yt-dlp process that is still running. Also returns how many were kept.
+fn stale_partial_downloads(dir: &Path) -> Result<(Vec<PathBuf>, usize)> {
+    let mut partials = Vec::new();
+    if dir.is_dir() {
+        find_partial_downloads(dir, &mut partials)?;
+    }
+    partials.sort();
+    let mut in_progress = 0;
+    partials.retain(|path| {
+        let recent = fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() < PARTIAL_IN_USE_WINDOW)
+            .unwrap_or(false);
+        in_progress += usize::from(recent);
+        !recent
+    });
+    Ok((partials, in_progress))
 }
 
-fn convert_release_detail(detail: MbReleaseDetail) -> Result<MusicBrainzAlbum> {
-    let MbReleaseDetail {
-        title,
-        date,
-        artist_credit,
-        media,
-    } = detail;
-
-    let album_title = title.unwrap_or_else(|| "Unknown Release".to_string());
-    let artist = {
-        let formatted = format_artist_credit(&artist_credit);
-        if formatted.is_empty() {
-            "Unknown Artist".to_string()
-        } else {
-            formatted
-        }
-    };
-
-    let mut tracks = Vec::new();
-    let mut discs_with_tracks = 0u32;
+fn describe_kept_partials(in_progress: usize) -> String {
+    format!(
+        "kept {} partial file{} changed in the last {} minutes; a download may still be writing {}",
+        in_progress,
+        if in_progress == 1 { "" } else { "s" },
+        PARTIAL_IN_USE_WINDOW.as_secs() / 60,
+        if in_progress == 1 { "it" } else { "them" }
+    )
+}
 
-    for (medium_index, medium) in media.into_iter().enumerate() {
-        if medium.tracks.is_empty() {
-            continue;
+// --restart: throws away what interrupted runs left in the destination (nested
+// album and playlist folders included) and the temp dir.
+fn discard_partial_downloads(destination: &Path, tmp_dir: Option<&Path>) -> Result<()> {
+    let mut removed = 0;
+    for dir in std::iter::once(destination).chain(tmp_dir) {
+        let (partials, in_progress) = stale_partial_downloads(dir)?;
+        for path in &partials {
+            fs::remove_file(path)?;
         }
-        discs_with_tracks += 1;
-        let disc_number = medium.position.unwrap_or((medium_index + 1) as u32);
-        for (index_on_disc, track) in medium.tracks.into_iter().enumerate() {
-            let title = track
-                .title
-                .or_else(|| track.recording.and_then(|rec| rec.title))
-                .unwrap_or_else(|| format!("Track {}", index_on_disc + 1));
-            let position = track
-                .position
-                .or_else(|| track.number.and_then(|num| num.parse::<u32>().ok()))
-                .unwrap_or((index_on_disc + 1) as u32);
-            let overall_index = tracks.len() + 1;
-            tracks.push(MusicBrainzTrack {
-                title,
-                disc: disc_number,
-                position,
-                overall_index,
-            });
+        removed += partials.len();
+        if in_progress > 0 {
+            status!("{}", describe_kept_partials(in_progress));
         }
     }
+    status!(
+        "removed {} partial download file{}",
+        removed,
+        if removed == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
 
-    if tracks.is_empty() {
+// Checked up front because yt-dlp only fails on an unwritable temp dir after the
+// download has started.
+fn validate_tmp_dir(dir: &Path) -> Result<PathBuf> {
+    let dir = ensure_absolute(dir)?;
+    ensure_directory_path(&dir, "temp dir")?;
+    let probe = dir.join(format!(".bippi-write-test-{}", std::process::id()));
+    fs::create_dir_all(&dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|err| {
+            AppError::Message(format!(
+                "temp dir {} is not writable: {}",
+                dir.display(),
+                err
+            ))
+        })?;
+    Ok(dir)
+}
+
+// yt-dlp gets the size as written; parsing only rejects what it would choke on.
+fn validate_max_filesize(size: Option<String>) -> Result<Option<String>> {
+    let Some(size) = size else {
+        return Ok(None);
+    };
+    if parse_size(&size)? == 0 {
         return Err(AppError::Message(
-            "MusicBrainz release does not contain any tracks".to_string(),
+            "--max-filesize must be larger than zero".to_string(),
         ));
     }
+    Ok(Some(size.trim().to_string()))
+}
 
-    let total_discs = if discs_with_tracks == 0 {
-        1
-    } else {
-        discs_with_tracks
+// "50M", "1.5G" or plain bytes, with binary multiples like yt-dlp's size options.
+fn parse_size(value: &str) -> Result<u64> {
+    let trimmed = value.trim();
+    let invalid = || {
+        AppError::Message(format!(
+            "invalid size '{}'; use a number with an optional K, M, G or T suffix (e.g. 50M)",
+            value
+        ))
     };
-
-    Ok(MusicBrainzAlbum {
-        title: album_title,
-        artist,
-        release_date: date,
-        total_discs,
-        tracks,
-    })
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let exponent = match suffix.to_ascii_uppercase() {
+                'K' => 1,
+                'M' => 2,
+                'G' => 3,
+                'T' => 4,
+                _ => return Err(invalid()),
+            };
+            (&trimmed[..index], 1024u64.pow(exponent))
+        }
+        _ => (trimmed, 1),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * multiplier as f64) as u64)
 }
 
-fn format_artist_credit(credits: &[MbArtistCredit]) -> String {
-    if credits.is_empty() {
-        return String::new();
+// Binary multiples, matching parse_size.
+fn format_size(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    let mut value = bytes / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{:.1} {}", value, UNITS[unit])
+    } else {
+        format!("{:.0} {}", value, UNITS[unit])
     }
+}
 
-    let mut composed = String::new();
-    for credit in credits {
-        if let Some(name) = credit.name.as_deref().or_else(|| {
-            credit
-                .artist
-                .as_ref()
-                .and_then(|artist| artist.name.as_deref())
-        }) {
-            composed.push_str(name);
-        }
-        if let Some(join) = credit.joinphrase.as_deref() {
-            composed.push_str(join);
+// --since-file: the file's mtime becomes --since, and a successful run moves it
+// to when the run started. Resolved once, before any stdin targets fan out, so
+// every target is checked against the same date.
+fn download_since_file(
+    path: &Path,
+    mut args: DownloadArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+    mode: DownloadMode,
+) -> Result<Vec<PathBuf>> {
+    let started = SystemTime::now();
+    match since_file_date(path)? {
+        Some(date) => {
+            status!(
+                "only downloading items uploaded on or after {} ({})",
+                date,
+                path.display()
+            );
+            args.since = Some(date);
         }
+        None => status!("{} does not exist yet; checking every item", path.display()),
     }
-
-    if composed.is_empty() {
-        credits
-            .iter()
-            .filter_map(|credit| {
-                credit
-                    .artist
-                    .as_ref()
-                    .and_then(|artist| artist.name.clone())
-            })
-            .collect::<Vec<_>>()
-            .join(" & ")
-    } else {
-        composed
+    let simulate = args.simulate;
+    let produced = handle_download(args, config, musicbrainz, mode)?;
+    if !simulate {
+        touch_since_file(path, started)?;
     }
+    Ok(produced)
 }
 
-fn track_output_template(destination: &Path, track: &MusicBrainzTrack, total_discs: u32) -> String {
-    let prefix = if total_discs > 1 {
-        format!("{:02}-{:02}", track.disc, track.position)
-    } else {
-        format!("{:02}", track.overall_index)
-    };
-    let safe_title = sanitize_filename(&track.title);
-    let file_name = format!("{} - {}.%(ext)s", prefix, safe_title);
-    destination.join(file_name).to_string_lossy().to_string()
+fn since_file_date(path: &Path) -> Result<Option<String>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(format_yyyymmdd(metadata.modified()?))),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
 }
 
-fn build_metadata_args(
-    album: &MusicBrainzAlbum,
-    track: &MusicBrainzTrack,
-    total_tracks: usize,
-) -> String {
-    let mut parts = vec![
-        format!("-metadata artist={}", quote_metadata_value(&album.artist)),
-        format!("-metadata album={}", quote_metadata_value(&album.title)),
-        format!(
-            "-metadata album_artist={}",
-            quote_metadata_value(&album.artist)
-        ),
-        format!("-metadata title={}", quote_metadata_value(&track.title)),
-        format!(
-            "-metadata track={}",
-            quote_metadata_value(&format!("{:02}/{}", track.overall_index, total_tracks))
-        ),
-    ];
+fn touch_since_file(path: &Path, time: SystemTime) -> Result<()> {
+    fs::File::options()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .set_modified(time)?;
+    Ok(())
+}
 
-    if album.total_discs > 1 {
-        parts.push(format!(
-            "-metadata disc={}",
-            quote_metadata_value(&track.disc.to_string())
+// All of stdin is read before the first download starts, so yt-dlp never
+// competes with bippi for the same input.
+fn download_stdin_targets(
+    args: DownloadArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+    mode: DownloadMode,
+) -> Result<Vec<PathBuf>> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    let targets = parse_stdin_targets(&input);
+    if targets.is_empty() {
+        return Err(AppError::Message(
+            "no targets were given on stdin".to_string(),
         ));
     }
 
-    if let Some(date) = &album.release_date {
-        parts.push(format!("-metadata date={}", quote_metadata_value(date)));
+    let mut failed = 0;
+    let mut produced = Vec::new();
+    for (index, target) in targets.iter().enumerate() {
+        status!(
+            "{} {}",
+            Tone::Info.paint(format!("({}/{})", index + 1, targets.len())),
+            target
+        );
+        let line_args = DownloadArgs {
+            target: vec![target.clone()],
+            ..args.clone()
+        };
+        match handle_download(line_args, config, musicbrainz, mode) {
+            Ok(files) => produced.extend(files),
+            Err(err) => {
+                eprintln!("{} {}: {}", Tone::Error.paint("error:"), target, err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(AppError::Message(format!(
+            "{} of {} stdin targets failed",
+            failed,
+            targets.len()
+        )));
     }
+    Ok(produced)
+}
 
-    format!("ffmpeg:{}", parts.join(" "))
+// One target per line; blank lines and "#" comments are skipped.
+fn parse_stdin_targets(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
 }
 
-fn quote_metadata_value(value: &str) -> String {
-    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
-    format!("\"{}\"", escaped)
+// Stdout is reserved for audio with --stdout and for JSON events with --progress-json.
+fn route_status_output(args: &DownloadArgs) {
+    if args.stdout || args.progress_json {
+        STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+    }
 }
 
-fn sanitize_filename(input: &str) -> String {
-    let mut sanitized = String::with_capacity(input.len());
-    for ch in input.chars() {
-        match ch {
-            '/' | '\\' | '?' | '*' | '"' | '<' | '>' | '|' | ':' => sanitized.push('_'),
-            c if c.is_control() => sanitized.push('_'),
-            _ => sanitized.push(ch),
+fn build_format_chain(format: &str, fallback: &[String]) -> Result<Vec<String>> {
+    let mut chain = vec![format.trim().to_ascii_lowercase()];
+    for candidate in fallback {
+        let candidate = candidate.trim().to_ascii_lowercase();
+        if candidate.is_empty() {
+            return Err(AppError::Message(
+                "--format-fallback entries must not be empty".to_string(),
+            ));
+        }
+        if !chain.contains(&candidate) {
+            chain.push(candidate);
         }
     }
-    let trimmed = sanitized.trim().trim_matches('.');
-    if trimmed.is_empty() {
-        "track".to_string()
-    } else {
-        trimmed.to_string()
+    if chain[0].is_empty() {
+        return Err(AppError::Message("--format must not be empty".to_string()));
     }
+    Ok(chain)
 }
 
-#[derive(Debug)]
-struct MusicBrainzAlbum {
-    title: String,
-    artist: String,
-    release_date: Option<String>,
-    total_discs: u32,
-    tracks: Vec<MusicBrainzTrack>,
-}
-
-#[derive(Debug)]
-struct MusicBrainzTrack {
-    title: String,
-    disc: u32,
-    position: u32,
-    overall_index: usize,
+fn describe_format_chain(formats: &[String]) -> String {
+    match formats.split_first() {
+        Some((first, [])) => first.clone(),
+        Some((first, rest)) => format!("{} (falling back to {})", first, rest.join(", ")),
+        None => String::new(),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct MbReleaseSearchResponse {
-    #[serde(default)]
-    releases: Vec<MbReleaseSearchEntry>,
+fn run_yt_dlp_with_fallback(
+    formats: &[String],
+    single_item: bool,
+    mut build_command: impl FnMut(&str) -> Command,
+) -> Result<String> {
+    let mut last_error = None;
+    for (index, format) in formats.iter().enumerate() {
+        if let Some(err) = &last_error {
+            status!("{}; retrying as {}", err, format);
+        }
+        match run_yt_dlp_capturing(build_command(format))? {
+            Ok(()) => {
+                if index > 0 {
+                    status!("downloaded as {} after falling back", format);
+                }
+                return Ok(format.clone());
+            }
+            // A playlist run exits non-zero when any one item fails, and
+            // retrying it would download every other item a second time.
+            Err((err, stderr)) if single_item && is_format_failure(&stderr) => {
+                last_error = Some(err)
+            }
+            Err((err, _)) => return Err(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| AppError::Message("no audio format to try".to_string())))
 }
 
-#[derive(Debug, Deserialize)]
-struct MbReleaseSearchEntry {
-    id: String,
+#[derive(Clone, Debug, Default)]
+struct YtDlpOptions {
+    match_filter: Option<String>,
+    no_continue: bool,
+    max_filesize: Option<String>,
+    tmp_dir: Option<PathBuf>,
+    keep_video: bool,
+    subtitle_langs: Option<String>,
+    lyrics_from_subs: bool,
+    write_thumbnail: bool,
+    convert_thumbnails: Option<String>,
+    date_after: Option<String>,
+    download_archive: Option<PathBuf>,
+    format_sort: Option<String>,
+    geo_bypass: bool,
+    geo_bypass_country: Option<String>,
+    cookies_from_browser: Option<String>,
+    impersonate: Option<String>,
+    // One merged "IE:ARGS" spec per extractor.
+    extractor_args: Vec<String>,
+    quiet: bool,
+    stdout_to_stderr: bool,
+    no_embed_metadata: bool,
+    write_info_json: bool,
+    write_description: bool,
+    simulate: bool,
+    max_downloads: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
-struct MbReleaseDetail {
-    #[serde(default)]
-    title: Option<String>,
-    #[serde(default)]
-    date: Option<String>,
-    #[serde(rename = "artist-credit", default)]
-    artist_credit: Vec<MbArtistCredit>,
-    #[serde(default)]
-    media: Vec<MbMedium>,
+fn base_yt_dlp_command(format: &str, output_template: &str, options: &YtDlpOptions) -> Command {
+    let mut command = Command::new("yt-dlp");
+    command.arg("--ignore-errors").arg(if options.no_continue {
+        "--no-continue"
+    } else {
+        "--continue"
+    });
+    command
+        .arg("-x")
+        .arg("--audio-format")
+        .arg(format)
+        .arg("--output")
+        .arg(output_template);
+    if !options.no_embed_metadata {
+        command.arg("--embed-metadata");
+    }
+    apply_access_options(&mut command, options);
+    if let Some(filter) = &options.match_filter {
+        command.arg("--match-filter").arg(filter);
+    }
+    if let Some(size) = &options.max_filesize {
+        command.arg("--max-filesize").arg(size);
+    }
+    if let Some(dir) = &options.tmp_dir {
+        // Only the finished file is moved into the destination.
+        let mut paths = std::ffi::OsString::from("temp:");
+        paths.push(dir);
+        command.arg("--paths").arg(paths);
+    }
+    if options.keep_video {
+        command.arg("--keep-video");
+    }
+    if let Some(langs) = &options.subtitle_langs {
+        // None of the audio-only containers can carry subtitle streams, so the
+        // subtitles are always written as sidecar files next to the audio.
+        command
+            .arg("--write-subs")
+            .arg("--write-auto-subs")
+            .arg("--sub-langs")
+            .arg(langs);
+        if options.lyrics_from_subs {
+            command.arg("--convert-subs").arg("srt");
+        }
+    }
+    if options.write_thumbnail {
+        command.arg("--write-thumbnail");
+        if let Some(format) = &options.convert_thumbnails {
+            command.arg("--convert-thumbnails").arg(format);
+        }
+    }
+    if options.write_info_json {
+        command.arg("--write-info-json");
+    }
+    if options.write_description {
+        command.arg("--write-description");
+    }
+    if options.simulate {
+        command.arg("--simulate");
+    }
+    if let Some(max) = options.max_downloads {
+        command.arg("--max-downloads").arg(max.to_string());
+    }
+    if let Some(date) = &options.date_after {
+        command.arg("--dateafter").arg(date);
+    }
+    if let Some(archive) = &options.download_archive {
+        command.arg("--download-archive").arg(archive);
+    }
+    if let Some(spec) = &options.format_sort {
+        command.arg("--format-sort").arg(spec);
+    }
+    command.stdin(Stdio::null());
+    if options.quiet {
+        command
+            .arg("--quiet")
+            .arg("--no-warnings")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+    } else if options.stdout_to_stderr {
+        command
+            .stdout(Stdio::from(std::io::stderr()))
+            .stderr(Stdio::inherit());
+    } else {
+        command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    }
+    command
 }
 
-#[derive(Debug, Deserialize)]
-struct MbArtistCredit {
-    #[serde(default)]
-    name: Option<String>,
-    #[serde(default)]
-    joinphrase: Option<String>,
-    #[serde(default)]
-    artist: Option<MbArtist>,
+// Audio extraction and tagging need a real file, so streaming hands over the best
+// audio stream untouched instead of going through base_yt_dlp_command.
+fn stdout_yt_dlp_command(options: &YtDlpOptions, target: &str) -> Command {
+    let mut command = Command::new("yt-dlp");
+    command
+        .arg("--no-playlist")
+        .arg("--format")
+        .arg("bestaudio/best")
+        .arg("--output")
+        .arg("-");
+    apply_access_options(&mut command, options);
+    if let Some(filter) = &options.match_filter {
+        command.arg("--match-filter").arg(filter);
+    }
+    if let Some(spec) = &options.format_sort {
+        command.arg("--format-sort").arg(spec);
+    }
+    if options.quiet {
+        command.arg("--quiet").arg("--no-warnings");
+    }
+    command.arg(target);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(if options.quiet {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        });
+    command
 }
 
-#[derive(Debug, Deserialize)]
-struct MbArtist {
-    #[serde(default)]
-    name: Option<String>,
+// Options that decide whether yt-dlp can reach a video at all; they also go on
+// probe commands so a probe sees the same availability as the real download.
+fn apply_access_options(command: &mut Command, options: &YtDlpOptions) {
+    if options.geo_bypass {
+        command.arg("--geo-bypass");
+    }
+    if let Some(country) = &options.geo_bypass_country {
+        command.arg("--geo-bypass-country").arg(country);
+    }
+    if let Some(spec) = &options.cookies_from_browser {
+        command.arg("--cookies-from-browser").arg(spec);
+    }
+    if let Some(target) = &options.impersonate {
+        command.arg("--impersonate").arg(target);
+    }
+    for spec in &options.extractor_args {
+        command.arg("--extractor-args").arg(spec);
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct MbMedium {
-    #[serde(default)]
-    position: Option<u32>,
-    #[serde(default)]
-    tracks: Vec<MbTrack>,
+// yt-dlp keeps only the last --extractor-args given for an extractor, so config
+// defaults, --extractor-args and --po-token are merged into one spec per
+// extractor, later arguments after earlier ones.
+fn build_extractor_args(
+    defaults: &[String],
+    extra: &[String],
+    po_token: Option<&str>,
+) -> Result<Vec<String>> {
+    let po_token = po_token.map(po_token_extractor_args).transpose()?;
+    let mut merged: Vec<(String, Vec<String>)> = Vec::new();
+    for spec in defaults.iter().chain(extra).chain(po_token.as_ref()) {
+        let (extractor, args) = parse_extractor_args(spec)?;
+        match merged
+            .iter_mut()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(extractor))
+        {
+            Some((_, existing)) => existing.push(args.to_string()),
+            None => merged.push((extractor.to_ascii_lowercase(), vec![args.to_string()])),
+        }
+    }
+    Ok(merged
+        .into_iter()
+        .map(|(extractor, args)| format!("{}:{}", extractor, args.join(";")))
+        .collect())
 }
 
-#[derive(Debug, Deserialize)]
-struct MbTrack {
-    #[serde(default)]
-    position: Option<u32>,
-    #[serde(default)]
-    number: Option<String>,
-    #[serde(default)]
-    title: Option<String>,
-    #[serde(default)]
-    recording: Option<MbRecording>,
+fn parse_extractor_args(spec: &str) -> Result<(&str, &str)> {
+    let spec = spec.trim();
+    match spec.split_once(':') {
+        Some((extractor, args))
+            if !extractor.is_empty()
+                && extractor
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !args.trim().is_empty() =>
+        {
+            Ok((extractor, args.trim()))
+        }
+        _ => Err(AppError::Message(format!(
+            "--extractor-args expects EXTRACTOR:ARGS (e.g. youtube:player_client=web), got '{spec}'"
+        ))),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct MbRecording {
-    #[serde(default)]
-    title: Option<String>,
+// A bare token is taken to be for the web client's video streams; one that
+// already names its CLIENT.CONTEXT (e.g. mweb.gvs+...) is passed on as is.
+fn po_token_extractor_args(token: &str) -> Result<String> {
+    let token = token.trim();
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        return Err(AppError::Message(
+            "--po-token must be a non-empty token without spaces".to_string(),
+        ));
+    }
+    if token.contains('+') {
+        Ok(format!("youtube:po_token={token}"))
+    } else {
+        Ok(format!("youtube:po_token=web.gvs+{token}"))
+    }
 }
 
-fn looks_like_url(input: &str) -> bool {
-    let lowered = input.trim().to_ascii_lowercase();
-    lowered.starts_with("http://")
-        || lowered.starts_with("https://")
-        || lowered.starts_with("ytsearch:")
-        || lowered.starts_with("ytsearch")
-        || lowered.starts_with("www.")
-        || lowered.contains("://")
+// A target is CLIENT[:OS], e.g. chrome, chrome-124 or safari:ios; which ones
+// work depends on the yt-dlp build, so that is left to check_impersonate_support.
+fn validate_impersonate_target(target: String) -> Result<String> {
+    let target = target.trim().to_ascii_lowercase();
+    if !target.is_empty()
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':' | '_'))
+    {
+        Ok(target)
+    } else {
+        Err(AppError::Message(format!(
+            "--impersonate expects a target like chrome or safari:ios, got '{target}'"
+        )))
+    }
 }
 
-fn should_apply_album_metadata(download_album: bool, resolved_target: &str) -> bool {
-    download_album && looks_like_playlist(resolved_target)
+// yt-dlp rejects an unusable target before it looks at any URL, so a run
+// without one is enough to tell whether this build can impersonate it.
+fn check_impersonate_support(target: &str) -> Result<()> {
+    let output = Command::new("yt-dlp")
+        .arg("--impersonate")
+        .arg(target)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_yt_dlp_error)?;
+    match describe_impersonate_error(target, &String::from_utf8_lossy(&output.stderr)) {
+        Some(message) => Err(AppError::Message(message)),
+        None => Ok(()),
+    }
 }
 
-fn looks_like_playlist(value: &str) -> bool {
-    let lowered = value.to_ascii_lowercase();
-    lowered.contains("list=")
+fn describe_impersonate_error(target: &str, stderr: &str) -> Option<String> {
+    let lowered = stderr.to_ascii_lowercase();
+    if lowered.contains("no such option") && lowered.contains("--impersonate") {
+        Some(
+            "this yt-dlp predates --impersonate; update it and install curl_cffi \
+             (pip install -U \"yt-dlp[default,curl-cffi]\")"
+                .to_string(),
+        )
+    } else if lowered.contains("impersonate target")
+        && (lowered.contains("not available") || lowered.contains("unsupported"))
+    {
+        Some(format!(
+            "this yt-dlp cannot impersonate '{target}'; install a curl_cffi-enabled build \
+             (pip install -U \"yt-dlp[default,curl-cffi]\") and see `yt-dlp --list-impersonate-targets`"
+        ))
+    } else {
+        None
+    }
 }
 
-fn build_single_search_query(query: &str) -> String {
-    let trimmed = query.trim();
+// The browsers and keyrings yt-dlp can read cookies from.
+const COOKIE_BROWSERS: &[&str] = &[
+    "brave", "chrome", "chromium", "edge", "firefox", "opera", "safari", "vivaldi", "whale",
+];
+const COOKIE_KEYRINGS: &[&str] = &[
+    "basictext",
+    "gnomekeyring",
+    "kwallet",
+    "kwallet5",
+    "kwallet6",
+];
 
-    // If query contains artist - song format, preserve it for better search results
-    let search_query = if let Some((artist, song)) = split_artist_song(trimmed) {
-        format!("{} {}", artist, song)
-    } else {
-        trimmed.to_string()
+// yt-dlp's BROWSER[+KEYRING][:PROFILE][::CONTAINER]. The profile may be a path
+// (even "C:\..."), so only the browser, keyring and container are checked.
+fn validate_cookies_from_browser(spec: Option<String>) -> Result<Option<String>> {
+    let Some(spec) = spec else {
+        return Ok(None);
+    };
+    let invalid = |detail: String| {
+        AppError::Message(format!(
+            "--cookies-from-browser expects BROWSER[+KEYRING][:PROFILE][::CONTAINER], {detail}"
+        ))
     };
 
-    let mut terms = String::with_capacity(search_query.len() + 24);
-    terms.push_str(&search_query);
+    let spec = spec.trim();
+    let (head, container) = match spec.split_once("::") {
+        Some((head, container)) => (head, Some(container.trim())),
+        None => (spec, None),
+    };
+    let (browser, profile) = match head.split_once(':') {
+        Some((browser, profile)) => (browser, Some(profile.trim())),
+        None => (head, None),
+    };
+    let (name, keyring) = match browser.split_once('+') {
+        Some((name, keyring)) => (name.trim(), Some(keyring.trim())),
+        None => (browser.trim(), None),
+    };
 
-    if !search_query.to_ascii_lowercase().contains("audio") {
-        terms.push_str(" audio");
+    let name = name.to_ascii_lowercase();
+    if !COOKIE_BROWSERS.contains(&name.as_str()) {
+        return Err(invalid(format!(
+            "but '{}' is not a supported browser ({})",
+            name,
+            COOKIE_BROWSERS.join(", ")
+        )));
     }
-
-    terms.push_str(" -\"music video\"");
-
-    format!("ytsearch1:{}", terms.trim())
-}
-
-fn split_artist_song(raw: &str) -> Option<(String, String)> {
-    for delimiter in ['-', '\u{2013}', '\u{2014}'] {
-        if let Some((artist, song)) = raw.split_once(delimiter) {
-            let artist = artist.trim();
-            let song = song.trim();
-            if !artist.is_empty() && !song.is_empty() {
-                return Some((artist.to_string(), song.to_string()));
-            }
+    let mut normalized = name.clone();
+    if let Some(keyring) = keyring {
+        let keyring = keyring.to_ascii_lowercase();
+        if !COOKIE_KEYRINGS.contains(&keyring.as_str()) {
+            return Err(invalid(format!(
+                "but '{}' is not a supported keyring ({})",
+                keyring,
+                COOKIE_KEYRINGS.join(", ")
+            )));
         }
+        normalized.push('+');
+        normalized.push_str(&keyring.to_ascii_uppercase());
     }
-    None
+    if let Some(profile) = profile {
+        if profile.is_empty() {
+            return Err(invalid("but the profile after ':' is empty".to_string()));
+        }
+        normalized.push(':');
+        normalized.push_str(profile);
+    }
+    if let Some(container) = container {
+        if name != "firefox" {
+            return Err(invalid(format!(
+                "but containers are a Firefox feature, not {}",
+                name
+            )));
+        }
+        if container.is_empty() {
+            return Err(invalid("but the container after '::' is empty".to_string()));
+        }
+        normalized.push_str("::");
+        normalized.push_str(container);
+    }
+    Ok(Some(normalized))
 }
 
-fn handle_alias(command: AliasCommand, config: &mut AppConfig) -> Result<bool> {
-    match command {
-        AliasCommand::Add(args) => {
-            let entry = AliasEntry {
-                url: args.url,
-                album: args.album,
-            };
-            let existed = config.aliases.insert(args.name.clone(), entry).is_some();
-            if existed {
-                println!("updated alias '{}'", args.name);
-            } else {
-                println!("created alias '{}'", args.name);
-            }
-            Ok(true)
-        }
-        AliasCommand::Remove(args) => {
-            if config.aliases.remove(&args.name).is_some() {
-                println!("removed alias '{}'", args.name);
-                Ok(true)
+fn validate_country_code(code: Option<String>) -> Result<Option<String>> {
+    match code {
+        Some(code) => {
+            let code = code.trim().to_ascii_uppercase();
+            if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+                Ok(Some(code))
             } else {
                 Err(AppError::Message(format!(
-                    "alias '{}' not found",
-                    args.name
+                    "--geo-bypass-country expects a two-letter ISO 3166-1 code (e.g. US), got '{code}'"
                 )))
             }
         }
-        AliasCommand::List => {
-            if config.aliases.is_empty() {
-                println!("no aliases defined yet");
+        None => Ok(None),
+    }
+}
+
+fn validate_format_sort(spec: Option<String>) -> Result<Option<String>> {
+    match spec {
+        Some(spec) if spec.trim().is_empty() => Err(AppError::Message(
+            "--format-sort needs a sort spec (e.g. acodec:flac,abr)".to_string(),
+        )),
+        Some(spec) => Ok(Some(spec.trim().to_string())),
+        None => Ok(None),
+    }
+}
+
+fn validate_date_after(date: Option<String>) -> Result<Option<String>> {
+    let Some(date) = date else {
+        return Ok(None);
+    };
+    let date = date.trim().to_ascii_lowercase();
+    if is_valid_yt_dlp_date(&date) {
+        Ok(Some(date))
+    } else {
+        Err(AppError::Message(format!(
+            "--since expects YYYYMMDD or a relative date like now-7days, got '{date}'"
+        )))
+    }
+}
+
+fn is_valid_yt_dlp_date(date: &str) -> bool {
+    if date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()) {
+        return true;
+    }
+
+    let Some(rest) = date
+        .strip_prefix("today")
+        .or_else(|| date.strip_prefix("now"))
+    else {
+        return false;
+    };
+    if rest.is_empty() {
+        return true;
+    }
+    let Some(rest) = rest.strip_prefix(['-', '+']) else {
+        return false;
+    };
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let unit = rest[digits..].trim_end_matches('s');
+    digits > 0 && matches!(unit, "day" | "week" | "month" | "year")
+}
+
+fn build_duration_filter(min: Option<u64>, max: Option<u64>) -> Result<Option<String>> {
+    if let (Some(min), Some(max)) = (min, max)
+        && min > max
+    {
+        return Err(AppError::Message(format!(
+            "--min-duration ({min}) must not be greater than --max-duration ({max})"
+        )));
+    }
+
+    let mut clauses = Vec::new();
+    if let Some(min) = min {
+        clauses.push(format!("duration >= {min}"));
+    }
+    if let Some(max) = max {
+        clauses.push(format!("duration <= {max}"));
+    }
+
+    if clauses.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(clauses.join(" & ")))
+    }
+}
+
+fn validate_subtitle_langs(langs: Option<String>) -> Result<Option<String>> {
+    match langs {
+        Some(langs) => {
+            let cleaned = langs
+                .split(',')
+                .map(str::trim)
+                .filter(|lang| !lang.is_empty())
+                .collect::<Vec<_>>()
+                .join(",");
+            if cleaned.is_empty() {
+                Err(AppError::Message(
+                    "--subtitles needs at least one language (e.g. en or en,de)".to_string(),
+                ))
             } else {
-                for (name, entry) in &config.aliases {
-                    if entry.album {
-                        println!("{} -> {} (album)", name, entry.url);
-                    } else {
-                        println!("{} -> {}", name, entry.url);
-                    }
-                }
+                Ok(Some(cleaned))
             }
-            Ok(false)
         }
+        None => Ok(None),
     }
 }
 
-fn handle_config(command: ConfigCommand, config: &mut AppConfig) -> Result<bool> {
-    match command {
-        ConfigCommand::SetDest(args) => {
-            let absolute = ensure_absolute(&args.path)?;
-            if let Some(parent) = absolute.parent() {
-                fs::create_dir_all(parent)?;
+// Only the subtitles written next to `files` are converted.
+fn write_lyrics_from_subtitles(files: &[PathBuf]) -> Result<()> {
+    let mut converted = 0;
+    let mut subtitles = Vec::new();
+    for file in files {
+        let (Some(dir), Some(audio_stem)) = (file.parent(), file.file_stem()) else {
+            continue;
+        };
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("srt") {
+                continue;
             }
-            if !absolute.exists() {
-                fs::create_dir_all(&absolute)?;
+            // "<title>.<lang>.srt" belongs to "<title>.<ext>".
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let base = stem.rsplit_once('.').map_or(stem, |(base, _lang)| base);
+            if std::ffi::OsStr::new(base) == audio_stem {
+                subtitles.push((path.with_file_name(format!("{base}.lrc")), path));
             }
-            config.default_destination = Some(absolute.clone());
-            println!("default destination set to {}", absolute.display());
-            Ok(true)
         }
-        ConfigCommand::Show => {
-            match &config.default_destination {
-                Some(path) => println!("default destination: {}", path.display()),
-                None => println!("default destination: not set"),
-            }
-            if config.aliases.is_empty() {
-                println!("aliases: none");
-            } else {
-                println!("aliases: {}", config.aliases.len());
-            }
-            Ok(false)
+    }
+    for (lrc_path, path) in subtitles {
+        // "<title>.<lang>.srt" becomes "<title>.lrc" so players pick it up next to "<title>.<ext>".
+        if lrc_path.exists() {
+            continue;
         }
-        ConfigCommand::ClearDest => {
-            if config.default_destination.take().is_some() {
-                println!("cleared default destination");
-                Ok(true)
-            } else {
-                println!("default destination was already unset");
-                Ok(false)
-            }
+
+        let lrc = srt_to_lrc(&fs::read_to_string(&path)?);
+        if lrc.is_empty() {
+            continue;
         }
+        fs::write(&lrc_path, lrc)?;
+        status!("wrote lyrics to {}", lrc_path.display());
+        converted += 1;
     }
-}
 
-fn ensure_absolute(path: &Path) -> Result<PathBuf> {
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else {
-        Ok(std::env::current_dir()?.join(path))
+    if converted == 0 {
+        status!("no subtitles were found; skipping .lrc conversion");
     }
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AppConfig {
-    #[serde(default)]
-    default_destination: Option<PathBuf>,
-    #[serde(default)]
-    aliases: BTreeMap<String, AliasEntry>,
+fn srt_to_lrc(srt: &str) -> String {
+    let mut lines = Vec::new();
+    let mut last_text = String::new();
+    let normalized = srt.replace("\r\n", "\n");
+
+    for block in normalized.split("\n\n") {
+        let mut block_lines = block.lines().map(str::trim).filter(|line| !line.is_empty());
+        let Some(timing) = block_lines.find(|line| line.contains("-->")) else {
+            continue;
+        };
+        let Some(timestamp) = timing
+            .split("-->")
+            .next()
+            .and_then(|start| srt_timestamp_to_lrc(start.trim()))
+        else {
+            continue;
+        };
+
+        let text = block_lines
+            .map(strip_subtitle_markup)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        // Auto-generated captions repeat rolling lines; keep each lyric line once.
+        if text.is_empty() || text == last_text {
+            continue;
+        }
+        lines.push(format!("[{timestamp}]{text}"));
+        last_text = text;
+    }
+
+    if lines.is_empty() {
+        String::new()
+    } else {
+        lines.join("\n") + "\n"
+    }
 }
 
-impl AppConfig {
-    fn load() -> Result<Self> {
-        let path = config_file_path()?;
-        if !path.exists() {
-            return Ok(Self::default());
+fn srt_timestamp_to_lrc(timestamp: &str) -> Option<String> {
+    let (clock, millis) = timestamp.split_once([',', '.'])?;
+    let mut parts = clock.split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = parts.next()?.parse().ok()?;
+    let millis: u64 = millis.get(..millis.len().min(3))?.parse().ok()?;
+    Some(format!(
+        "{:02}:{:02}.{:02}",
+        hours * 60 + minutes,
+        seconds,
+        millis / 10
+    ))
+}
+
+fn strip_subtitle_markup(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut depth = 0usize;
+    for ch in line.chars() {
+        match ch {
+            '<' | '{' => depth += 1,
+            '>' | '}' if depth > 0 => depth -= 1,
+            _ if depth == 0 => plain.push(ch),
+            _ => {}
         }
-        let data = fs::read(&path)?;
-        if data.is_empty() {
-            return Ok(Self::default());
+    }
+    plain.trim().to_string()
+}
+
+// Processing always runs in the same order: silence trimming, then fades, then
+// loudness normalization (which measures the trimmed and faded signal), and
+// finally ReplayGain tagging of the finished file.
+#[derive(Clone, Debug, Default)]
+struct AudioPostProcess {
+    trim_silence: Option<f64>,
+    fade_in: Option<f64>,
+    fade_out: Option<f64>,
+    normalize: Option<Normalization>,
+    replaygain: bool,
+    album_gain_only: bool,
+}
+
+impl AudioPostProcess {
+    fn is_active(&self) -> bool {
+        self.trim_silence.is_some()
+            || self.fade_in.is_some()
+            || self.fade_out.is_some()
+            || self.normalize.is_some()
+            || self.replaygain
+            || self.album_gain_only
+    }
+
+    fn shaping_filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        let trim = self.trim_silence.map(silence_trim_filter);
+
+        if let Some(trim) = &trim {
+            filters.push(trim.clone());
         }
-        let mut config: Self = serde_json::from_slice(&data)?;
-        if config.default_destination.is_none() {
-            config.default_destination = default_music_dir();
+        // silenceremove only trims reliably from the start and afade needs the track
+        // length for a fade-out, so the tail is handled on the reversed signal.
+        if trim.is_some() || self.fade_out.is_some() {
+            filters.push("areverse".to_string());
+            if let Some(trim) = &trim {
+                filters.push(trim.clone());
+            }
+            if let Some(duration) = self.fade_out {
+                filters.push(format!("afade=t=in:d={duration}"));
+            }
+            filters.push("areverse".to_string());
         }
-        Ok(config)
+        if let Some(duration) = self.fade_in {
+            filters.push(format!("afade=t=in:d={duration}"));
+        }
+        filters
     }
+}
 
-    fn save(&self) -> Result<()> {
-        let path = config_file_path()?;
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+fn validate_fade(flag: &str, duration: Option<f64>) -> Result<Option<f64>> {
+    match duration {
+        Some(duration) if !duration.is_finite() || duration < 0.0 => Err(AppError::Message(
+            format!("{flag} must be a non-negative number of seconds, got {duration}"),
+        )),
+        Some(0.0) => Ok(None),
+        other => Ok(other),
+    }
+}
+
+fn build_silence_trim(trim: bool, threshold: Option<f64>) -> Result<Option<f64>> {
+    if !trim {
+        return match threshold {
+            Some(_) => Err(AppError::Message(
+                "--silence-threshold requires --trim-silence".to_string(),
+            )),
+            None => Ok(None),
+        };
+    }
+
+    let threshold = threshold.unwrap_or(DEFAULT_SILENCE_THRESHOLD_DB);
+    // Anything louder than -20 dB would eat into quiet intros and fade-outs.
+    if !(-90.0..=-20.0).contains(&threshold) {
+        return Err(AppError::Message(format!(
+            "--silence-threshold must be between -90 and -20 dB, got {threshold}"
+        )));
+    }
+    Ok(Some(threshold))
+}
+
+fn silence_trim_filter(threshold_db: f64) -> String {
+    format!(
+        "silenceremove=start_periods=1:start_threshold={threshold_db}dB:start_silence={SILENCE_PADDING_SECS}"
+    )
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Normalization {
+    target_lufs: f64,
+    album: bool,
+}
+
+fn build_normalization(
+    normalize: bool,
+    album: bool,
+    target: Option<f64>,
+) -> Result<Option<Normalization>> {
+    if !normalize && !album {
+        return match target {
+            Some(_) => Err(AppError::Message(
+                "--normalize-target requires --normalize or --normalize-album".to_string(),
+            )),
+            None => Ok(None),
+        };
+    }
+
+    let target_lufs = target.unwrap_or(DEFAULT_TARGET_LUFS);
+    if !(-70.0..=-5.0).contains(&target_lufs) {
+        return Err(AppError::Message(format!(
+            "--normalize-target must be between -70 and -5 LUFS, got {target_lufs}"
+        )));
+    }
+
+    Ok(Some(Normalization { target_lufs, album }))
+}
+
+fn audio_extension_for_format(format: &str) -> Option<&'static str> {
+    match format.to_ascii_lowercase().as_str() {
+        "mp3" => Some("mp3"),
+        "m4a" | "aac" | "alac" => Some("m4a"),
+        "opus" => Some("opus"),
+        "vorbis" | "ogg" => Some("ogg"),
+        "flac" => Some("flac"),
+        "wav" => Some("wav"),
+        _ => None,
+    }
+}
+
+// The files a yt-dlp run finished, as yt-dlp itself reports them through
+// --print-to-file, rather than guessed from what changed in the destination.
+struct ProducedFiles {
+    list: PathBuf,
+}
+
+impl ProducedFiles {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let list = std::env::temp_dir().join(format!(
+            "bippi-produced-{}-{}.txt",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        Self { list }
+    }
+
+    fn record(&self, command: &mut Command) {
+        // yt-dlp reads the file name as an output template, where '%' starts a field.
+        command
+            .arg("--print-to-file")
+            .arg("after_move:filepath")
+            .arg(self.list.to_string_lossy().replace('%', "%%"));
+    }
+
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        let listed = match fs::read_to_string(&self.list) {
+            Ok(listed) => listed,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut files: Vec<PathBuf> = listed
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+}
+
+impl Drop for ProducedFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.list);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AudioProbe {
+    duration: Option<f64>,
+    sample_rate: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LoudnessMeasurement {
+    input_i: f64,
+    input_tp: f64,
+    input_lra: f64,
+    input_thresh: f64,
+    target_offset: f64,
+}
+
+fn post_process_audio(files: &[PathBuf], post: &AudioPostProcess) -> Result<()> {
+    if files.is_empty() || !post.is_active() {
+        return Ok(());
+    }
+
+    let probes = files
+        .iter()
+        .map(|file| probe_audio(file))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut normalize = post.normalize;
+    let album_gain = match normalize {
+        Some(normalization) if normalization.album => {
+            status!("analyzing loudness of {} track(s)", files.len());
+            let mut measured = Vec::with_capacity(files.len());
+            for (file, probe) in files.iter().zip(&probes) {
+                let loudness =
+                    measure_loudness(file, &post.shaping_filters(), normalization.target_lufs)?;
+                measured.push((loudness.input_i, probe.duration.unwrap_or(1.0)));
+            }
+            match album_loudness(&measured) {
+                Some(loudness) => {
+                    let gain = normalization.target_lufs - loudness;
+                    status!(
+                        "album loudness {:.1} LUFS; applying {:+.1} dB to every track",
+                        loudness,
+                        gain
+                    );
+                    Some(gain)
+                }
+                None => {
+                    status!("album is silent; skipping normalization");
+                    normalize = None;
+                    None
+                }
+            }
         }
-        let json = serde_json::to_vec_pretty(self)?;
-        fs::write(path, json)?;
-        Ok(())
+        _ => None,
+    };
+
+    for (file, probe) in files.iter().zip(&probes) {
+        let mut filters = post.shaping_filters();
+
+        if let Some(normalization) = normalize {
+            if let Some(gain) = album_gain {
+                filters.push(format!("volume={gain:.2}dB"));
+                filters.push(format!(
+                    "alimiter=limit={:.3}:level=false",
+                    db_to_linear(TRUE_PEAK_LIMIT_DB)
+                ));
+            } else {
+                let measured = measure_loudness(file, &filters, normalization.target_lufs)?;
+                if measured.input_i.is_finite() {
+                    filters.push(loudnorm_filter(normalization.target_lufs, Some(&measured)));
+                } else {
+                    status!("{} is silent; skipping normalization", file.display());
+                }
+            }
+        }
+
+        if filters.is_empty() {
+            continue;
+        }
+        status!("processing {}", file.display());
+        rewrite_with_filters(file, &filters, probe.sample_rate)?;
+    }
+
+    if post.replaygain {
+        write_replaygain_tags(files, &probes)?;
     }
+    if post.album_gain_only {
+        write_album_gain_tags(files)?;
+    }
+
+    Ok(())
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self {
-            default_destination: default_music_dir(),
-            aliases: BTreeMap::new(),
+// --album-gain-only: one ffmpeg run measures the tracks back to back, which
+// yields the album's loudness and true peak without a pass per track.
+fn write_album_gain_tags(files: &[PathBuf]) -> Result<()> {
+    status!("measuring album loudness across {} track(s)", files.len());
+    let measured = measure_album_loudness(files)?;
+    if !measured.input_i.is_finite() {
+        status!("album is silent; skipping album gain");
+        return Ok(());
+    }
+
+    let album = ReplayGainValues {
+        gain: REPLAYGAIN_REFERENCE_LUFS - measured.input_i,
+        peak: db_to_linear(measured.input_tp),
+    };
+    status!(
+        "album loudness {:.1} LUFS; tagging album gain {:+.2} dB",
+        measured.input_i,
+        album.gain
+    );
+    for file in files {
+        let mut tags = replaygain_tags(None, Some(&album));
+        if lowercase_extension(file) == "opus" {
+            tags.push(("R128_ALBUM_GAIN".to_string(), r128_gain(measured.input_i)));
         }
+        write_tags(file, &tags)?;
     }
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AliasEntry {
-    url: String,
-    #[serde(default)]
-    album: bool,
+fn measure_album_loudness(files: &[PathBuf]) -> Result<LoudnessMeasurement> {
+    let mut command = Command::new("ffmpeg");
+    command.arg("-hide_banner").arg("-nostats");
+    for file in files {
+        command.arg("-i").arg(file);
+    }
+    let output = command
+        .arg("-filter_complex")
+        .arg(album_loudness_graph(files.len(), REPLAYGAIN_REFERENCE_LUFS))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_ffmpeg_error)?;
+
+    if !output.status.success() {
+        return Err(AppError::Message(
+            "ffmpeg could not analyze the album's loudness".to_string(),
+        ));
+    }
+    parse_loudnorm_output(&String::from_utf8_lossy(&output.stderr)).ok_or_else(|| {
+        AppError::Message("could not read the album's loudness measurements".to_string())
+    })
 }
 
-fn config_file_path() -> Result<PathBuf> {
-    let mut base = dirs::config_dir().ok_or(AppError::MissingConfigDir)?;
-    base.push(APP_NAME);
-    base.push(CONFIG_FILENAME);
-    Ok(base)
+// Tracks may differ in sample rate and layout, so each is resampled to a common
+// format before concat joins them into one stream for loudnorm.
+fn album_loudness_graph(tracks: usize, target_lufs: f64) -> String {
+    let mut graph = String::new();
+    for index in 0..tracks {
+        graph.push_str(&format!(
+            "[{index}:a:0]aresample=48000,aformat=sample_fmts=fltp:channel_layouts=stereo[a{index}];"
+        ));
+    }
+    for index in 0..tracks {
+        graph.push_str(&format!("[a{index}]"));
+    }
+    graph.push_str(&format!(
+        "concat=n={}:v=0:a=1,{}",
+        tracks,
+        loudnorm_filter(target_lufs, None)
+    ));
+    graph
 }
 
-fn default_music_dir() -> Option<PathBuf> {
-    dirs::home_dir().map(|home| home.join("music"))
+// Q7.8 fixed point dB, as Opus players expect.
+fn r128_gain(loudness: f64) -> String {
+    let gain = ((R128_REFERENCE_LUFS - loudness) * 256.0).round();
+    (gain.clamp(i16::MIN as f64, i16::MAX as f64) as i16).to_string()
 }
 
-#[derive(Parser, Debug)]
-#[command(
-    author,
-    version,
-    about = "Download music from YouTube and other sources",
-    propagate_version = true
-)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+fn write_replaygain_tags(files: &[PathBuf], probes: &[AudioProbe]) -> Result<()> {
+    status!("computing ReplayGain for {} track(s)", files.len());
+    let measurements = files
+        .iter()
+        .map(|file| measure_loudness(file, &[], REPLAYGAIN_REFERENCE_LUFS))
+        .collect::<Result<Vec<_>>>()?;
+
+    let weighted: Vec<(f64, f64)> = measurements
+        .iter()
+        .zip(probes)
+        .map(|(measured, probe)| (measured.input_i, probe.duration.unwrap_or(1.0)))
+        .collect();
+    let album = album_loudness(&weighted).map(|loudness| ReplayGainValues {
+        gain: REPLAYGAIN_REFERENCE_LUFS - loudness,
+        peak: measurements
+            .iter()
+            .map(|measured| db_to_linear(measured.input_tp))
+            .fold(0.0, f64::max),
+    });
+
+    for (file, measured) in files.iter().zip(&measurements) {
+        let track = measured.input_i.is_finite().then(|| ReplayGainValues {
+            gain: REPLAYGAIN_REFERENCE_LUFS - measured.input_i,
+            peak: db_to_linear(measured.input_tp),
+        });
+        let tags = replaygain_tags(track.as_ref(), album.as_ref());
+        if tags.is_empty() {
+            status!("{} is silent; skipping ReplayGain", file.display());
+            continue;
+        }
+        write_tags(file, &tags)?;
+    }
+
+    Ok(())
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Download a single track using a URL, alias, or search
-    Single(DownloadArgs),
-    /// Download an entire album/playlist
-    Album(DownloadArgs),
-    /// Manage human-friendly aliases for URLs
-    Alias {
-        #[command(subcommand)]
-        command: AliasCommand,
-    },
-    /// Configure default download settings
-    Config {
-        #[command(subcommand)]
-        command: ConfigCommand,
-    },
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ReplayGainValues {
+    gain: f64,
+    peak: f64,
 }
 
-#[derive(Args, Debug)]
-struct DownloadArgs {
-    /// URL, alias name, or free-form search query
-    #[arg(value_name = "TARGET", num_args = 1..)]
-    target: Vec<String>,
-    /// Destination directory for the downloaded audio
-    #[arg(short, long)]
-    dest: Option<PathBuf>,
-    /// Audio format (mp3, m4a, flac ...)
-    #[arg(short, long, default_value = "mp3")]
-    format: String,
+fn replaygain_tags(
+    track: Option<&ReplayGainValues>,
+    album: Option<&ReplayGainValues>,
+) -> Vec<(String, String)> {
+    let mut tags = Vec::new();
+    if let Some(track) = track {
+        tags.push((
+            "REPLAYGAIN_TRACK_GAIN".to_string(),
+            format!("{:.2} dB", track.gain),
+        ));
+        tags.push((
+            "REPLAYGAIN_TRACK_PEAK".to_string(),
+            format!("{:.6}", track.peak),
+        ));
+    }
+    if let Some(album) = album {
+        tags.push((
+            "REPLAYGAIN_ALBUM_GAIN".to_string(),
+            format!("{:.2} dB", album.gain),
+        ));
+        tags.push((
+            "REPLAYGAIN_ALBUM_PEAK".to_string(),
+            format!("{:.6}", album.peak),
+        ));
+    }
+    tags
 }
 
-#[derive(Subcommand, Debug)]
-enum AliasCommand {
-    /// Create or update an alias mapped to a URL
-    Add(AliasAddArgs),
-    /// Remove an alias
-    Remove(AliasRemoveArgs),
-    /// List all aliases
-    List,
+fn write_tags(file: &Path, tags: &[(String, String)]) -> Result<()> {
+    let ext = lowercase_extension(file);
+    let temp = sibling_temp_path(file);
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(file)
+        .arg("-map")
+        .arg("0")
+        .arg("-map_metadata")
+        .arg("0")
+        .arg("-c")
+        .arg("copy");
+    command.args(metadata_args(tags));
+    // The mp4 muxer drops keys it does not know unless told to keep them.
+    if ext == "m4a" {
+        command.arg("-movflags").arg("use_metadata_tags");
+    }
+    command.arg(&temp);
+
+    replace_with_ffmpeg_output(command, &temp, file, "write tags to")
 }
 
-#[derive(Args, Debug)]
-struct AliasAddArgs {
-    /// Short name for the alias (e.g. "focus")
-    name: String,
-    /// URL that the alias resolves to
-    url: String,
-    /// Mark the alias as an album/playlist
-    #[arg(long)]
-    album: bool,
+fn metadata_args(tags: &[(String, String)]) -> Vec<String> {
+    tags.iter()
+        .flat_map(|(key, value)| ["-metadata".to_string(), format!("{key}={value}")])
+        .collect()
 }
 
-#[derive(Args, Debug)]
-struct AliasRemoveArgs {
-    /// Alias name to remove
-    name: String,
+fn loudnorm_filter(target_lufs: f64, measured: Option<&LoudnessMeasurement>) -> String {
+    let mut filter = format!(
+        "loudnorm=I={}:TP={}:LRA={}",
+        target_lufs, TRUE_PEAK_LIMIT_DB, LOUDNESS_RANGE_TARGET
+    );
+    match measured {
+        // Second pass: feeding the first-pass measurements back lets loudnorm apply a
+        // linear gain instead of dynamically compressing the track.
+        Some(measured) => filter.push_str(&format!(
+            ":measured_I={:.2}:measured_TP={:.2}:measured_LRA={:.2}:measured_thresh={:.2}:offset={:.2}:linear=true",
+            measured.input_i,
+            measured.input_tp,
+            measured.input_lra,
+            measured.input_thresh,
+            measured.target_offset
+        )),
+        None => filter.push_str(":print_format=json"),
+    }
+    filter
 }
 
-#[derive(Subcommand, Debug)]
-enum ConfigCommand {
-    /// Set the default download destination directory
-    SetDest(ConfigSetDestArgs),
-    /// Show the current configuration
-    Show,
-    /// Clear the default download destination
-    ClearDest,
+fn album_loudness(tracks: &[(f64, f64)]) -> Option<f64> {
+    let mut energy = 0.0;
+    let mut total_duration = 0.0;
+    for &(loudness, duration) in tracks {
+        if loudness.is_finite() && duration > 0.0 {
+            energy += duration * 10f64.powf(loudness / 10.0);
+            total_duration += duration;
+        }
+    }
+    if energy > 0.0 && total_duration > 0.0 {
+        Some(10.0 * (energy / total_duration).log10())
+    } else {
+        None
+    }
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
 }
 
-#[derive(Args, Debug)]
-struct ConfigSetDestArgs {
-    /// Directory path where downloads should be saved by default
-    path: PathBuf,
-}
+fn measure_loudness(
+    file: &Path,
+    filters: &[String],
+    target_lufs: f64,
+) -> Result<LoudnessMeasurement> {
+    let mut chain = filters.to_vec();
+    chain.push(loudnorm_filter(target_lufs, None));
+
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(file)
+        .arg("-af")
+        .arg(chain.join(","))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_ffmpeg_error)?;
+
+    if !output.status.success() {
+        return Err(AppError::Message(format!(
+            "ffmpeg could not analyze {}",
+            file.display()
+        )));
+    }
+
+    parse_loudnorm_output(&String::from_utf8_lossy(&output.stderr)).ok_or_else(|| {
+        AppError::Message(format!(
+            "could not read loudness measurements for {}",
+            file.display()
+        ))
+    })
+}
+
+fn parse_loudnorm_output(stderr: &str) -> Option<LoudnessMeasurement> {
+    let start = stderr.rfind('{')?;
+    let end = stderr[start..].find('}')? + start;
+    let parsed: serde_json::Value = serde_json::from_str(&stderr[start..=end]).ok()?;
+    let field = |name: &str| -> Option<f64> { parsed.get(name)?.as_str()?.trim().parse().ok() };
+
+    Some(LoudnessMeasurement {
+        input_i: field("input_i")?,
+        input_tp: field("input_tp")?,
+        input_lra: field("input_lra")?,
+        input_thresh: field("input_thresh")?,
+        target_offset: field("target_offset")?,
+    })
+}
+
+fn probe_audio(file: &Path) -> Result<AudioProbe> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a:0")
+        .arg("-show_entries")
+        .arg("stream=sample_rate:format=duration")
+        .arg("-of")
+        .arg("json")
+        .arg(file)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_ffmpeg_error)?;
+
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    let duration = parsed
+        .pointer("/format/duration")
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse().ok());
+    let sample_rate = parsed
+        .pointer("/streams/0/sample_rate")
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse().ok());
+
+    Ok(AudioProbe {
+        duration,
+        sample_rate,
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct AudioTags {
+    title: Option<String>,
+    artist: Option<String>,
+    album_artist: Option<String>,
+    album: Option<String>,
+    track: Option<u32>,
+    disc: Option<u32>,
+    duration: Option<f64>,
+}
+
+fn probe_tags(file: &Path) -> Result<AudioTags> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration:format_tags:stream_tags")
+        .arg("-of")
+        .arg("json")
+        .arg(file)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_ffmpeg_error)?;
+
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    Ok(parse_probe_tags(&parsed))
+}
+
+// Tag keys differ in case between containers (ID3 vs Vorbis comments), and Ogg
+// files keep their tags on the stream rather than the container.
+fn parse_probe_tags(parsed: &serde_json::Value) -> AudioTags {
+    let mut sources = vec![parsed.pointer("/format/tags")];
+    if let Some(streams) = parsed.get("streams").and_then(|v| v.as_array()) {
+        sources.extend(streams.iter().map(|stream| stream.get("tags")));
+    }
+    let tag = |key: &str| {
+        sources
+            .iter()
+            .flatten()
+            .filter_map(|tags| tags.as_object())
+            .flat_map(|tags| tags.iter())
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .and_then(|(_, value)| value.as_str())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let number =
+        |key: &str| tag(key).and_then(|value| value.split('/').next()?.trim().parse::<u32>().ok());
+
+    AudioTags {
+        title: tag("title"),
+        artist: tag("artist"),
+        album_artist: tag("album_artist").or_else(|| tag("albumartist")),
+        album: tag("album"),
+        track: number("track").or_else(|| number("tracknumber")),
+        disc: number("disc").or_else(|| number("discnumber")),
+        duration: parsed
+            .pointer("/format/duration")
+            .and_then(|v| v.as_str())
+            .and_then(|v| v.parse().ok()),
+    }
+}
+
+fn rewrite_with_filters(file: &Path, filters: &[String], sample_rate: Option<u32>) -> Result<()> {
+    let ext = lowercase_extension(file);
+    let temp = sibling_temp_path(file);
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(file)
+        .arg("-map")
+        .arg("0:a")
+        .arg("-map_metadata")
+        .arg("0")
+        .arg("-af")
+        .arg(filters.join(","));
+    // loudnorm upsamples internally, so always write back at the source rate.
+    if let Some(rate) = sample_rate {
+        command.arg("-ar").arg(rate.to_string());
+    }
+    command.args(reencode_quality_args(&ext)).arg(&temp);
+
+    replace_with_ffmpeg_output(command, &temp, file, "process")
+}
+
+fn lowercase_extension(file: &Path) -> String {
+    file.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn sibling_temp_path(file: &Path) -> PathBuf {
+    let stem = file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("track");
+    file.with_file_name(format!("{stem}.bippi-tmp.{}", lowercase_extension(file)))
+}
+
+fn replace_with_ffmpeg_output(
+    mut command: Command,
+    temp: &Path,
+    file: &Path,
+    action: &str,
+) -> Result<()> {
+    let status = command
+        .stdin(Stdio::null())
+        .status()
+        .map_err(map_ffmpeg_error)?;
+    if !status.success() {
+        let _ = fs::remove_file(temp);
+        return Err(AppError::Message(format!(
+            "ffmpeg failed to {} {}",
+            action,
+            file.display()
+        )));
+    }
+
+    fs::rename(temp, file)?;
+    Ok(())
+}
+
+fn reencode_quality_args(ext: &str) -> &'static [&'static str] {
+    match ext {
+        "mp3" => &["-q:a", "2"],
+        "m4a" | "aac" => &["-b:a", "256k"],
+        "opus" => &["-b:a", "160k"],
+        "ogg" => &["-q:a", "6"],
+        _ => &[],
+    }
+}
+
+fn map_ffmpeg_error(err: std::io::Error) -> AppError {
+    if err.kind() == ErrorKind::NotFound {
+        AppError::Message(
+            "ffmpeg/ffprobe was not found in PATH. Install it from https://ffmpeg.org and try again.".to_string(),
+        )
+    } else {
+        AppError::Io(err)
+    }
+}
+
+// With --quiet-ytdlp yt-dlp's error lines are held back and only shown when
+// the run fails.
+fn run_yt_dlp(command: Command) -> Result<()> {
+    run_yt_dlp_capturing(command)?.map_err(|(err, _)| err)
+}
+
+// Runs yt-dlp like run_yt_dlp, but hands back the stderr of a failed run so
+// the caller can tell what kind of failure it was. Without --quiet the lines
+// are passed on as they arrive, so the user sees them as before.
+fn run_yt_dlp_capturing(
+    mut command: Command,
+) -> Result<std::result::Result<(), (AppError, String)>> {
+    let quiet = command.get_args().any(|arg| arg == "--quiet");
+    let mut child = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(map_yt_dlp_error)?;
+    let mut stderr = String::new();
+    if let Some(pipe) = child.stderr.take() {
+        for line in BufReader::new(pipe).split(b'\n') {
+            let line = String::from_utf8_lossy(&line?).into_owned();
+            if !quiet {
+                eprintln!("{}", line);
+            }
+            stderr.push_str(&line);
+            stderr.push('\n');
+        }
+    }
+    let status = child.wait()?;
+
+    if status.success() {
+        Ok(Ok(()))
+    } else if max_downloads_reached(&command, status.code()) {
+        status!("stopped after reaching the --max-downloads limit");
+        Ok(Ok(()))
+    } else {
+        if quiet {
+            for line in stderr.lines().filter(|line| !line.trim().is_empty()) {
+                eprintln!("{}", line);
+            }
+        }
+        let err = AppError::YtDlpFailed(status.code().unwrap_or(-1));
+        Ok(Err((err, stderr)))
+    }
+}
+
+// Only a failure to fetch or convert to the requested format is worth
+// retrying in the next format; anything else would fail the same way again.
+fn is_format_failure(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    [
+        "postprocessing:",
+        "requested format is not available",
+        "conversion failed",
+    ]
+    .iter()
+    .any(|marker| stderr.contains(marker))
+}
+
+// yt-dlp exits with 101 when --max-downloads stops it, which is the requested
+// outcome; other commands can exit 101 too, so the flag has to be present.
+const YT_DLP_MAX_DOWNLOADS_EXIT: i32 = 101;
+
+fn max_downloads_reached(command: &Command, code: Option<i32>) -> bool {
+    code == Some(YT_DLP_MAX_DOWNLOADS_EXIT)
+        && command.get_args().any(|arg| arg == "--max-downloads")
+}
+
+fn validate_max_downloads(max_downloads: Option<u32>) -> Result<Option<u32>> {
+    if max_downloads == Some(0) {
+        return Err(AppError::Message(
+            "--max-downloads must be at least 1".to_string(),
+        ));
+    }
+    Ok(max_downloads)
+}
+
+fn resolve_album_query(query: &str, keyword: &str, min_results: Option<usize>) -> Result<String> {
+    status!("searching YouTube for album '{}'", query);
+
+    match find_album_playlist(query, keyword, min_results)? {
+        Some(url) => {
+            status!("found playlist match: {}", url);
+            Ok(url)
+        }
+        None => {
+            status!(
+                "no playlist found for '{}'; falling back to first search result",
+                query
+            );
+            Ok(build_single_search_query(query))
+        }
+    }
+}
+
+fn resolve_single_query(
+    query: &str,
+    config: &AppConfig,
+    strict_allowlist: bool,
+    interactive: bool,
+    site: SearchSite,
+    min_results: Option<usize>,
+) -> Result<String> {
+    if strict_allowlist && config.allowlist.is_empty() {
+        return Err(AppError::Message(
+            "--strict-allowlist requires at least one entry in the allowlist".to_string(),
+        ));
+    }
+
+    if interactive {
+        if std::io::stdin().is_terminal() {
+            return pick_search_result(query, config, strict_allowlist, site, min_results);
+        }
+        status!("stdin is not a terminal; --interactive falls back to automatic selection");
+    }
+
+    // A YouTube Music search URL is a whole result list, so its first match
+    // has to be picked here rather than by yt-dlp. --min-results needs the
+    // result count, so it probes as well.
+    let filtered = !config.blocklist.is_empty() || !config.allowlist.is_empty();
+    if !filtered && site == SearchSite::YouTube && min_results.is_none() {
+        status!("searching YouTube for '{}' (first match)", query);
+        return Ok(build_single_search_query(query));
+    }
+
+    status!(
+        "searching {} for '{}' ({} match)",
+        site.name(),
+        query,
+        if filtered { "filtered" } else { "first" }
+    );
+    let mut results = probe_search(site, &site.query_terms(query), SEARCH_PROBE_RESULTS)?;
+    if results.is_empty() && site == SearchSite::YouTubeMusic {
+        status!("YouTube Music returned no songs; searching YouTube");
+        let youtube = SearchSite::YouTube;
+        results = probe_search(youtube, &youtube.query_terms(query), SEARCH_PROBE_RESULTS)?;
+    }
+    check_min_results(results.len(), min_results, query)?;
+    if results.is_empty() {
+        // yt-dlp's own first match cannot be checked, so it is only used when
+        // nothing would have been filtered out anyway.
+        if config.blocklist.is_empty() && !strict_allowlist {
+            return Ok(build_single_search_query(query));
+        }
+        return Err(AppError::Message(format!(
+            "no search results for '{}' could be checked against the {}; nothing was downloaded",
+            query,
+            if strict_allowlist {
+                "allowlist"
+            } else {
+                "blocklist"
+            }
+        )));
+    }
+
+    match select_search_result(&results, config, strict_allowlist) {
+        Some(result) => {
+            status!("selected '{}'", result.title);
+            Ok(result.url.clone())
+        }
+        None if strict_allowlist => Err(AppError::Message(format!(
+            "none of the {} search results for '{}' came from an allowlisted uploader",
+            results.len(),
+            query
+        ))),
+        None => Err(AppError::Message(format!(
+            "all {} search results for '{}' matched the blocklist",
+            results.len(),
+            query
+        ))),
+    }
+}
+
+// --min-results: a short result list usually means a misspelled or overly
+// specific query, so stop before downloading whatever did come back.
+fn check_min_results(found: usize, min_results: Option<usize>, query: &str) -> Result<()> {
+    match min_results {
+        Some(min) if found < min => Err(AppError::Message(format!(
+            "only {} search result{} for '{}' (--min-results {}); try rewording the query",
+            found,
+            if found == 1 { "" } else { "s" },
+            query,
+            min
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Blocklisted results are left out of the list, and with --strict-allowlist so
+// is everything from an untrusted uploader.
+fn pick_search_result(
+    query: &str,
+    config: &AppConfig,
+    strict_allowlist: bool,
+    site: SearchSite,
+    min_results: Option<usize>,
+) -> Result<String> {
+    status!("searching {} for '{}'", site.name(), query);
+    let results = probe_search(site, &site.query_terms(query), SEARCH_PROBE_RESULTS)?;
+    check_min_results(results.len(), min_results, query)?;
+    let candidates: Vec<&SearchResult> = results
+        .iter()
+        .filter(|result| blocklist_match(result, &config.blocklist).is_none())
+        .filter(|result| !strict_allowlist || is_allowlisted(result, &config.allowlist))
+        .collect();
+    if candidates.is_empty() {
+        return Err(AppError::Message(format!(
+            "no usable search results for '{}' ({} found)",
+            query,
+            results.len()
+        )));
+    }
+
+    for (index, result) in candidates.iter().enumerate() {
+        status!("  {}) {}", index + 1, describe_search_result(result));
+    }
+    let chosen = candidates[prompt_selection("result", candidates.len())?];
+    status!("selected '{}'", chosen.title);
+    Ok(chosen.url.clone())
+}
+
+fn describe_search_result(result: &SearchResult) -> String {
+    let mut line = result.title.clone();
+    if let Some(uploader) = &result.uploader {
+        line.push_str(&format!(" - {}", uploader));
+    }
+    if let Some(duration) = result.duration {
+        line.push_str(&format!(" [{}]", format_duration(duration)));
+    }
+    line
+}
+
+fn select_search_result<'a>(
+    results: &'a [SearchResult],
+    config: &AppConfig,
+    strict_allowlist: bool,
+) -> Option<&'a SearchResult> {
+    let clean: Vec<&SearchResult> = results
+        .iter()
+        .filter(|result| match blocklist_match(result, &config.blocklist) {
+            Some(term) => {
+                status!("skipping '{}' (blocklisted: '{}')", result.title, term);
+                false
+            }
+            None => true,
+        })
+        .collect();
+
+    let trusted = clean
+        .iter()
+        .copied()
+        .find(|result| is_allowlisted(result, &config.allowlist));
+
+    if trusted.is_some() || strict_allowlist {
+        trusted
+    } else {
+        clean.first().copied()
+    }
+}
+
+fn is_allowlisted(result: &SearchResult, allowlist: &[String]) -> bool {
+    let Some(uploader) = result.uploader.as_deref() else {
+        return false;
+    };
+    let uploader = uploader.to_lowercase();
+    allowlist.iter().any(|term| {
+        let term = term.to_lowercase();
+        !term.is_empty() && uploader.contains(&term)
+    })
+}
+
+#[derive(Clone, Debug)]
+struct SearchResult {
+    title: String,
+    uploader: Option<String>,
+    url: String,
+    duration: Option<f64>,
+}
+
+fn probe_search(site: SearchSite, terms: &str, count: usize) -> Result<Vec<SearchResult>> {
+    let Some(parsed) = probe_flat_playlist(&site.search_target(terms, count), Some(count))? else {
+        return Ok(Vec::new());
+    };
+
+    Ok(parsed
+        .get("entries")
+        .and_then(|value| value.as_array())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(search_result_from_entry)
+                .take(count)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn search_result_from_entry(entry: &serde_json::Value) -> Option<SearchResult> {
+    let id = entry.get("id").and_then(|v| v.as_str());
+    let url = entry
+        .get("url")
+        .and_then(|v| v.as_str())
+        .filter(|url| url.contains("://"))
+        .map(str::to_string)
+        .or_else(|| id.map(|id| format!("https://www.youtube.com/watch?v={id}")))?;
+    let title = entry
+        .get("title")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let uploader = entry
+        .get("channel")
+        .or_else(|| entry.get("uploader"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+
+    Some(SearchResult {
+        title,
+        uploader,
+        url,
+        duration: entry.get("duration").and_then(|v| v.as_f64()),
+    })
+}
+
+fn blocklist_match<'a>(result: &SearchResult, blocklist: &'a [String]) -> Option<&'a str> {
+    let title = result.title.to_lowercase();
+    let uploader = result
+        .uploader
+        .as_deref()
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+
+    blocklist.iter().map(String::as_str).find(|term| {
+        let term = term.to_lowercase();
+        !term.is_empty() && (title.contains(&term) || uploader.contains(&term))
+    })
+}
+
+// `limit` stops yt-dlp after that many entries; a YouTube Music search URL
+// carries no count of its own and would otherwise be paged through to the end.
+fn probe_flat_playlist(target: &str, limit: Option<usize>) -> Result<Option<serde_json::Value>> {
+    let mut command = Command::new("yt-dlp");
+    command.arg("--flat-playlist").arg("-J");
+    if let Some(limit) = limit {
+        command.arg("--playlist-end").arg(limit.to_string());
+    }
+    let output = command
+        .arg(target)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_yt_dlp_error)?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(serde_json::from_slice(&output.stdout).ok())
+}
+
+// With --min-results only playlist hits count, since videos are never picked here.
+fn find_album_playlist(
+    query: &str,
+    keyword: &str,
+    min_results: Option<usize>,
+) -> Result<Option<String>> {
+    let search_term = format!(
+        "ytsearch{}:{}",
+        SEARCH_PROBE_RESULTS,
+        album_search_terms(query, keyword)
+    );
+    let parsed = probe_flat_playlist(&search_term, None)?;
+    let playlists: Vec<String> = parsed
+        .as_ref()
+        .and_then(|parsed| parsed.get("entries"))
+        .and_then(|value| value.as_array())
+        .map(|entries| entries.iter().filter_map(playlist_url_from_entry).collect())
+        .unwrap_or_default();
+    check_min_results(playlists.len(), min_results, query)?;
+
+    Ok(playlists.into_iter().next())
+}
+
+// Like the " audio" hint for single searches, the keyword is only added when the
+// query does not already mention it.
+fn album_search_terms(query: &str, keyword: &str) -> String {
+    let query = query.trim();
+    let keyword = keyword.trim();
+    if keyword.is_empty() || query.to_lowercase().contains(&keyword.to_lowercase()) {
+        query.to_string()
+    } else {
+        format!("{} {}", query, keyword)
+    }
+}
+
+fn playlist_url_from_entry(entry: &serde_json::Value) -> Option<String> {
+    let entry_type = entry.get("_type").and_then(|v| v.as_str());
+    let ie_key = entry.get("ie_key").and_then(|v| v.as_str());
+    let url = entry.get("url").and_then(|v| v.as_str());
+    let playlist_id = entry.get("playlist_id").and_then(|v| v.as_str());
+    let id = entry.get("id").and_then(|v| v.as_str());
+    let fallback_id = playlist_id.or(id);
+
+    // A search hit that is a mix is never an album, so it is not offered.
+    if matches!(ie_key, Some("YoutubeMix"))
+        || fallback_id.is_some_and(is_mix_playlist_id)
+        || url
+            .and_then(playlist_list_id)
+            .is_some_and(|list_id| is_mix_playlist_id(&list_id))
+    {
+        return None;
+    }
+
+    if let Some(url) = url {
+        if url.contains("://") && url.contains("list=") {
+            return Some(url.to_string());
+        }
+
+        if matches!(entry_type, Some("playlist"))
+            || matches!(ie_key, Some("YoutubeTab" | "YoutubePlaylist"))
+        {
+            return Some(normalize_playlist_url(url, fallback_id));
+        }
+    }
+
+    if let Some(id) = fallback_id
+        && ["PL", "OL", "RDCLAK"]
+            .iter()
+            .any(|prefix| id.starts_with(prefix))
+    {
+        return Some(format!("https://www.youtube.com/playlist?list={id}"));
+    }
+
+    None
+}
+
+fn normalize_playlist_url(url: &str, fallback_id: Option<&str>) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else if url.starts_with("/playlist?") {
+        format!("https://www.youtube.com{url}")
+    } else if url.starts_with("playlist?") {
+        format!("https://www.youtube.com/{url}")
+    } else if url.starts_with("/watch?") {
+        format!("https://www.youtube.com{url}")
+    } else if url.starts_with("watch?") {
+        format!("https://www.youtube.com/{url}")
+    } else if let Some(id) = fallback_id {
+        format!("https://www.youtube.com/playlist?list={id}")
+    } else {
+        format!("https://www.youtube.com/playlist?list={url}")
+    }
+}
+
+fn is_direct_url(target: &str) -> bool {
+    looks_like_url(target) && !target.to_ascii_lowercase().starts_with("ytsearch")
+}
+
+fn check_availability(url: &str, options: &YtDlpOptions) -> Result<()> {
+    let mut command = Command::new("yt-dlp");
+    command
+        .arg("--simulate")
+        .arg("--no-warnings")
+        .arg("--no-playlist")
+        .arg("-J");
+    apply_access_options(&mut command, options);
+    let output = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_yt_dlp_error)?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // Anything that is not a known availability problem is left for the real
+    // download to report with yt-dlp's full output.
+    match describe_availability_error(&String::from_utf8_lossy(&output.stderr)) {
+        Some(message) => Err(AppError::Message(message)),
+        None => Ok(()),
+    }
+}
+
+fn describe_availability_error(stderr: &str) -> Option<String> {
+    let lowered = stderr.to_ascii_lowercase();
+    let region_blocked = [
+        "not available in your country",
+        "blocked it in your country",
+        "not made this video available in your country",
+        "geo restricted",
+        "geo-restricted",
+    ]
+    .iter()
+    .any(|needle| lowered.contains(needle));
+
+    region_blocked.then(|| {
+        "this content is region-blocked in your location; \
+         try --geo-bypass or --geo-bypass-country <CC>, or a proxy/VPN in a country where it is available"
+            .to_string()
+    })
+}
+
+fn map_yt_dlp_error(err: std::io::Error) -> AppError {
+    if err.kind() == ErrorKind::NotFound {
+        AppError::Message(
+            "yt-dlp was not found in PATH. Install it from https://github.com/yt-dlp/yt-dlp and try again.".to_string(),
+        )
+    } else {
+        AppError::Io(err)
+    }
+}
+
+// Settings shared by every track of a MusicBrainz-backed album download.
+struct ReleaseDownload<'a> {
+    destination: &'a Path,
+    formats: &'a [String],
+    options: &'a YtDlpOptions,
+    post_process: &'a AudioPostProcess,
+    match_threshold: f64,
+    musicbrainz: &'a MusicBrainzSettings,
+    progress_json: bool,
+    tags: TagOptions,
+    dedupe: bool,
+    // Set by --clean-titles; `None` keeps MusicBrainz titles as they are.
+    title_noise: Option<&'a [String]>,
+    // Set by --prefer-album-version; empty otherwise.
+    version_excludes: &'a [String],
+    // Off with --no-feat-variants.
+    feat_variants: bool,
+    // Set by --strip-album-suffix (or the config); `None` keeps the album title.
+    album_suffixes: Option<&'a [String]>,
+    disc: Option<u32>,
+    flat_numbering: bool,
+    file_naming: FileNaming,
+    case: TitleCase,
+    nest: bool,
+    // Set by --thumbnail-as-folder-jpg: the album art file saved in the album folder.
+    cover_name: Option<&'a str>,
+    max_path_length: usize,
+    // `None` with --no-space-check.
+    min_free_space: Option<u64>,
+    choose: bool,
+    summary: bool,
+    album_template: &'a OutputTemplate,
+    search_site: SearchSite,
+    metadata_source: MetadataSource,
+    zip: Option<AlbumZip<'a>>,
+    write_m3u: bool,
+    // Set by --continue-on-mb-error: failed lookups fall back to a YouTube search.
+    continue_on_lookup_error: bool,
+    // Set by --restart: stale partial files go just before the first download.
+    restart: bool,
+}
+
+// Set by --zip: the finished album's files are bundled at `path`, and with
+// --zip-only removed from the destination afterwards.
+#[derive(Clone, Copy)]
+struct AlbumZip<'a> {
+    path: &'a Path,
+    delete_loose: bool,
+}
+
+impl AlbumZip<'_> {
+    // Deliberate skips aside, an archive is only made of a complete album; with
+    // --zip-only the loose files would be gone and the gap easy to miss.
+    fn ensure_complete(&self, downloaded: usize, total: usize) -> Result<()> {
+        if downloaded >= total {
+            return Ok(());
+        }
+        Err(AppError::Message(format!(
+            "{} of {} tracks were not downloaded, so {} was not written; the downloaded files were kept",
+            total - downloaded,
+            total,
+            self.path.display()
+        )))
+    }
+
+    // `files` are archived in the given order, named relative to `root`, followed
+    // by `covers`: only art saved by this run, so artwork the user already kept
+    // next to the tracks is neither archived nor deleted.
+    fn write(&self, root: &Path, files: &[PathBuf], covers: &[PathBuf]) -> Result<()> {
+        if files.is_empty() {
+            status!(
+                "no files were downloaded; {} was not written",
+                self.path.display()
+            );
+            return Ok(());
+        }
+        let mut entries = files.to_vec();
+        entries.extend_from_slice(covers);
+
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let mut archive = zip::ZipWriter::new(fs::File::create(self.path)?);
+        // Audio is already compressed, so the entries are stored as they are.
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(true);
+        for entry in &entries {
+            let name = entry.strip_prefix(root).unwrap_or(entry);
+            let name = name
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            archive.start_file(name, options)?;
+            std::io::copy(&mut fs::File::open(entry)?, &mut archive)?;
+        }
+        archive.finish()?;
+        status!(
+            "{}",
+            Tone::Success.paint(format!(
+                "packed {} file{} into {}",
+                entries.len(),
+                if entries.len() == 1 { "" } else { "s" },
+                self.path.display()
+            ))
+        );
+
+        if self.delete_loose {
+            for entry in &entries {
+                fs::remove_file(entry)?;
+            }
+            status!("removed the loose files (--zip-only)");
+        }
+        Ok(())
+    }
+}
+
+// An extended M3U listing `entries` in the given order, with paths relative to
+// `root` (the folder the playlist is saved in).
+fn album_m3u(album: &Album, entries: &[(&Track, &Path)], root: &Path) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for (track, file) in entries {
+        let seconds = track
+            .duration
+            .map_or(-1, |duration| duration.round() as i64);
+        let path = file.strip_prefix(root).unwrap_or(file);
+        let path = path
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        playlist.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            seconds,
+            track_artist(album, track),
+            track.title,
+            path
+        ));
+    }
+    playlist
+}
+
+fn write_album_m3u(
+    album: &Album,
+    entries: &[(&Track, &Path)],
+    root: &Path,
+    naming: FileNaming,
+) -> Result<()> {
+    if entries.is_empty() {
+        status!("no files were downloaded; no .m3u8 playlist was written");
+        return Ok(());
+    }
+    let path = root.join(format!("{}.m3u8", naming.name(&album.title)));
+    fs::write(&path, album_m3u(album, entries, root))?;
+    status!(
+        "{}",
+        Tone::Success.paint(format!("wrote playlist {}", path.display()))
+    );
+    Ok(())
+}
+
+// Network, timeout and bad-response errors, as opposed to a release that does
+// not exist or a cancelled --choose prompt.
+fn is_lookup_failure(err: &AppError) -> bool {
+    matches!(
+        err,
+        AppError::Http(_)
+            | AppError::MusicBrainzTimeout(_)
+            | AppError::EmptyRelease(_)
+            | AppError::MusicBrainzResponse(_)
+    )
+}
+
+fn download_album_with_metadata(query: &str, download: &ReleaseDownload) -> Result<Vec<PathBuf>> {
+    let resolver = download.metadata_source.resolver(download.musicbrainz)?;
+    status!("searching {} for album '{}'", resolver.name(), query);
+
+    let found = if download.choose {
+        resolver.choose_album(query)
+    } else {
+        resolver.find_album(query)
+    };
+    let found = match found {
+        Err(err) if download.continue_on_lookup_error && is_lookup_failure(&err) => {
+            return Err(AppError::MetadataLookupFailed {
+                backend: resolver.name(),
+                detail: err.to_string(),
+            });
+        }
+        found => found?,
+    };
+    let Some(mut album) = found else {
+        return Err(AppError::AlbumNotFound {
+            backend: resolver.name(),
+            query: query.to_string(),
+        });
+    };
+    clean_album_titles(&mut album, download.title_noise);
+    strip_album_title_suffixes(&mut album, download.album_suffixes);
+    recase_album_titles(&mut album, download.case);
+    announce_release(&album);
+    select_disc(&mut album, download.disc)?;
+
+    let targets = find_track_urls(&album, download)?;
+    download_album_tracks(&album, &targets, download)
+}
+
+// Aliases pinned to a release skip the MusicBrainz search, and once the YouTube
+// URL of every track is known they skip the YouTube searches as well.
+fn download_alias_release(
+    name: &str,
+    alias: &AliasEntry,
+    mbid: &str,
+    download: &ReleaseDownload,
+) -> Result<Vec<PathBuf>> {
+    status!("using alias '{}' -> MusicBrainz release {}", name, mbid);
+
+    let client = MusicBrainzClient::new(download.musicbrainz)?;
+    let mut album = client.fetch_release(mbid)?;
+    clean_album_titles(&mut album, download.title_noise);
+    strip_album_title_suffixes(&mut album, download.album_suffixes);
+    recase_album_titles(&mut album, download.case);
+    announce_release(&album);
+    select_disc(&mut album, download.disc)?;
+
+    // Stored URLs cover the whole release, in track order.
+    if alias.track_urls.len() == album.track_total {
+        status!("using the stored YouTube URL for each track");
+        let targets: Vec<Option<String>> = album
+            .tracks
+            .iter()
+            .map(|track| alias.track_urls.get(track.overall_index - 1).cloned())
+            .collect();
+        return download_album_tracks(&album, &targets, download);
+    }
+    if !alias.track_urls.is_empty() {
+        status!("stored track URLs no longer match the release; searching again");
+    }
+
+    let targets = find_track_urls(&album, download)?;
+    let produced = download_album_tracks(&album, &targets, download)?;
+    if download.options.simulate || download.disc.is_some() {
+        return Ok(produced);
+    }
+
+    // Only a complete set of real URLs is worth remembering.
+    let urls: Option<Vec<String>> = targets
+        .into_iter()
+        .map(|target| target.filter(|url| is_direct_url(url)))
+        .collect();
+    if let Some(urls) = urls {
+        update_alias_entry(name, |entry| {
+            entry.track_urls = urls;
+            status!("stored track URLs for alias '{}'", name);
+        })?;
+    }
+    Ok(produced)
+}
+
+// The candidate list comes straight from the search response, so only the
+// release that is picked costs a detail request.
+fn choose_release(client: &MusicBrainzClient, query: &str) -> Result<Option<Album>> {
+    let candidates = client.search_releases(query, RELEASE_CANDIDATES)?;
+    let Some(first) = candidates.first() else {
+        return Ok(None);
+    };
+    if candidates.len() == 1 {
+        return client.fetch_release(&first.id).map(Some);
+    }
+    for (index, candidate) in candidates.iter().enumerate() {
+        status!("  {}) {}", index + 1, describe_release_candidate(candidate));
+    }
+    let index = if std::io::stdin().is_terminal() {
+        prompt_selection("release", candidates.len())?
+    } else {
+        status!("stdin is not a terminal; using the first release");
+        0
+    };
+    client.fetch_release(&candidates[index].id).map(Some)
+}
+
+// Even a quoted "Artist - Album" query can put a fuzzy match first, so a low
+// MusicBrainz score is surfaced instead of silently trusted.
+fn describe_uncertain_match(candidate: &MbReleaseSearchEntry) -> Option<String> {
+    let score = candidate
+        .score
+        .filter(|score| *score < UNCERTAIN_MATCH_SCORE)?;
+    Some(format!(
+        "uncertain match (MusicBrainz score {}): {}; use --choose to pick the release, or pin one with `alias add <name> --album --mbid <MBID>`",
+        score,
+        describe_release_candidate(candidate)
+    ))
+}
+
+fn describe_release_candidate(candidate: &MbReleaseSearchEntry) -> String {
+    let artist = format_artist_credit(&candidate.artist_credit);
+    let mut details: Vec<String> = [&candidate.date, &candidate.country]
+        .into_iter()
+        .flatten()
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .collect();
+    let formats: Vec<&str> = candidate
+        .media
+        .iter()
+        .filter_map(|medium| medium.format.as_deref())
+        .collect();
+    if !formats.is_empty() {
+        details.push(formats.join(" + "));
+    }
+    if let Some(count) = candidate.track_count {
+        details.push(format!(
+            "{} track{}",
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+    format!(
+        "{} - {}{} ({})",
+        if artist.is_empty() {
+            "Unknown Artist"
+        } else {
+            &artist
+        },
+        candidate.title.as_deref().unwrap_or("Unknown Release"),
+        describe_disambiguation(candidate.disambiguation()),
+        details.join(", ")
+    )
+}
+
+// Asks until a number from the list is entered; an empty line or EOF picks the first.
+fn prompt_selection(label: &str, count: usize) -> Result<usize> {
+    let stdin = std::io::stdin();
+    loop {
+        eprint!("pick a {} [1-{}, default 1]: ", label, count);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(0);
+        }
+        match parse_selection(&line, count) {
+            Some(index) => return Ok(index),
+            None => eprintln!("enter a number between 1 and {}", count),
+        }
+    }
+}
+
+fn parse_selection(input: &str, count: usize) -> Option<usize> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Some(0);
+    }
+    match input.parse::<usize>() {
+        Ok(choice) if (1..=count).contains(&choice) => Some(choice - 1),
+        _ => None,
+    }
+}
+
+fn describe_disambiguation(disambiguation: Option<&str>) -> String {
+    disambiguation
+        .map(|comment| format!(" [{}]", comment))
+        .unwrap_or_default()
+}
+
+// --disc: the tracks keep their release-wide numbers, so names and tags come
+// out the same as in a full download.
+fn select_disc(album: &mut Album, disc: Option<u32>) -> Result<()> {
+    let Some(disc) = disc else {
+        return Ok(());
+    };
+    if !album.tracks.iter().any(|track| track.disc == disc) {
+        return Err(AppError::Message(format!(
+            "--disc {} is out of range; '{}' has {} disc{}",
+            disc,
+            album.title,
+            album.total_discs,
+            if album.total_discs == 1 { "" } else { "s" }
+        )));
+    }
+    album.tracks.retain(|track| track.disc == disc);
+    status!(
+        "downloading only disc {} of {} ({} track{})",
+        disc,
+        album.total_discs,
+        album.tracks.len(),
+        if album.tracks.len() == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+fn announce_release(album: &Album) {
+    status!(
+        "found release: {} - {}{} ({} track{})",
+        album.artist,
+        album.title,
+        describe_disambiguation(album.disambiguation.as_deref()),
+        album.tracks.len(),
+        if album.tracks.len() == 1 { "" } else { "s" }
+    );
+}
+
+// The track's own credit (features, compilations), else the release artist.
+fn track_artist<'a>(album: &'a Album, track: &'a Track) -> &'a str {
+    track.artist.as_deref().unwrap_or(&album.artist)
+}
+
+// Uploads write featured artists as "ft. X", "feat. X" or leave them out, so a
+// title crediting a feature is also searched in the other spellings and without
+// it, in that order. Other titles are searched as they are.
+fn featured_title_variants(title: &str) -> Vec<String> {
+    let mut variants = vec![title.to_string()];
+    if let Some((head, featured, tail)) = split_featured_artists(title) {
+        for marker in ["ft.", "feat."] {
+            variants.push(format!("{}{} {}{}", head, marker, featured, tail));
+        }
+        let (head, tail) = match (head.strip_suffix(['(', '[']), tail.strip_prefix([')', ']'])) {
+            (Some(head), Some(tail)) => (head, tail),
+            _ => (head, tail),
+        };
+        variants.push(
+            format!("{} {}", head, tail)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    let mut seen = HashSet::new();
+    variants.retain(|variant| seen.insert(variant.clone()));
+    variants
+}
+
+// Splits "Song (feat. X) [Live]" into "Song (", "X" and ") [Live]". Outside
+// brackets only "feat.", "ft." and "featuring" count, so "A Feat of Strength"
+// stays whole.
+fn split_featured_artists(title: &str) -> Option<(&str, &str, &str)> {
+    let lower = title.to_ascii_lowercase();
+    for (start, _) in lower.char_indices() {
+        let before = lower[..start].chars().next_back();
+        let bracketed = matches!(before, Some('(' | '['));
+        if !(bracketed || matches!(before, None | Some(' '))) {
+            continue;
+        }
+        let markers: &[&str] = if bracketed {
+            &["featuring ", "feat. ", "feat ", "ft. ", "ft "]
+        } else {
+            &["featuring ", "feat. ", "ft. "]
+        };
+        let Some(marker) = markers
+            .iter()
+            .find(|marker| lower[start..].starts_with(*marker))
+        else {
+            continue;
+        };
+        let names_start = start + marker.len();
+        let closers: &[char] = if bracketed { &[')', ']'] } else { &['(', '['] };
+        let names_end = title[names_start..]
+            .find(closers)
+            .map_or(title.len(), |offset| names_start + offset);
+        let featured = title[names_start..names_end].trim_end();
+        if featured.is_empty() {
+            continue;
+        }
+        return Some((
+            &title[..start],
+            featured,
+            &title[names_start + featured.len()..],
+        ));
+    }
+    None
+}
+
+// Picks a YouTube video per track by title similarity instead of trusting the
+// first search hit. `None` marks a track with no result above the threshold.
+fn find_track_urls(album: &Album, download: &ReleaseDownload) -> Result<Vec<Option<String>>> {
+    let threshold = download.match_threshold;
+    let site = download.search_site;
+    let total_tracks = album.tracks.len();
+    let mut targets = Vec::with_capacity(total_tracks);
+    for (index, track) in album.tracks.iter().enumerate() {
+        let progress = Tone::Info.paint(format!("[{}/{}]", index + 1, total_tracks));
+        status!(
+            "{} searching {} for '{} - {}'",
+            progress,
+            site.name(),
+            track_artist(album, track),
+            track.title
+        );
+
+        // Only the search terms change; the title is still scored as MusicBrainz has it.
+        let titles = if download.feat_variants {
+            featured_title_variants(&track.title)
+        } else {
+            vec![track.title.clone()]
+        };
+        let mut first_terms = None;
+        let mut best: Option<(SearchResult, f64)> = None;
+        for (attempt, title) in titles.iter().enumerate() {
+            if attempt > 0 {
+                status!("{} no confident match; searching for '{}'", progress, title);
+            }
+            let terms = exclude_other_versions(
+                site.query_terms(&format!(
+                    "{} {} {}",
+                    track_artist(album, track),
+                    title,
+                    album.title
+                )),
+                track,
+                download,
+            );
+            let results = probe_search(site, &terms, TRACK_SEARCH_RESULTS)?;
+            first_terms.get_or_insert(terms);
+            if let Some((found, score)) =
+                best_album_track_candidate(album, track, &results, download)
+                && best
+                    .as_ref()
+                    .is_none_or(|(_, best_score)| score > *best_score)
+            {
+                best = Some((found.clone(), score));
+            }
+            if best.as_ref().is_some_and(|(_, score)| *score >= threshold) {
+                break;
+            }
+        }
+        let Some((best, score)) = best else {
+            status!(
+                "{} {}",
+                progress,
+                Tone::Warning.paint("search probe returned nothing; using the first match")
+            );
+            targets.push(first_terms.map(|terms| format!("ytsearch1:{}", terms)));
+            continue;
+        };
+        if score >= threshold {
+            status!(
+                "{} {}",
+                progress,
+                Tone::Success.paint(format!("chose '{}' (score {:.2})", best.title, score))
+            );
+            targets.push(Some(best.url.clone()));
+        } else {
+            status!(
+                "{} {}",
+                progress,
+                Tone::Warning.paint(format!(
+                    "best result '{}' scored {:.2}, below {:.2}; skipping",
+                    best.title, score, threshold
+                ))
+            );
+            targets.push(None);
+        }
+    }
+    Ok(targets)
+}
+
+// Other ways to search for a track whose chosen video failed to download, for
+// example because it is region-blocked. Tried in order before giving up.
+fn track_search_variants(album: &Album, track: &Track) -> Vec<String> {
+    let base = format!("{} {}", track_artist(album, track), track.title);
+    vec![
+        base.clone(),
+        format!("{} official audio", base),
+        format!("{} lyrics", base),
+    ]
+}
+
+// The best match for `terms` that has not been tried yet, held to the same
+// --match-threshold as the first search.
+fn find_alternate_track_url(
+    album: &Album,
+    track: &Track,
+    terms: &str,
+    tried: &[String],
+    download: &ReleaseDownload,
+) -> Result<Option<String>> {
+    let terms = exclude_other_versions(terms.to_string(), track, download);
+    let results: Vec<SearchResult> =
+        probe_search(download.search_site, &terms, TRACK_SEARCH_RESULTS)?
+            .into_iter()
+            .filter(|result| !tried.contains(&result.url))
+            .collect();
+    Ok(best_album_track_candidate(album, track, &results, download)
+        .filter(|(_, score)| *score >= download.match_threshold)
+        .map(|(best, _)| best.url.clone()))
+}
+
+// The --prefer-album-version terms that the track's own title does not contain,
+// so "Battery (Live)" on a live album still matches live uploads.
+fn unwanted_versions<'a>(title: &str, excludes: &'a [String]) -> Vec<&'a str> {
+    let title = format!(" {} ", normalize_title(title));
+    excludes
+        .iter()
+        .map(String::as_str)
+        .filter(|term| !title.contains(&format!(" {} ", normalize_title(term))))
+        .collect()
+}
+
+fn mentions_any(candidate: &str, terms: &[&str]) -> bool {
+    let candidate = format!(" {} ", normalize_title(candidate));
+    terms
+        .iter()
+        .any(|term| candidate.contains(&format!(" {} ", normalize_title(term))))
+}
+
+// YouTube search honours "-term"; YouTube Music does not, so there only the
+// candidate ranking below applies.
+fn exclude_other_versions(mut terms: String, track: &Track, download: &ReleaseDownload) -> String {
+    if download.search_site != SearchSite::YouTube {
+        return terms;
+    }
+    for term in unwanted_versions(&track.title, download.version_excludes) {
+        if term.contains(' ') {
+            terms.push_str(&format!(" -\"{}\"", term));
+        } else {
+            terms.push_str(&format!(" -{}", term));
+        }
+    }
+    terms
+}
+
+// Titles are compared without bracketed parts, so "Battery (Live)" scores as
+// high as "Battery". With --prefer-album-version a confident match that is not
+// another version wins; otherwise the closest title does.
+fn best_album_track_candidate<'a>(
+    album: &Album,
+    track: &Track,
+    results: &'a [SearchResult],
+    download: &ReleaseDownload,
+) -> Option<(&'a SearchResult, f64)> {
+    let artist = track_artist(album, track);
+    let unwanted = unwanted_versions(&track.title, download.version_excludes);
+    if !unwanted.is_empty() {
+        let album_version = highest_score(
+            results
+                .iter()
+                .filter(|result| !mentions_any(&result.title, &unwanted))
+                .map(|result| {
+                    (
+                        result,
+                        track_match_score(artist, &track.title, &result.title),
+                    )
+                }),
+        )
+        .filter(|(_, score)| *score >= download.match_threshold);
+        if album_version.is_some() {
+            return album_version;
+        }
+    }
+    best_track_candidate(artist, &track.title, results)
+}
+
+fn best_track_candidate<'a>(
+    artist: &str,
+    title: &str,
+    results: &'a [SearchResult],
+) -> Option<(&'a SearchResult, f64)> {
+    highest_score(
+        results
+            .iter()
+            .map(|result| (result, track_match_score(artist, title, &result.title))),
+    )
+}
+
+// Ties keep the earlier, higher-ranked search result.
+fn highest_score<'a>(
+    scored: impl Iterator<Item = (&'a SearchResult, f64)>,
+) -> Option<(&'a SearchResult, f64)> {
+    scored.fold(None, |best, candidate| match best {
+        Some((_, best_score)) if best_score >= candidate.1 => best,
+        _ => Some(candidate),
+    })
+}
+
+// Jaro-Winkler similarity between the MusicBrainz title and the video title once
+// the artist name, bracketed suffixes and upload boilerplate are removed.
+fn track_match_score(artist: &str, title: &str, candidate: &str) -> f64 {
+    let expected = normalize_title(title);
+    let mut cleaned: Vec<String> = normalize_title(&strip_bracketed(candidate))
+        .split(' ')
+        .filter(|word| !VIDEO_TITLE_NOISE.contains(word))
+        .map(str::to_string)
+        .collect();
+
+    let artist_words: Vec<String> = normalize_title(artist)
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !artist_words.is_empty()
+        && !expected.contains(&artist_words.join(" "))
+        && let Some(start) = cleaned
+            .windows(artist_words.len())
+            .position(|window| window == artist_words.as_slice())
+    {
+        cleaned.drain(start..start + artist_words.len());
+    }
+
+    strsim::jaro_winkler(&expected, &cleaned.join(" "))
+}
+
+fn strip_bracketed(title: &str) -> String {
+    let mut depth = 0usize;
+    let mut stripped = String::with_capacity(title.len());
+    for ch in title.chars() {
+        match ch {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => stripped.push(ch),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+fn clean_album_titles(album: &mut Album, noise: Option<&[String]>) {
+    let Some(noise) = noise else {
+        return;
+    };
+    for track in &mut album.tracks {
+        let cleaned = clean_title(&track.title, noise);
+        if cleaned != track.title {
+            status!("cleaned title '{}' -> '{}'", track.title, cleaned);
+            track.title = cleaned;
+        }
+    }
+}
+
+// Only trailing bracketed groups go, and only when they name an edition
+// ("(2011 Remaster)", "[Deluxe Edition]"); "(Live)" or a subtitle stays. The
+// disambiguation comment is separate and unaffected.
+fn strip_album_suffixes(title: &str, suffixes: &[String]) -> String {
+    let terms: Vec<&str> = suffixes.iter().map(String::as_str).collect();
+    let mut title = title.trim_end();
+    while let Some(open) = match title.chars().last() {
+        Some(')') => title.rfind('('),
+        Some(']') => title.rfind('['),
+        _ => None,
+    } {
+        let rest = title[..open].trim_end();
+        if rest.is_empty() || !mentions_any(&title[open + 1..title.len() - 1], &terms) {
+            break;
+        }
+        title = rest;
+    }
+    title.to_string()
+}
+
+fn strip_album_title_suffixes(album: &mut Album, suffixes: Option<&[String]>) {
+    let Some(suffixes) = suffixes else {
+        return;
+    };
+    let stripped = strip_album_suffixes(&album.title, suffixes);
+    if stripped != album.title {
+        status!("using album title '{}' (was '{}')", stripped, album.title);
+        album.title = stripped;
+    }
+}
+
+fn recase_album_titles(album: &mut Album, case: TitleCase) {
+    for track in &mut album.tracks {
+        track.title = case.apply(&track.title);
+    }
+}
+
+// Capitalized words are read as acronyms ("BBC", "NASA's") unless the whole
+// title is upper-case; then only roman numerals and TITLE_ACRONYMS survive.
+fn title_case(title: &str) -> String {
+    let mut letters = title.chars().filter(|ch| ch.is_alphabetic()).peekable();
+    let shouting = letters.peek().is_some() && letters.all(char::is_uppercase);
+    let words: Vec<&str> = title.split(' ').collect();
+    let last = words.iter().rposition(|word| !word.is_empty());
+
+    let mut starts_phrase = true;
+    let mut cased = Vec::with_capacity(words.len());
+    for (index, word) in words.iter().enumerate() {
+        let capitalize = starts_phrase || Some(index) == last || word.starts_with(['(', '[', '"']);
+        let parts: Vec<String> = word
+            .split('-')
+            .enumerate()
+            .map(|(part_index, part)| {
+                title_case_word(part, capitalize && part_index == 0, shouting)
+            })
+            .collect();
+        cased.push(parts.join("-"));
+        if !word.is_empty() {
+            starts_phrase = word.ends_with([':', '.', '!', '?']) || matches!(*word, "-" | "–");
+        }
+    }
+    cased.join(" ")
+}
+
+fn title_case_word(word: &str, capitalize: bool, shouting: bool) -> String {
+    let core_start = word.find(char::is_alphanumeric);
+    let core_end = word.rfind(char::is_alphanumeric);
+    let (Some(start), Some(end)) = (core_start, core_end) else {
+        return word.to_string();
+    };
+    let end = end + word[end..].chars().next().map_or(1, char::len_utf8);
+    let (prefix, core, suffix) = (&word[..start], &word[start..end], &word[end..]);
+
+    let lower = core.to_lowercase();
+    let letters: Vec<char> = core.chars().filter(|ch| ch.is_alphabetic()).collect();
+    let all_upper = letters.iter().all(|ch| ch.is_uppercase());
+    let inner_upper = core.chars().skip(1).any(char::is_uppercase);
+    let cased =
+        if is_roman_numeral(&core.to_uppercase()) || TITLE_ACRONYMS.contains(&lower.as_str()) {
+            core.to_uppercase()
+        } else if !shouting && letters.len() > 1 && all_upper {
+            core.to_string()
+        } else if !shouting && inner_upper {
+            // Mixed case such as "McCartney" or "iPhone" is deliberate.
+            core.to_string()
+        } else if !capitalize && TITLE_SMALL_WORDS.contains(&lower.as_str()) {
+            lower
+        } else {
+            let mut chars = lower.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => lower,
+            }
+        };
+    format!("{}{}{}", prefix, cased, suffix)
+}
+
+// Only I, V and X up to 39: "MIX" or "DC" are far more often words than years.
+fn is_roman_numeral(word: &str) -> bool {
+    let units = word.trim_start_matches('X');
+    word.len() - units.len() <= 3
+        && !word.is_empty()
+        && ["", "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX"].contains(&units)
+}
+
+// Removes "(Official Audio)", "[HD]", " - Remastered 2011" and the like when the
+// group contains a noise term as whole words. A title that would end up empty
+// is returned unchanged.
+fn clean_title(title: &str, noise: &[String]) -> String {
+    let is_noise = |text: &str| {
+        let text = format!(" {} ", normalize_title(text));
+        noise.iter().any(|term| {
+            let term = normalize_title(term);
+            !term.is_empty() && text.contains(&format!(" {} ", term))
+        })
+    };
+
+    let mut kept = String::with_capacity(title.len());
+    let mut group = String::new();
+    let mut depth = 0usize;
+    for ch in title.chars() {
+        match ch {
+            '(' | '[' => {
+                depth += 1;
+                group.push(ch);
+            }
+            ')' | ']' if depth > 0 => {
+                depth -= 1;
+                group.push(ch);
+                if depth == 0 {
+                    if !is_noise(&group) {
+                        kept.push_str(&group);
+                    }
+                    group.clear();
+                }
+            }
+            _ if depth > 0 => group.push(ch),
+            _ => kept.push(ch),
+        }
+    }
+    kept.push_str(&group);
+
+    if let Some((head, tail)) = kept.rsplit_once(" - ")
+        && is_noise(tail)
+    {
+        kept.truncate(head.len());
+    }
+
+    let cleaned = kept.split_whitespace().collect::<Vec<_>>().join(" ");
+    if cleaned.is_empty() {
+        title.to_string()
+    } else {
+        cleaned
+    }
+}
+
+// Bracketed suffixes ("(Live)", "[Remastered]") and featured artists are dropped
+// so that versions of the same song compare equal.
+fn dedupe_title_key(title: &str) -> String {
+    normalize_title(&strip_bracketed(title))
+        .split(' ')
+        .take_while(|word| !matches!(*word, "feat" | "ft" | "featuring"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn validate_match_threshold(threshold: f64) -> Result<f64> {
+    if (0.0..=1.0).contains(&threshold) {
+        Ok(threshold)
+    } else {
+        Err(AppError::Message(format!(
+            "--match-threshold must be between 0 and 1, got {}",
+            threshold
+        )))
+    }
+}
+
+// `targets` holds one yt-dlp target per track; `None` tracks are skipped.
+fn download_album_tracks(
+    album: &Album,
+    targets: &[Option<String>],
+    download: &ReleaseDownload,
+) -> Result<Vec<PathBuf>> {
+    let ReleaseDownload {
+        destination,
+        formats,
+        options,
+        post_process,
+        ..
+    } = *download;
+    let album_dir = destination.join(download.file_naming.name(&album.title));
+    let simulate = options.simulate;
+    let destination = if download.nest {
+        if !simulate {
+            fs::create_dir_all(&album_dir)?;
+        }
+        album_dir.as_path()
+    } else {
+        destination
+    };
+    if let Some(min_free) = download.min_free_space {
+        let pending = album
+            .tracks
+            .iter()
+            .zip(targets)
+            .filter(|(_, target)| target.is_some())
+            .map(|(track, _)| track);
+        let estimate = PlaylistEstimate::from_tracks(pending);
+        check_free_space(destination, Some(&estimate), &formats[0], min_free)?;
+    }
+    let filename_length = filename_budget(destination, download.max_path_length)?;
+    status!(
+        "saving audio to {} as {}",
+        destination.display(),
+        describe_format_chain(formats)
+    );
+
+    // Durations come from MusicBrainz here, so the playlist duration filter does not apply.
+    let mut track_options = options.clone();
+    track_options.match_filter = None;
+
+    if download.restart {
+        discard_partial_downloads(destination, options.tmp_dir.as_deref())?;
+    }
+    let started = SystemTime::now();
+    let total_tracks = album.tracks.len();
+    let mut skipped = 0usize;
+    let mut downloaded = 0usize;
+    let mut duplicates = 0usize;
+    let mut produced = Vec::new();
+    // The track behind each produced file, for --write-m3u.
+    let mut produced_tracks = Vec::new();
+    let mut seen_titles: HashMap<String, &str> = HashMap::new();
+    for (index, (track, target)) in album.tracks.iter().zip(targets).enumerate() {
+        // Each track is its own yt-dlp run, so the cap is counted here.
+        if let Some(max) = options.max_downloads
+            && downloaded >= max as usize
+        {
+            status!("stopping after {} tracks (--max-downloads)", max);
+            break;
+        }
+        let progress = Tone::Info.paint(format!("[{}/{}]", index + 1, total_tracks));
+        let event = |name: &str| {
+            serde_json::json!({
+                "event": name,
+                "index": index + 1,
+                "total": total_tracks,
+                "title": track.title,
+            })
+        };
+        let Some(target) = target else {
+            skipped += 1;
+            let mut failed = event("track_failed");
+            failed["error"] = "no confident YouTube match".into();
+            emit_progress(download.progress_json, failed);
+            continue;
+        };
+        let dedupe_key = dedupe_title_key(&track.title);
+        if download.dedupe
+            && let Some(original) = seen_titles.get(&dedupe_key)
+        {
+            duplicates += 1;
+            status!(
+                "{} {}",
+                progress,
+                Tone::Warning.paint(format!(
+                    "skipping '{}', a duplicate of '{}'",
+                    track.title, original
+                ))
+            );
+            let mut duplicate = event("track_skipped");
+            duplicate["reason"] = format!("duplicate of '{}'", original).into();
+            emit_progress(download.progress_json, duplicate);
+            continue;
+        }
+        emit_progress(download.progress_json, event("track_start"));
+        status!(
+            "{} {} '{} - {}'",
+            progress,
+            if simulate {
+                "simulating"
+            } else {
+                "downloading"
+            },
+            track_artist(album, track),
+            track.title
+        );
+
+        let stem = download.album_template.track_stem(
+            album,
+            track,
+            download.tags.numbering_offset,
+            download.flat_numbering,
+            download.file_naming,
+            filename_length,
+        );
+        let output_template = track_output_template(destination, &stem);
+        let fetch = |target: &str| {
+            run_yt_dlp_with_fallback(formats, true, |format| {
+                let mut command = base_yt_dlp_command(format, &output_template, &track_options);
+                command.arg("--no-playlist");
+                command.arg(target);
+                command
+            })
+        };
+        let mut result = fetch(target);
+        if matches!(result, Err(AppError::YtDlpFailed(_))) {
+            let mut tried = vec![target.clone()];
+            for terms in track_search_variants(album, track) {
+                let Some(alternate) =
+                    find_alternate_track_url(album, track, &terms, &tried, download)?
+                else {
+                    continue;
+                };
+                status!(
+                    "{} {}",
+                    progress,
+                    Tone::Warning.paint(format!(
+                        "download failed; retrying with a search for '{}'",
+                        terms
+                    ))
+                );
+                result = fetch(&alternate);
+                if !matches!(result, Err(AppError::YtDlpFailed(_))) {
+                    break;
+                }
+                tried.push(alternate);
+            }
+        }
+        let result = result.and_then(|format| {
+            if !simulate {
+                tag_downloaded_track(album, track, &stem, download.tags, destination, &format)?;
+            }
+            Ok(format)
+        });
+        match result {
+            Ok(format) => {
+                downloaded += 1;
+                if !simulate && let Some(file) = find_track_file(destination, &stem, &format)? {
+                    produced.push(file);
+                    produced_tracks.push(track);
+                }
+                status!(
+                    "{} {}",
+                    progress,
+                    Tone::Success.paint(if simulate { "downloadable" } else { "done" })
+                );
+                seen_titles.entry(dedupe_key).or_insert(&track.title);
+                let mut done = event("track_done");
+                done["format"] = format.into();
+                emit_progress(download.progress_json, done);
+            }
+            Err(err) => {
+                let mut failed = event("track_failed");
+                failed["error"] = err.to_string().into();
+                emit_progress(download.progress_json, failed);
+                return Err(err);
+            }
+        }
+    }
+
+    if simulate {
+        status!(
+            "{}",
+            Tone::Success.paint(format!(
+                "simulated {} of {} tracks; nothing was written",
+                downloaded, total_tracks
+            ))
+        );
+    }
+    // Reported once everything else is done, so the matched tracks still get
+    // their post-processing, cover art and playlist.
+    let unmatched = (skipped > 0).then(|| {
+        AppError::Message(format!(
+            "skipped {} track{} without a confident YouTube match (lower --match-threshold to accept them)",
+            skipped,
+            if skipped == 1 { "" } else { "s" }
+        ))
+    });
+    if duplicates > 0 {
+        status!(
+            "{}",
+            Tone::Warning.paint(format!(
+                "skipped {} duplicate track{}",
+                duplicates,
+                if duplicates == 1 { "" } else { "s" }
+            ))
+        );
+    }
+    if simulate {
+        return unmatched.map_or(Ok(produced), Err);
+    }
+    if options.lyrics_from_subs {
+        write_lyrics_from_subtitles(&produced)?;
+    }
+    if post_process.is_active() {
+        post_process_audio(&produced, post_process)?;
+    }
+    emit_progress(
+        download.progress_json,
+        serde_json::json!({
+            "event": "album_done",
+            "artist": album.artist,
+            "album": album.title,
+            "total": total_tracks,
+            "downloaded": downloaded,
+            "skipped": skipped,
+            "duplicates": duplicates,
+        }),
+    );
+    if download.summary {
+        DownloadSummary::from_files(destination, &produced, started)?
+            .report(download.progress_json);
+    }
+    let mut covers = Vec::new();
+    if let Some(name) = download.cover_name
+        && !produced.is_empty()
+    {
+        covers.extend(save_album_cover(
+            album,
+            targets,
+            destination,
+            name,
+            download,
+        )?);
+    }
+    if download.write_m3u {
+        let entries: Vec<(&Track, &Path)> = produced_tracks
+            .iter()
+            .copied()
+            .zip(produced.iter().map(PathBuf::as_path))
+            .collect();
+        write_album_m3u(album, &entries, destination, download.file_naming)?;
+    }
+    if let Some(zip) = download.zip {
+        // Tracks skipped by --dedupe are deliberate, so they do not count as missing.
+        zip.ensure_complete(downloaded + duplicates, total_tracks)?;
+        zip.write(download.destination, &produced, &covers)?;
+    }
+    unmatched.map_or(Ok(produced), Err)
+}
+
+fn validate_cover_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    let is_jpeg = ["jpg", "jpeg"].contains(&lowercase_extension(Path::new(name)).as_str());
+    if is_jpeg && !name.contains(['/', '\\']) && Path::new(name).file_stem().is_some() {
+        Ok(name.to_string())
+    } else {
+        Err(AppError::Message(format!(
+            "--cover-name must be a .jpg file name such as cover.jpg or folder.jpg, got '{name}'"
+        )))
+    }
+}
+
+// Prefers the release's front cover from the metadata source, then the first
+// track's YouTube thumbnail. An existing cover file is left alone.
+fn save_album_cover(
+    album: &Album,
+    targets: &[Option<String>],
+    dir: &Path,
+    name: &str,
+    download: &ReleaseDownload,
+) -> Result<Option<PathBuf>> {
+    let path = dir.join(name);
+    if path.exists() {
+        return Ok(None);
+    }
+    if let Some(url) = &album.cover_url {
+        match fetch_cover_art(url, download.musicbrainz) {
+            Ok(Some(image)) => {
+                fs::write(&path, image)?;
+                status!("saved the album cover as {}", path.display());
+                return Ok(Some(path));
+            }
+            Ok(None) => status!("the release has no front cover; using a YouTube thumbnail"),
+            Err(err) => status!(
+                "could not fetch the album cover ({}); using a YouTube thumbnail",
+                err
+            ),
+        }
+    }
+    let Some(target) = targets.iter().flatten().next() else {
+        return Ok(None);
+    };
+    let mut source = Command::new("yt-dlp");
+    source.arg("--no-playlist").arg(target);
+    save_thumbnail_cover(source, dir, name, download.options)
+}
+
+// Cover Art Archive answers 404 for releases without a front image.
+fn fetch_cover_art(url: &str, musicbrainz: &MusicBrainzSettings) -> Result<Option<Vec<u8>>> {
+    if musicbrainz.offline {
+        return Ok(None);
+    }
+    let response = Client::builder()
+        .user_agent(musicbrainz.user_agent.as_str())
+        .timeout(Duration::from_secs(musicbrainz.timeout_secs))
+        .build()?
+        .get(url)
+        .send()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.bytes()?.to_vec()))
+}
+
+// `source` is a yt-dlp command that already names the video or playlist item.
+// Returns the cover only when this call wrote it.
+fn save_thumbnail_cover(
+    mut source: Command,
+    dir: &Path,
+    name: &str,
+    options: &YtDlpOptions,
+) -> Result<Option<PathBuf>> {
+    let path = dir.join(name);
+    if path.exists() {
+        return Ok(None);
+    }
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    source
+        .arg("--skip-download")
+        .arg("--write-thumbnail")
+        .arg("--convert-thumbnails")
+        .arg("jpg")
+        .arg("--quiet")
+        .arg("--no-warnings")
+        .arg("--output")
+        .arg(format!(
+            "thumbnail:{}",
+            dir.join(format!("{stem}.%(ext)s")).display()
+        ));
+    apply_access_options(&mut source, options);
+    source.stdin(Stdio::null());
+    run_yt_dlp(source)?;
+
+    // yt-dlp always names the converted thumbnail "<stem>.jpg".
+    let written = dir.join(format!("{stem}.jpg"));
+    if written != path && written.is_file() {
+        fs::rename(&written, &path)?;
+    }
+    if !path.is_file() {
+        return Ok(None);
+    }
+    status!("saved the first track's thumbnail as {}", path.display());
+    Ok(Some(path))
+}
+
+// --progress-json writes one JSON object per line to stdout for GUI front ends.
+fn emit_progress(enabled: bool, event: serde_json::Value) {
+    if enabled {
+        println!("{}", event);
+    }
+}
+
+struct MusicBrainzClient {
+    client: Client,
+    base_url: String,
+    timeout_secs: u64,
+    token: Option<String>,
+    locale: Option<String>,
+    min_interval: Duration,
+}
+
+// Every MusicBrainz request in the process waits its turn here, whichever
+// client or thread sends it; yt-dlp subprocesses never go through it.
+static MUSICBRAINZ_LIMITER: RateLimiter<SystemClock> = RateLimiter::new(SystemClock);
+
+trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+// The lock is held while sleeping, so concurrent callers queue up and each gets
+// its own slot instead of all waking at the same moment.
+struct RateLimiter<C: Clock> {
+    clock: C,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl<C: Clock> RateLimiter<C> {
+    const fn new(clock: C) -> Self {
+        Self {
+            clock,
+            last_request: Mutex::new(None),
+        }
+    }
+
+    fn wait_for_turn(&self, min_interval: Duration) {
+        let mut last = self
+            .last_request
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(previous) = *last {
+            let ready = previous + min_interval;
+            let now = self.clock.now();
+            if ready > now {
+                self.clock.sleep(ready - now);
+            }
+        }
+        *last = Some(self.clock.now());
+    }
+}
+
+// MusicBrainz options after applying command-line overrides to the config.
+#[derive(Debug)]
+struct MusicBrainzSettings {
+    offline: bool,
+    user_agent: String,
+    timeout_secs: u64,
+    base_url: String,
+    token: Option<String>,
+    locale: Option<String>,
+}
+
+impl MusicBrainzSettings {
+    fn resolve(cli: &Cli, config: &MusicBrainzConfig) -> Result<Self> {
+        let user_agent = match cli.user_agent.as_ref().or(config.user_agent.as_ref()) {
+            Some(user_agent) => validate_user_agent(user_agent)?,
+            None => default_user_agent(),
+        };
+        let timeout_secs = match cli.mb_timeout.or(config.timeout_secs) {
+            Some(secs) => validate_mb_timeout(secs)?,
+            None => DEFAULT_MUSICBRAINZ_TIMEOUT_SECS,
+        };
+        let base_url = match cli.mb_url.as_ref().or(config.base_url.as_ref()) {
+            Some(url) => validate_mb_url(url)?,
+            None => MUSICBRAINZ_BASE_URL.to_string(),
+        };
+        let token = config.token.as_deref().map(validate_mb_token).transpose()?;
+        let locale = cli
+            .mb_locale
+            .as_deref()
+            .or(config.locale.as_deref())
+            .map(validate_mb_locale)
+            .transpose()?;
+        Ok(Self {
+            offline: cli.offline,
+            user_agent,
+            timeout_secs,
+            base_url,
+            token,
+            locale,
+        })
+    }
+}
+
+// MusicBrainz asks clients to identify themselves with a name, version and contact.
+fn default_user_agent() -> String {
+    format!(
+        "{}/{} ( {} )",
+        APP_NAME,
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_REPOSITORY")
+    )
+}
+
+fn validate_user_agent(user_agent: &str) -> Result<String> {
+    let user_agent = user_agent.trim();
+    if user_agent.is_empty() || reqwest::header::HeaderValue::from_str(user_agent).is_err() {
+        return Err(AppError::Message(format!(
+            "'{}' is not a usable user agent; use something like \"{}\"",
+            user_agent,
+            default_user_agent()
+        )));
+    }
+    Ok(user_agent.to_string())
+}
+
+fn validate_mb_timeout(secs: u64) -> Result<u64> {
+    if secs == 0 {
+        return Err(AppError::Message(
+            "the MusicBrainz timeout must be at least 1 second".to_string(),
+        ));
+    }
+    Ok(secs)
+}
+
+// Mirrors are addressed by their web service root, e.g. http://localhost:5000/ws/2.
+fn validate_mb_url(url: &str) -> Result<String> {
+    let trimmed = url.trim().trim_end_matches('/');
+    match reqwest::Url::parse(trimmed) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => {
+            Ok(trimmed.to_string())
+        }
+        _ => Err(AppError::Message(format!(
+            "'{}' is not an http(s) URL; use something like {}",
+            url, MUSICBRAINZ_BASE_URL
+        ))),
+    }
+}
+
+// Locales as MusicBrainz writes them on aliases: "ja", "en_US".
+fn validate_mb_locale(locale: &str) -> Result<String> {
+    let locale = locale.trim().replace('-', "_");
+    let (language, region) = locale.split_once('_').unwrap_or((&locale, ""));
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && region.chars().all(|c| c.is_ascii_alphanumeric());
+    if !valid || locale.ends_with('_') {
+        return Err(AppError::Message(format!(
+            "'{}' is not a locale; use a language code like ja or en_US",
+            locale
+        )));
+    }
+    Ok(locale)
+}
+
+fn validate_mb_token(token: &str) -> Result<String> {
+    let token = token.trim();
+    if token.is_empty() || reqwest::header::HeaderValue::from_str(token).is_err() {
+        return Err(AppError::Message(
+            "the MusicBrainz token must be non-empty printable text".to_string(),
+        ));
+    }
+    Ok(token.to_string())
+}
+
+impl MusicBrainzClient {
+    fn new(settings: &MusicBrainzSettings) -> Result<Self> {
+        if settings.offline {
+            return Err(AppError::Message(
+                "MusicBrainz lookups are disabled by --offline".to_string(),
+            ));
+        }
+        let client = Client::builder()
+            .user_agent(settings.user_agent.as_str())
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .build()?;
+        let min_interval = if settings.token.is_some() {
+            MUSICBRAINZ_AUTHENTICATED_INTERVAL
+        } else {
+            MUSICBRAINZ_REQUEST_INTERVAL
+        };
+        Ok(Self {
+            client,
+            base_url: settings.base_url.clone(),
+            timeout_secs: settings.timeout_secs,
+            token: settings.token.clone(),
+            locale: settings.locale.clone(),
+            min_interval,
+        })
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let timed_out = |err: reqwest::Error| {
+            if err.is_timeout() {
+                AppError::MusicBrainzTimeout(self.timeout_secs)
+            } else if err.is_decode() {
+                AppError::MusicBrainzResponse(err.to_string())
+            } else {
+                AppError::Http(err)
+            }
+        };
+        MUSICBRAINZ_LIMITER.wait_for_turn(self.min_interval);
+        let mut request = self.client.get(url).header("Accept", "application/json");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json())
+            .map_err(timed_out)
+    }
+
+    fn search_releases(&self, query: &str, limit: usize) -> Result<Vec<MbReleaseSearchEntry>> {
+        let releases = self.run_release_search(&build_musicbrainz_search_query(query), limit)?;
+        if !releases.is_empty() {
+            return Ok(releases);
+        }
+        // Release searches only see the credited artist name, so a romanized
+        // "Artist - Album" misses native-script credits. Artist searches also
+        // match aliases and transliterations, which gives the artist IDs to retry with.
+        let Some((artist, album)) = split_artist_album(query) else {
+            return Ok(releases);
+        };
+        let artist_ids = self.search_artist_ids(&artist)?;
+        if artist_ids.is_empty() {
+            return Ok(releases);
+        }
+        status!(
+            "no release credited to '{}'; retrying with the artist's MusicBrainz aliases",
+            artist
+        );
+        self.run_release_search(&build_musicbrainz_alias_query(&album, &artist_ids), limit)
+    }
+
+    fn run_release_search(
+        &self,
+        search_query: &str,
+        limit: usize,
+    ) -> Result<Vec<MbReleaseSearchEntry>> {
+        let search_url = format!(
+            "{}/release/?query={}&fmt=json&limit={}",
+            self.base_url,
+            encode(search_query),
+            limit
+        );
+
+        let search_response: MbReleaseSearchResponse = self.get_json(&search_url)?;
+        Ok(search_response.releases)
+    }
+
+    fn search_artist_ids(&self, artist: &str) -> Result<Vec<String>> {
+        let artist = escape_musicbrainz_query(artist);
+        let search_query = format!(
+            "artist:\"{0}\" OR alias:\"{0}\" OR sortname:\"{0}\"",
+            artist
+        );
+        let search_url = format!(
+            "{}/artist/?query={}&fmt=json&limit={}",
+            self.base_url,
+            encode(&search_query),
+            ARTIST_ALIAS_CANDIDATES
+        );
+
+        let search_response: MbArtistSearchResponse = self.get_json(&search_url)?;
+        Ok(search_response
+            .artists
+            .into_iter()
+            .filter(|artist| artist.score.unwrap_or(0) >= ARTIST_ALIAS_MIN_SCORE)
+            .map(|artist| artist.id)
+            .collect())
+    }
+
+    fn fetch_release(&self, release_id: &str) -> Result<Album> {
+        convert_release_detail(self.fetch_release_detail(release_id)?)
+    }
+
+    fn fetch_release_detail(&self, release_id: &str) -> Result<MbReleaseDetail> {
+        // Artist aliases are only needed to pick names in the preferred locale.
+        let includes = if self.locale.is_some() {
+            "recordings+artist-credits+release-groups+aliases"
+        } else {
+            "recordings+artist-credits+release-groups"
+        };
+        let detail_url = format!(
+            "{}/release/{}?inc={}&fmt=json",
+            self.base_url, release_id, includes
+        );
+
+        let mut detail: MbReleaseDetail = self.get_json(&detail_url)?;
+        if let Some(locale) = &self.locale {
+            localize_artist_credits(&mut detail, locale);
+        }
+        Ok(detail)
+    }
+}
+
+// Among the top-scored releases, one without a disambiguation comment is
+// usually the standard edition rather than a "deluxe" or "explicit" one.
+fn best_release_candidate(candidates: &[MbReleaseSearchEntry]) -> Option<&MbReleaseSearchEntry> {
+    let top_score = candidates.first()?.score;
+    candidates
+        .iter()
+        .take_while(|candidate| candidate.score == top_score)
+        .find(|candidate| candidate.disambiguation().is_none())
+        .or(candidates.first())
+}
+
+// Digital and placeholder releases can come back without any media, so the other
+// candidates are tried in turn; only a list of nothing but empty releases fails.
+fn first_release_with_tracks(
+    best: &MbReleaseSearchEntry,
+    candidates: &[MbReleaseSearchEntry],
+    mut fetch: impl FnMut(&str) -> Result<Album>,
+) -> Result<Album> {
+    let ordered = std::iter::once(best).chain(
+        candidates
+            .iter()
+            .filter(|candidate| candidate.id != best.id),
+    );
+    let mut last_empty = None;
+    for candidate in ordered {
+        let result = if candidate.track_count == Some(0) {
+            Err(AppError::EmptyRelease(
+                candidate.title.clone().unwrap_or_default(),
+            ))
+        } else {
+            fetch(&candidate.id)
+        };
+        match result {
+            Ok(album) => {
+                if let Some(warning) = describe_uncertain_match(candidate) {
+                    status!("{}", Tone::Warning.paint(warning));
+                }
+                return Ok(album);
+            }
+            Err(err @ AppError::EmptyRelease(_)) => {
+                status!(
+                    "skipping {}: it has no tracks",
+                    describe_release_candidate(candidate)
+                );
+                last_empty = Some(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_empty.unwrap_or_else(|| AppError::EmptyRelease(best.id.clone())))
+}
+
+// A source of album metadata (title, artists, dates, track list) for album
+// downloads. Backends map their own responses onto `Album` and `Track`, so the
+// download loop does not depend on any one of them.
+trait MetadataResolver {
+    fn name(&self) -> &'static str;
+
+    fn find_album(&self, query: &str) -> Result<Option<Album>>;
+
+    // For --write-tags-only: the release the best matching recording appears on,
+    // holding just that track.
+    fn find_recording(&self, query: &str) -> Result<Option<Album>>;
+
+    // For --choose; backends without a candidate list use their best match.
+    fn choose_album(&self, query: &str) -> Result<Option<Album>> {
+        self.find_album(query)
+    }
+}
+
+impl MetadataResolver for MusicBrainzClient {
+    fn name(&self) -> &'static str {
+        "MusicBrainz"
+    }
+
+    fn find_album(&self, query: &str) -> Result<Option<Album>> {
+        let candidates = self.search_releases(query, RELEASE_CANDIDATES)?;
+        let Some(release) = best_release_candidate(&candidates) else {
+            return Ok(None);
+        };
+
+        first_release_with_tracks(release, &candidates, |id| self.fetch_release(id)).map(Some)
+    }
+
+    fn choose_album(&self, query: &str) -> Result<Option<Album>> {
+        choose_release(self, query)
+    }
+
+    fn find_recording(&self, query: &str) -> Result<Option<Album>> {
+        let search_url = format!(
+            "{}/recording/?query={}&fmt=json&limit={}",
+            self.base_url,
+            encode(&build_musicbrainz_recording_query(query)),
+            RECORDING_CANDIDATES
+        );
+        let search_response: MbRecordingSearchResponse = self.get_json(&search_url)?;
+        // Results come best first; recordings on no release have nothing to tag with.
+        let Some((recording, release)) = search_response
+            .recordings
+            .iter()
+            .find_map(|recording| Some((recording, pick_recording_release(&recording.releases)?)))
+        else {
+            return Ok(None);
+        };
+        if let Some(score) = recording
+            .score
+            .filter(|&score| score < UNCERTAIN_MATCH_SCORE)
+        {
+            status!(
+                "{}",
+                Tone::Warning.paint(format!(
+                    "uncertain match (score {}); check the tags before relying on them",
+                    score
+                ))
+            );
+        }
+
+        let detail = self.fetch_release_detail(&release.id)?;
+        let index = detail
+            .media
+            .iter()
+            .flat_map(|medium| &medium.tracks)
+            .position(|track| {
+                track
+                    .recording
+                    .as_ref()
+                    .is_some_and(|found| found.id.as_deref() == Some(recording.id.as_str()))
+            });
+        let mut album = convert_release_detail(detail)?;
+        let Some(index) = index else {
+            return Err(AppError::Message(format!(
+                "MusicBrainz release {} does not list recording {}",
+                release.id, recording.id
+            )));
+        };
+        let track = album.tracks.swap_remove(index);
+        album.tracks = vec![track];
+        Ok(Some(album))
+    }
+}
+
+fn build_musicbrainz_recording_query(raw: &str) -> String {
+    if let Some((artist, title)) = split_artist_album(raw) {
+        format!(
+            "recording:\"{}\" AND artist:\"{}\"",
+            escape_musicbrainz_query(&title),
+            escape_musicbrainz_query(&artist)
+        )
+    } else {
+        raw.to_string()
+    }
+}
+
+// A recording shows up on singles, compilations and reissues alike; the
+// earliest official album is the one its tags should name.
+fn pick_recording_release(releases: &[MbRecordingRelease]) -> Option<&MbRecordingRelease> {
+    releases.iter().min_by_key(|release| {
+        let official = release
+            .status
+            .as_deref()
+            .is_some_and(|status| status.eq_ignore_ascii_case("official"));
+        let album = release
+            .release_group
+            .as_ref()
+            .and_then(|group| group.primary_type.as_deref())
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("album"));
+        let rank = match (official, album) {
+            (true, true) => 0,
+            (true, false) => 1,
+            _ => 2,
+        };
+        let date = non_empty(release.date.as_deref());
+        (rank, date.is_none(), date)
+    })
+}
+
+fn build_musicbrainz_search_query(raw: &str) -> String {
+    if let Some((artist, album)) = split_artist_album(raw) {
+        format!(
+            "release:\"{}\" AND artist:\"{}\"",
+            escape_musicbrainz_query(&album),
+            escape_musicbrainz_query(&artist)
+        )
+    } else {
+        raw.to_string()
+    }
+}
+
+fn build_musicbrainz_alias_query(album: &str, artist_ids: &[String]) -> String {
+    let album = escape_musicbrainz_query(album);
+    let artists = artist_ids
+        .iter()
+        .map(|id| format!("arid:{}", id))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    format!(
+        "(release:\"{0}\" OR alias:\"{0}\") AND ({1})",
+        album, artists
+    )
+}
+
+// Replaces credited names with the artist's alias for `locale` (the primary
+// one if several match), leaving artists without such an alias alone.
+fn localize_artist_credits(detail: &mut MbReleaseDetail, locale: &str) {
+    let track_credits = detail
+        .media
+        .iter_mut()
+        .flat_map(|medium| medium.tracks.iter_mut())
+        .flat_map(|track| {
+            let recording = track
+                .recording
+                .iter_mut()
+                .flat_map(|recording| recording.artist_credit.iter_mut());
+            track.artist_credit.iter_mut().chain(recording)
+        });
+    for credit in detail.artist_credit.iter_mut().chain(track_credits) {
+        let alias = credit
+            .artist
+            .as_ref()
+            .and_then(|artist| locale_alias(&artist.aliases, locale))
+            .map(str::to_string);
+        if let Some(alias) = alias {
+            credit.name = Some(alias);
+        }
+    }
+}
+
+fn locale_alias<'a>(aliases: &'a [MbAlias], locale: &str) -> Option<&'a str> {
+    let matches_locale = |alias: &&MbAlias| {
+        alias.locale.as_deref().is_some_and(|alias_locale| {
+            alias_locale.eq_ignore_ascii_case(locale)
+                || alias_locale
+                    .split('_')
+                    .next()
+                    .is_some_and(|language| language.eq_ignore_ascii_case(locale))
+        })
+    };
+    let mut candidates = aliases.iter().filter(matches_locale);
+    let first = candidates.clone().next();
+    candidates
+        .find(|alias| alias.primary == Some(true))
+        .or(first)
+        .and_then(|alias| non_empty(alias.name.as_deref()))
+}
+
+fn split_artist_album(raw: &str) -> Option<(String, String)> {
+    for delimiter in ['-', '\u{2013}', '\u{2014}'] {
+        if let Some((artist, album)) = raw.split_once(delimiter) {
+            let artist = artist.trim();
+            let album = album.trim();
+            if !artist.is_empty() && !album.is_empty() {
+                return Some((artist.to_string(), album.to_string()));
+            }
+        }
+    }
+    None
+}
+
+// Accepts a bare release MBID or a musicbrainz.org release URL.
+fn parse_release_mbid(input: &str) -> Option<String> {
+    let input = input.trim().trim_end_matches('/');
+    let candidate = match input.rsplit_once("/release/") {
+        Some((_, rest)) => rest.split(['/', '?', '#']).next().unwrap_or_default(),
+        None => input,
+    };
+    let is_uuid = candidate.len() == 36
+        && candidate.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    is_uuid.then(|| candidate.to_ascii_lowercase())
+}
+
+fn escape_musicbrainz_query(value: &str) -> String {
+    value.replace('"', "\\\"")
+}
+
+fn convert_release_detail(detail: MbReleaseDetail) -> Result<Album> {
+    let MbReleaseDetail {
+        id,
+        title,
+        date,
+        disambiguation,
+        artist_credit,
+        media,
+        release_group,
+    } = detail;
+
+    let album_title = title.unwrap_or_else(|| "Unknown Release".to_string());
+    let artist = {
+        let formatted = format_artist_credit(&artist_credit);
+        if formatted.is_empty() {
+            "Unknown Artist".to_string()
+        } else {
+            formatted
+        }
+    };
+
+    let mut tracks = Vec::new();
+    let mut discs_with_tracks = 0u32;
+
+    for (medium_index, medium) in media.into_iter().enumerate() {
+        if medium.tracks.is_empty() {
+            continue;
+        }
+        discs_with_tracks += 1;
+        let disc_number = medium.position.unwrap_or((medium_index + 1) as u32);
+        for (index_on_disc, track) in medium.tracks.into_iter().enumerate() {
+            let recording = track.recording.unwrap_or_default();
+            let artist = [&track.artist_credit, &recording.artist_credit]
+                .into_iter()
+                .map(|credits| format_artist_credit(credits))
+                .find(|artist| !artist.is_empty());
+            let title = track
+                .title
+                .or(recording.title)
+                .unwrap_or_else(|| format!("Track {}", index_on_disc + 1));
+            let duration = track
+                .length
+                .or(recording.length)
+                .map(|ms| ms as f64 / 1000.0);
+            let position = track
+                .position
+                .or_else(|| track.number.and_then(|num| num.parse::<u32>().ok()))
+                .unwrap_or((index_on_disc + 1) as u32);
+            let overall_index = tracks.len() + 1;
+            tracks.push(Track {
+                title,
+                artist,
+                disc: disc_number,
+                position,
+                overall_index,
+                duration,
+            });
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(AppError::EmptyRelease(album_title));
+    }
+
+    let total_discs = if discs_with_tracks == 0 {
+        1
+    } else {
+        discs_with_tracks
+    };
+
+    Ok(Album {
+        title: album_title,
+        artist,
+        release_date: date,
+        original_date: release_group
+            .and_then(|group| non_empty(group.first_release_date.as_deref()).map(str::to_string)),
+        disambiguation: non_empty(disambiguation.as_deref()).map(str::to_string),
+        cover_url: id
+            .as_ref()
+            .map(|id| format!("{COVER_ART_ARCHIVE_URL}/release/{id}/front-500")),
+        source_url: id.map(|id| format!("{MUSICBRAINZ_RELEASE_URL}/{id}")),
+        total_discs,
+        track_total: tracks.len(),
+        tracks,
+    })
+}
+
+fn format_artist_credit(credits: &[MbArtistCredit]) -> String {
+    if credits.is_empty() {
+        return String::new();
+    }
+
+    let mut composed = String::new();
+    for credit in credits {
+        if let Some(name) = credit.name.as_deref().or_else(|| {
+            credit
+                .artist
+                .as_ref()
+                .and_then(|artist| artist.name.as_deref())
+        }) {
+            composed.push_str(name);
+        }
+        if let Some(join) = credit.joinphrase.as_deref() {
+            composed.push_str(join);
+        }
+    }
+
+    if composed.is_empty() {
+        credits
+            .iter()
+            .filter_map(|credit| {
+                credit
+                    .artist
+                    .as_ref()
+                    .and_then(|artist| artist.name.clone())
+            })
+            .collect::<Vec<_>>()
+            .join(" & ")
+    } else {
+        composed
+    }
+}
+
+// How many characters a file name in `directory` may have, without its
+// extension, for the whole path to stay within `max_path_length`. A directory
+// too deep for that still gets MIN_FILENAME_LENGTH rather than an error.
+fn filename_budget(directory: &Path, max_path_length: usize) -> Result<usize> {
+    let directory_length = std::path::absolute(directory)?
+        .to_string_lossy()
+        .chars()
+        .count();
+    match max_path_length.checked_sub(directory_length + 1 + MAX_EXTENSION_LENGTH) {
+        Some(budget) if budget >= MIN_FILENAME_LENGTH => Ok(budget),
+        _ => {
+            status!(
+                "{}",
+                Tone::Warning.paint(format!(
+                    "{} is too deep for --limit-filename-length {}; file names are cut to {} characters",
+                    directory.display(),
+                    max_path_length,
+                    MIN_FILENAME_LENGTH
+                ))
+            );
+            Ok(MIN_FILENAME_LENGTH)
+        }
+    }
+}
+
+fn truncate_stem(stem: &str, max_length: usize) -> String {
+    if stem.chars().count() <= max_length {
+        return stem.to_string();
+    }
+    let truncated: String = stem.chars().take(max_length).collect();
+    truncated.trim_end_matches([' ', '.']).to_string()
+}
+
+fn track_output_template(destination: &Path, stem: &str) -> String {
+    // yt-dlp treats '%' as the start of a template field.
+    let file_name = format!("{}.%(ext)s", stem.replace('%', "%%"));
+    destination.join(file_name).to_string_lossy().to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TemplateField {
+    Title,
+    Artist,
+    Album,
+    Track,
+    Disc,
+}
+
+impl TemplateField {
+    const ALL: [TemplateField; 5] = [
+        TemplateField::Title,
+        TemplateField::Artist,
+        TemplateField::Album,
+        TemplateField::Track,
+        TemplateField::Disc,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            TemplateField::Title => "title",
+            TemplateField::Artist => "artist",
+            TemplateField::Album => "album",
+            TemplateField::Track => "track",
+            TemplateField::Disc => "disc",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Field(TemplateField),
+}
+
+// A file name template such as "{track} - {title}". MusicBrainz downloads fill
+// the fields from the release; everything else maps them to yt-dlp fields.
+#[derive(Debug, PartialEq)]
+struct OutputTemplate(Vec<TemplatePart>);
+
+impl OutputTemplate {
+    fn parse(template: &str) -> Result<Self> {
+        let invalid = |detail: String| {
+            AppError::Message(format!(
+                "invalid output template '{}': {}",
+                template, detail
+            ))
+        };
+        if template.trim().is_empty() {
+            return Err(invalid("it is empty".to_string()));
+        }
+        if template.contains(['/', '\\']) {
+            return Err(invalid(
+                "it names files, not folders; use --nest for an album folder".to_string(),
+            ));
+        }
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                return Err(invalid("'{' is never closed".to_string()));
+            };
+            let name = &rest[start + 1..start + len];
+            let Some(field) = TemplateField::ALL
+                .into_iter()
+                .find(|field| field.name() == name)
+            else {
+                let known: Vec<String> = TemplateField::ALL
+                    .iter()
+                    .map(|field| format!("{{{}}}", field.name()))
+                    .collect();
+                return Err(invalid(format!(
+                    "unknown placeholder '{{{}}}' (available: {})",
+                    name,
+                    known.join(", ")
+                )));
+            };
+            parts.push(TemplatePart::Field(field));
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        Ok(Self(parts))
+    }
+
+    // Multi-disc releases number tracks as "DD-TT" so the discs sort apart,
+    // unless `flat_numbering` asks for one 1..N run across the discs. A stem
+    // longer than `max_length` loses the end of its title first, so the track
+    // number and other fields survive; only a template without room for that
+    // is cut from the end as a whole.
+    fn track_stem(
+        &self,
+        album: &Album,
+        track: &Track,
+        numbering_offset: usize,
+        flat_numbering: bool,
+        naming: FileNaming,
+        max_length: usize,
+    ) -> String {
+        let render = |title: &str| -> String {
+            let stem: String = self
+                .0
+                .iter()
+                .map(|part| match part {
+                    TemplatePart::Text(text) => text.clone(),
+                    TemplatePart::Field(TemplateField::Title) => title.to_string(),
+                    TemplatePart::Field(TemplateField::Artist) => {
+                        naming.name(track_artist(album, track))
+                    }
+                    TemplatePart::Field(TemplateField::Album) => naming.name(&album.title),
+                    TemplatePart::Field(TemplateField::Track)
+                        if album.total_discs > 1 && !flat_numbering =>
+                    {
+                        format!("{:02}-{:02}", track.disc, track.position)
+                    }
+                    TemplatePart::Field(TemplateField::Track) => {
+                        format!("{:02}", track.overall_index + numbering_offset)
+                    }
+                    TemplatePart::Field(TemplateField::Disc) => track.disc.to_string(),
+                })
+                .collect();
+            naming.name(&stem)
+        };
+        let title = naming.name(&track.title);
+        let stem = render(&title);
+        let overflow = stem.chars().count().saturating_sub(max_length);
+        if overflow == 0 {
+            return stem;
+        }
+        let title_length = title.chars().count();
+        if self.0.contains(&TemplatePart::Field(TemplateField::Title)) && overflow < title_length {
+            let shortened = render(&truncate_stem(&title, title_length - overflow));
+            if shortened.chars().count() <= max_length {
+                return shortened;
+            }
+        }
+        truncate_stem(&stem, max_length)
+    }
+
+    // `track` is the zero-padded number or yt-dlp field for {track}; plain
+    // single downloads have none.
+    fn ytdlp_file_name(&self, track: Option<&str>) -> Result<String> {
+        let mut name = String::new();
+        for part in &self.0 {
+            match part {
+                TemplatePart::Text(text) => name.push_str(&text.replace('%', "%%")),
+                TemplatePart::Field(TemplateField::Title) => name.push_str("%(title)s"),
+                TemplatePart::Field(TemplateField::Artist) => {
+                    name.push_str("%(artist,uploader|Unknown Artist)s")
+                }
+                TemplatePart::Field(TemplateField::Album) => {
+                    name.push_str("%(album,playlist_title|Unknown Album)s")
+                }
+                TemplatePart::Field(TemplateField::Track) => match track {
+                    Some(track) => name.push_str(track),
+                    None => {
+                        return Err(AppError::Message(
+                            "the output template uses {track}, which needs --track-number for a single download"
+                                .to_string(),
+                        ));
+                    }
+                },
+                TemplatePart::Field(TemplateField::Disc) => name.push_str("%(disc_number|1)s"),
+            }
+        }
+        name.push_str(".%(ext)s");
+        Ok(name)
+    }
+}
+
+// Tags are written by our own ffmpeg pass with one argument per value, so titles
+// with quotes, ampersands or slashes never go through yt-dlp's shell-style
+// splitting of --postprocessor-args.
+fn tag_downloaded_track(
+    album: &Album,
+    track: &Track,
+    stem: &str,
+    options: TagOptions,
+    destination: &Path,
+    format: &str,
+) -> Result<()> {
+    let Some(file) = find_track_file(destination, stem, format)? else {
+        status!(
+            "could not find the file for '{}'; tags were not written",
+            stem
+        );
+        return Ok(());
+    };
+    let tags = track_metadata_tags(
+        album,
+        track,
+        album.track_total,
+        &lowercase_extension(&file),
+        options,
+    );
+    write_tags(&file, &tags)
+}
+
+fn find_track_file(destination: &Path, stem: &str, format: &str) -> Result<Option<PathBuf>> {
+    if let Some(ext) = audio_extension_for_format(format) {
+        let path = destination.join(format!("{}.{}", stem, ext));
+        return Ok(path.is_file().then_some(path));
+    }
+    for entry in fs::read_dir(destination)? {
+        let path = entry?.path();
+        let matches_stem = path.file_stem().is_some_and(|name| name == stem);
+        if matches_stem && AUDIO_EXTENSIONS.contains(&lowercase_extension(&path).as_str()) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TagStyle {
+    // ID3 (mp3) and MP4 atoms: "n/total" numbering in a single field.
+    Id3,
+    // Vorbis comments (opus, ogg, flac): upper-case keys with separate totals.
+    Vorbis,
+}
+
+impl TagStyle {
+    fn for_format(format: &str) -> Self {
+        match format.to_ascii_lowercase().as_str() {
+            "opus" | "ogg" | "vorbis" | "flac" => TagStyle::Vorbis,
+            _ => TagStyle::Id3,
+        }
+    }
+}
+
+// How MusicBrainz data is turned into tags, independent of the container.
+#[derive(Clone, Copy, Debug, Default)]
+struct TagOptions {
+    // Various-artists album: the album artist becomes "Various Artists" and each
+    // track keeps its own performer.
+    compilation: bool,
+    date_source: DateSource,
+    // Added to each track number by --numbering-start, for albums assembled over
+    // several runs.
+    numbering_offset: usize,
+    // --embed-source-url: the release page goes into the comment tag.
+    embed_source_url: bool,
+}
+
+// `format` is the audio format or file extension the tags are written to.
+fn track_metadata_tags(
+    album: &Album,
+    track: &Track,
+    total_tracks: usize,
+    format: &str,
+    options: TagOptions,
+) -> Vec<(String, String)> {
+    let style = TagStyle::for_format(format);
+    let tag = |key: &str, value: String| (key.to_string(), value);
+    let number = track.overall_index + options.numbering_offset;
+    let total_tracks = total_tracks + options.numbering_offset;
+    let artist = track_artist(album, track).to_string();
+    let album_artist = if options.compilation {
+        VARIOUS_ARTISTS.to_string()
+    } else {
+        album.artist.clone()
+    };
+    let mut tags = match style {
+        TagStyle::Id3 => vec![
+            tag("artist", artist),
+            tag("album", album.title.clone()),
+            tag("album_artist", album_artist),
+            tag("title", track.title.clone()),
+            tag("track", format!("{:02}/{}", number, total_tracks)),
+        ],
+        TagStyle::Vorbis => vec![
+            tag("ARTIST", artist),
+            tag("ALBUM", album.title.clone()),
+            tag("ALBUMARTIST", album_artist),
+            tag("TITLE", track.title.clone()),
+            tag("TRACKNUMBER", number.to_string()),
+            tag("TRACKTOTAL", total_tracks.to_string()),
+        ],
+    };
+
+    if album.total_discs > 1 {
+        match style {
+            TagStyle::Id3 => tags.push(tag("disc", track.disc.to_string())),
+            TagStyle::Vorbis => {
+                tags.push(tag("DISCNUMBER", track.disc.to_string()));
+                tags.push(tag("DISCTOTAL", album.total_discs.to_string()));
+            }
+        }
+    }
+
+    // Releases without a known original date keep their own date.
+    let date = match options.date_source {
+        DateSource::Release => album.release_date.as_ref(),
+        DateSource::Original => album.original_date.as_ref().or(album.release_date.as_ref()),
+    };
+    if let Some(date) = date {
+        let key = match style {
+            TagStyle::Id3 => "date",
+            TagStyle::Vorbis => "DATE",
+        };
+        tags.push(tag(key, date.clone()));
+    }
+
+    if options.compilation {
+        let key = match style {
+            TagStyle::Id3 => "compilation",
+            TagStyle::Vorbis => "COMPILATION",
+        };
+        tags.push(tag(key, "1".to_string()));
+    }
+
+    if options.embed_source_url
+        && let Some(url) = &album.source_url
+    {
+        let key = match style {
+            TagStyle::Id3 => "comment",
+            TagStyle::Vorbis => "COMMENT",
+        };
+        tags.push(tag(key, url.clone()));
+    }
+
+    tags
+}
+
+// How MusicBrainz titles become file and folder names.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileNaming {
+    // Characters Windows and macOS reject are replaced, so names work anywhere.
+    Portable,
+    // --no-sanitize: titles stay as they are apart from path separators.
+    Raw,
+}
+
+impl FileNaming {
+    fn name(self, input: &str) -> String {
+        match self {
+            FileNaming::Portable => sanitize_filename(input),
+            FileNaming::Raw => raw_filename(input),
+        }
+    }
+}
+
+// Separators and NUL are always replaced, and the same trimming as
+// sanitize_filename rules out "." and "..", so a title can never leave the folder.
+fn raw_filename(input: &str) -> String {
+    let replaced: String = input
+        .chars()
+        .map(|ch| match ch {
+            '/' | '\\' | '\0' => '_',
+            _ => ch,
+        })
+        .collect();
+    let trimmed = replaced.trim().trim_matches('.');
+    if trimmed.is_empty() {
+        "track".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn sanitize_filename(input: &str) -> String {
+    let mut sanitized = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '/' | '\\' | '?' | '*' | '"' | '<' | '>' | '|' | ':' => sanitized.push('_'),
+            c if c.is_control() => sanitized.push('_'),
+            _ => sanitized.push(ch),
+        }
+    }
+    let trimmed = sanitized.trim().trim_matches('.');
+    if trimmed.is_empty() {
+        "track".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[derive(Debug)]
+struct Album {
+    title: String,
+    artist: String,
+    release_date: Option<String>,
+    // The release group's first release, which predates remasters and reissues.
+    original_date: Option<String>,
+    disambiguation: Option<String>,
+    // Front cover image, if the metadata source has one.
+    cover_url: Option<String>,
+    // The release's page at the metadata source.
+    source_url: Option<String>,
+    total_discs: u32,
+    // Tracks on the whole release, even when --disc narrows `tracks` to one disc.
+    track_total: usize,
+    tracks: Vec<Track>,
+}
+
+#[derive(Debug)]
+struct Track {
+    title: String,
+    artist: Option<String>,
+    disc: u32,
+    position: u32,
+    overall_index: usize,
+    duration: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbReleaseSearchResponse {
+    #[serde(default)]
+    releases: Vec<MbReleaseSearchEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbReleaseSearchEntry {
+    id: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    country: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    #[serde(rename = "track-count", default)]
+    track_count: Option<u32>,
+    #[serde(default)]
+    media: Vec<MbSearchMedium>,
+    #[serde(default)]
+    disambiguation: Option<String>,
+    #[serde(default)]
+    score: Option<u32>,
+}
+
+impl MbReleaseSearchEntry {
+    fn disambiguation(&self) -> Option<&str> {
+        non_empty(self.disambiguation.as_deref())
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|value| !value.is_empty())
+}
+
+#[derive(Debug, Deserialize)]
+struct MbSearchMedium {
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbReleaseDetail {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    disambiguation: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    #[serde(default)]
+    media: Vec<MbMedium>,
+    #[serde(rename = "release-group", default)]
+    release_group: Option<MbReleaseGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbReleaseGroup {
+    #[serde(rename = "first-release-date", default)]
+    first_release_date: Option<String>,
+    #[serde(rename = "primary-type", default)]
+    primary_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbRecordingSearchResponse {
+    #[serde(default)]
+    recordings: Vec<MbRecordingSearchEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbRecordingSearchEntry {
+    id: String,
+    #[serde(default)]
+    score: Option<u32>,
+    #[serde(default)]
+    releases: Vec<MbRecordingRelease>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbRecordingRelease {
+    id: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(rename = "release-group", default)]
+    release_group: Option<MbReleaseGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbArtistCredit {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    joinphrase: Option<String>,
+    #[serde(default)]
+    artist: Option<MbArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbArtist {
+    #[serde(default)]
+    name: Option<String>,
+    // Only present when the lookup includes aliases (see --mb-locale).
+    #[serde(default)]
+    aliases: Vec<MbAlias>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbAlias {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    primary: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbArtistSearchResponse {
+    #[serde(default)]
+    artists: Vec<MbArtistSearchEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbArtistSearchEntry {
+    id: String,
+    #[serde(default)]
+    score: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbMedium {
+    #[serde(default)]
+    position: Option<u32>,
+    #[serde(default)]
+    tracks: Vec<MbTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MbTrack {
+    #[serde(default)]
+    position: Option<u32>,
+    #[serde(default)]
+    number: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    length: Option<u64>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    #[serde(default)]
+    recording: Option<MbRecording>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MbRecording {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    length: Option<u64>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+}
+
+fn looks_like_url(input: &str) -> bool {
+    let lowered = input.trim().to_ascii_lowercase();
+    lowered.starts_with("http://")
+        || lowered.starts_with("https://")
+        || lowered.starts_with("ytsearch:")
+        || lowered.starts_with("ytsearch")
+        || lowered.starts_with("www.")
+        || lowered.contains("://")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct WatchListUrl {
+    video_id: String,
+    list_id: String,
+}
+
+impl WatchListUrl {
+    fn video_url(&self) -> String {
+        format!("https://www.youtube.com/watch?v={}", self.video_id)
+    }
+
+    fn playlist_url(&self) -> String {
+        format!("https://www.youtube.com/playlist?list={}", self.list_id)
+    }
+}
+
+fn parse_watch_list_url(url: &str) -> Option<WatchListUrl> {
+    let lowered = url.to_ascii_lowercase();
+    let short_link = lowered.contains("youtu.be/");
+    if !lowered.contains("youtube.com/watch") && !short_link {
+        return None;
+    }
+
+    let url = url.split('#').next().unwrap_or(url);
+    let (path, query) = url.split_once('?')?;
+    let mut video_id = None;
+    let mut list_id = None;
+    for pair in query.split('&') {
+        match pair.split_once('=') {
+            Some(("v", value)) if !value.is_empty() => video_id = Some(value.to_string()),
+            Some(("list", value)) if !value.is_empty() => list_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if video_id.is_none() && short_link {
+        video_id = path
+            .rsplit('/')
+            .next()
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+    }
+
+    Some(WatchListUrl {
+        video_id: video_id?,
+        list_id: list_id?,
+    })
+}
+
+// Covers RD (video radio), RDMM (My Mix), RDAMPL (music mixes) and so on.
+// RDCLAK lists are YouTube Music's curated playlists, which do end.
+fn is_mix_playlist_id(id: &str) -> bool {
+    id.starts_with("RD") && !id.starts_with("RDCLAK")
+}
+
+fn playlist_list_id(url: &str) -> Option<String> {
+    let url = url.split('#').next().unwrap_or(url);
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|pair| match pair.split_once('=') {
+            Some(("list", value)) if !value.is_empty() => Some(value.to_string()),
+            _ => None,
+        })
+}
+
+// playlist_index keeps an item's original position even with --playlist-reverse,
+// while playlist_autonumber counts in download order, so reversed downloads are
+// numbered 01..N oldest-first.
+// `offset` goes through yt-dlp's template arithmetic for --numbering-start.
+fn playlist_index_field(reverse: bool, offset: usize) -> String {
+    let field = if reverse {
+        "playlist_autonumber"
+    } else {
+        "playlist_index"
+    };
+    if offset == 0 {
+        format!("%({})02d", field)
+    } else {
+        format!("%({}+{})02d", field, offset)
+    }
+}
+
+fn track_number_metadata_field(reverse: bool, offset: usize) -> String {
+    format!(
+        "{}:%(meta_track_number)s",
+        playlist_index_field(reverse, offset)
+    )
+}
+
+fn should_apply_album_metadata(download_album: bool, resolved_target: &str) -> bool {
+    download_album && looks_like_playlist(resolved_target)
+}
+
+fn looks_like_playlist(value: &str) -> bool {
+    let lowered = value.to_ascii_lowercase();
+    lowered.contains("list=")
+}
+
+fn build_single_search_query(query: &str) -> String {
+    format!("ytsearch1:{}", build_search_terms(query))
+}
+
+fn build_search_terms(query: &str) -> String {
+    let trimmed = query.trim();
+
+    // If query contains artist - song format, preserve it for better search results
+    let search_query = if let Some((artist, song)) = split_artist_song(trimmed) {
+        format!("{} {}", artist, song)
+    } else {
+        trimmed.to_string()
+    };
+
+    let mut terms = String::with_capacity(search_query.len() + 24);
+    terms.push_str(&search_query);
+
+    if !search_query.to_ascii_lowercase().contains("audio") {
+        terms.push_str(" audio");
+    }
+
+    terms.push_str(" -\"music video\"");
+
+    terms.trim().to_string()
+}
+
+fn split_artist_song(raw: &str) -> Option<(String, String)> {
+    for delimiter in ['-', '\u{2013}', '\u{2014}'] {
+        if let Some((artist, song)) = raw.split_once(delimiter) {
+            let artist = artist.trim();
+            let song = song.trim();
+            if !artist.is_empty() && !song.is_empty() {
+                return Some((artist.to_string(), song.to_string()));
+            }
+        }
+    }
+    None
+}
+
+fn handle_sync(
+    args: SyncArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+) -> Result<()> {
+    let SyncArgs { mut download, full } = args;
+    route_status_output(&download);
+    let name = download.target.join(" ").trim().to_string();
+    let alias = config
+        .aliases
+        .get(&name)
+        .ok_or_else(|| AppError::Message(format!("alias '{}' not found", name)))?;
+
+    if download.download_archive.is_none() {
+        let archive = alias_archive_path(&name)?;
+        if let Some(parent) = archive.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        download.download_archive = Some(archive);
+    }
+    if download.since.is_none() && !full {
+        download.since = alias.last_synced.clone();
+    }
+    match &download.since {
+        Some(since) => status!(
+            "syncing alias '{}' (items uploaded on or after {})",
+            name,
+            since
+        ),
+        None => status!("syncing alias '{}' (first sync, checking every item)", name),
+    }
+
+    let mode = if alias.album {
+        DownloadMode::Album
+    } else {
+        DownloadMode::Single
+    };
+    handle_download(download, config, musicbrainz, mode)?;
+
+    update_alias_entry(&name, |entry| {
+        let today = format_yyyymmdd(SystemTime::now());
+        status!("marked alias '{}' as synced on {}", name, today);
+        entry.last_synced = Some(today);
+    })
+}
+
+// Downloads don't hold the config lock, so changes recorded afterwards reload the
+// config under the lock; alias edits made while the download was running are kept.
+fn update_alias_entry(name: &str, update: impl FnOnce(&mut AliasEntry)) -> Result<()> {
+    let _lock = ConfigLock::acquire()?;
+    let mut fresh = AppConfig::load()?;
+    if let Some(entry) = fresh.aliases.get_mut(name) {
+        update(entry);
+        fresh.save()?;
+    }
+    Ok(())
+}
+
+fn handle_verify(args: VerifyArgs, musicbrainz: &MusicBrainzSettings) -> Result<()> {
+    let VerifyArgs {
+        dir,
+        mbid,
+        release,
+        tolerance,
+    } = args;
+    if !tolerance.is_finite() || tolerance < 0.0 {
+        return Err(AppError::Message(
+            "--tolerance must be zero or a positive number of seconds".to_string(),
+        ));
+    }
+
+    let local = read_local_tracks(&dir)?;
+    let client = MusicBrainzClient::new(musicbrainz)?;
+    let album = resolve_local_release(&client, mbid.as_deref(), release.as_deref(), &local)?;
+    println!(
+        "checking {} file{} in {} against {} - {} ({} track{})",
+        local.len(),
+        if local.len() == 1 { "" } else { "s" },
+        dir.display(),
+        album.artist,
+        album.title,
+        album.tracks.len(),
+        if album.tracks.len() == 1 { "" } else { "s" }
+    );
+
+    let matching = match_local_tracks(&album, &local);
+    let mut problems = 0usize;
+    for &(track_index, local_index) in &matching.pairs {
+        let track = &album.tracks[track_index];
+        let file = &local[local_index];
+        let name = file_display_name(&file.path);
+        if !local_title_matches(file, &track.title) {
+            problems += 1;
+            println!(
+                "misnamed: track {:02} '{}' is '{}'{}",
+                track.overall_index,
+                track.title,
+                name,
+                file.tags
+                    .title
+                    .as_deref()
+                    .map(|title| format!(" (titled '{}')", title))
+                    .unwrap_or_default()
+            );
+        }
+        if let (Some(expected), Some(actual)) = (track.duration, file.tags.duration)
+            && (expected - actual).abs() > tolerance
+        {
+            problems += 1;
+            println!(
+                "duration: track {:02} '{}' is {} but MusicBrainz lists {} ({})",
+                track.overall_index,
+                track.title,
+                format_duration(actual),
+                format_duration(expected),
+                name
+            );
+        }
+    }
+    for &track_index in &matching.missing {
+        let track = &album.tracks[track_index];
+        problems += 1;
+        println!(
+            "missing: track {:02} '{}'",
+            track.overall_index, track.title
+        );
+    }
+    for &local_index in &matching.extra {
+        problems += 1;
+        println!(
+            "unexpected: '{}' does not match any track on the release",
+            file_display_name(&local[local_index].path)
+        );
+    }
+
+    if problems == 0 {
+        println!("all {} tracks present and matching", album.tracks.len());
+        Ok(())
+    } else {
+        Err(AppError::Message(format!(
+            "{} problem{} found",
+            problems,
+            if problems == 1 { "" } else { "s" }
+        )))
+    }
+}
+
+fn handle_clean(args: CleanArgs, config: &AppConfig) -> Result<()> {
+    if args.dir.is_none() && config.is_invalid("default_destination") {
+        config.ensure_valid(&config_file_path()?)?;
+    }
+    let Some(dir) = args.dir.or_else(|| config.default_destination.clone()) else {
+        return Err(AppError::Message(
+            "no directory given and no default destination is set".to_string(),
+        ));
+    };
+    if !dir.is_dir() {
+        return Err(AppError::Message(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+
+    let (partials, in_progress) = stale_partial_downloads(&dir)?;
+    if in_progress > 0 {
+        println!("{}", describe_kept_partials(in_progress));
+    }
+    if partials.is_empty() {
+        println!("no partial downloads to remove in {}", dir.display());
+        return Ok(());
+    }
+
+    let mut reclaimed = 0u64;
+    for path in &partials {
+        reclaimed += fs::metadata(path)?.len();
+        let shown = path.strip_prefix(&dir).unwrap_or(path).display();
+        if args.dry_run {
+            println!("would remove {}", shown);
+        } else {
+            fs::remove_file(path)?;
+            println!("removed {}", shown);
+        }
+    }
+    println!(
+        "{} {} file{}, {}",
+        if args.dry_run {
+            "would remove"
+        } else {
+            "removed"
+        },
+        partials.len(),
+        if partials.len() == 1 { "" } else { "s" },
+        format_size(reclaimed as f64)
+    );
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum YtDlpInstall {
+    Standalone,
+    Pip(String),
+    Pipx,
+    Homebrew,
+    // Owned by apt, dnf, pacman, ...; pip would fail under PEP 668 or clobber it.
+    System,
+}
+
+impl YtDlpInstall {
+    fn update_command(&self) -> Option<Command> {
+        let (program, args): (&str, &[&str]) = match self {
+            Self::Standalone => ("yt-dlp", &["-U"]),
+            Self::Pip(python) => (python, &["-m", "pip", "install", "-U", "yt-dlp"]),
+            Self::Pipx => ("pipx", &["upgrade", "yt-dlp"]),
+            Self::Homebrew => ("brew", &["upgrade", "yt-dlp"]),
+            Self::System => return None,
+        };
+        let mut command = Command::new(program);
+        command.args(args);
+        Some(command)
+    }
+}
+
+// A guess from where the yt-dlp on PATH lives and what its first line says: pip
+// and pipx install a Python script, the release binaries update themselves and
+// anything under /usr/bin belongs to the distribution's package manager.
+fn detect_ytdlp_install(path: &Path, first_line: &str) -> YtDlpInstall {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with("/usr/bin/") || path.starts_with("/usr/lib/python3/dist-packages/") {
+        return YtDlpInstall::System;
+    }
+    if path.contains("/pipx/") {
+        return YtDlpInstall::Pipx;
+    }
+    if path.contains("/Cellar/") || path.contains("/homebrew/") || path.contains("/linuxbrew/") {
+        return YtDlpInstall::Homebrew;
+    }
+    let Some(shebang) = first_line.strip_prefix("#!") else {
+        return YtDlpInstall::Standalone;
+    };
+    let mut words = shebang.split_whitespace();
+    let python = match words.next() {
+        Some(env) if env.ends_with("/env") => words.next(),
+        interpreter => interpreter,
+    };
+    match python {
+        Some(python) if python.contains("python") => YtDlpInstall::Pip(python.to_string()),
+        _ => YtDlpInstall::Standalone,
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+fn ytdlp_version() -> Result<String> {
+    let output = Command::new("yt-dlp")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_yt_dlp_error)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn handle_update_ytdlp() -> Result<()> {
+    let before = ytdlp_version()?;
+    let path = find_in_path("yt-dlp").map(|path| fs::canonicalize(&path).unwrap_or(path));
+    let install = match &path {
+        Some(path) => {
+            let mut first_line = String::new();
+            if let Ok(file) = fs::File::open(path) {
+                // Binaries rarely have a newline early on, so only a bounded prefix is read.
+                let _ = BufReader::new(file.take(256)).read_line(&mut first_line);
+            }
+            detect_ytdlp_install(path, &first_line)
+        }
+        None => YtDlpInstall::Standalone,
+    };
+    let Some(mut command) = install.update_command() else {
+        return Err(AppError::Message(format!(
+            "yt-dlp {} at {} was installed by your system's package manager; update it with that (e.g. `sudo apt upgrade yt-dlp`)",
+            before,
+            path.unwrap_or_default().display()
+        )));
+    };
+    status!(
+        "updating yt-dlp {} with `{}`",
+        before,
+        describe_command(&command)
+    );
+    let result = command.status().map_err(|err| {
+        if err.kind() == ErrorKind::NotFound && install != YtDlpInstall::Standalone {
+            AppError::Message(format!(
+                "{} was not found in PATH",
+                command.get_program().to_string_lossy()
+            ))
+        } else {
+            map_yt_dlp_error(err)
+        }
+    })?;
+    if !result.success() {
+        return Err(AppError::Message(format!(
+            "`{}` failed ({})",
+            describe_command(&command),
+            result
+        )));
+    }
+    let after = ytdlp_version()?;
+    if after == before {
+        status!("yt-dlp {} is already up to date", after);
+    } else {
+        status!(
+            "{}",
+            Tone::Success.paint(format!("updated yt-dlp {} -> {}", before, after))
+        );
+    }
+    Ok(())
+}
+
+fn handle_version(args: VersionArgs) {
+    print_setting(APP_NAME, env!("CARGO_PKG_VERSION"));
+    if !args.full {
+        return;
+    }
+    let found = |version: Option<String>| version.unwrap_or_else(|| "not found".to_string());
+    print_setting("yt-dlp", found(tool_version("yt-dlp", "--version")));
+    print_setting("ffmpeg", found(tool_version("ffmpeg", "-version")));
+    print_setting(
+        "platform",
+        format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+    );
+}
+
+// The first line of the tool's version output, or None when it is missing or fails.
+fn tool_version(program: &str, flag: &str) -> Option<String> {
+    let output = Command::new(program)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    first_output_line(&output.stdout)
+}
+
+fn first_output_line(stdout: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+fn describe_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Conservative by design: only audio files directly in `dir` are considered, and
+// a file is kept when its name matches any track in upper or lower case.
+fn handle_prune(
+    args: PruneArgs,
+    config: &AppConfig,
+    musicbrainz: &MusicBrainzSettings,
+) -> Result<()> {
+    let PruneArgs {
+        dir,
+        mbid,
+        release,
+        output_template,
+        flat_numbering,
+        no_sanitize,
+        dry_run,
+        yes,
+    } = args;
+    let template = OutputTemplate::parse(
+        output_template
+            .as_deref()
+            .unwrap_or(config.template(TemplateKind::Album)),
+    )?;
+
+    let local = read_local_tracks(&dir)?;
+    let client = MusicBrainzClient::new(musicbrainz)?;
+    let album = resolve_local_release(&client, mbid.as_deref(), release.as_deref(), &local)?;
+    let budget = filename_budget(&dir, DEFAULT_MAX_PATH_LENGTH)?;
+    let naming = if no_sanitize {
+        FileNaming::Raw
+    } else {
+        FileNaming::Portable
+    };
+    let stale = unexpected_track_files(&album, &local, &template, flat_numbering, naming, budget);
+    println!(
+        "{} of {} file{} in {} match no track of {} - {}",
+        stale.len(),
+        local.len(),
+        if local.len() == 1 { "" } else { "s" },
+        dir.display(),
+        album.artist,
+        album.title
+    );
+    if stale.is_empty() {
+        return Ok(());
+    }
+    for path in &stale {
+        println!("  {}", file_display_name(path));
+    }
+    // Nothing matching at all usually means the wrong release or template.
+    let nothing_matches = stale.len() == local.len();
+    if dry_run {
+        if nothing_matches {
+            println!(
+                "{}",
+                Tone::Warning.paint("no file matches the track list; check --output-template")
+            );
+        }
+        return Ok(());
+    }
+    if nothing_matches {
+        return Err(AppError::Message(
+            "no file matches the track list, so nothing was deleted; check the release and --output-template"
+                .to_string(),
+        ));
+    }
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(AppError::Message(
+                "stdin is not a terminal; pass --yes to delete without confirmation".to_string(),
+            ));
+        }
+        if !confirm(&format!(
+            "delete {} file{}?",
+            stale.len(),
+            if stale.len() == 1 { "" } else { "s" }
+        ))? {
+            println!("nothing was deleted");
+            return Ok(());
+        }
+    }
+    for path in &stale {
+        fs::remove_file(path)?;
+        println!("removed {}", file_display_name(path));
+    }
+    Ok(())
+}
+
+// A file is unexpected when its name is none of the track stems a default
+// download would write (whole or cut to the path length budget) and its title
+// tag matches no track either. The tag check keeps files named by options the
+// stems cannot know about, like --numbering-start, --clean-titles or --case.
+fn unexpected_track_files<'a>(
+    album: &Album,
+    files: &'a [LocalTrack],
+    template: &OutputTemplate,
+    flat_numbering: bool,
+    naming: FileNaming,
+    budget: usize,
+) -> Vec<&'a Path> {
+    let expected: HashSet<String> = album
+        .tracks
+        .iter()
+        .flat_map(|track| {
+            let stem = |max_length| {
+                template.track_stem(album, track, 0, flat_numbering, naming, max_length)
+            };
+            [stem(budget), stem(usize::MAX)]
+        })
+        .map(|stem| stem.to_lowercase())
+        .collect();
+    files
+        .iter()
+        .filter(|file| AUDIO_EXTENSIONS.contains(&lowercase_extension(&file.path).as_str()))
+        .filter(|file| {
+            let stem = file
+                .path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            !expected.contains(&stem)
+        })
+        .filter(|file| {
+            !file.tags.title.as_deref().is_some_and(|title| {
+                album
+                    .tracks
+                    .iter()
+                    .any(|track| titles_match(&track.title, title))
+            })
+        })
+        .map(|file| file.path.as_path())
+        .collect()
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{} [y/N]: ", prompt);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(matches!(
+        line.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+fn handle_retag(args: RetagArgs, musicbrainz: &MusicBrainzSettings) -> Result<()> {
+    let RetagArgs {
+        dir,
+        mbid,
+        release,
+        by_order,
+        dry_run,
+        compilation,
+        date_source,
+    } = args;
+
+    let local = read_local_tracks(&dir)?;
+    let client = MusicBrainzClient::new(musicbrainz)?;
+    let album = resolve_local_release(&client, mbid.as_deref(), release.as_deref(), &local)?;
+    println!(
+        "retagging {} file{} in {} as {} - {}",
+        local.len(),
+        if local.len() == 1 { "" } else { "s" },
+        dir.display(),
+        album.artist,
+        album.title
+    );
+
+    let matching = if by_order {
+        match_tracks_by_order(album.tracks.len(), local.len())
+    } else {
+        match_local_tracks(&album, &local)
+    };
+
+    let total_tracks = album.tracks.len();
+    for &(track_index, local_index) in &matching.pairs {
+        let track = &album.tracks[track_index];
+        println!(
+            "{:02} {} <- {}",
+            track.overall_index,
+            track.title,
+            file_display_name(&local[local_index].path)
+        );
+    }
+    for &track_index in &matching.missing {
+        let track = &album.tracks[track_index];
+        println!(
+            "no file for track {:02} '{}'",
+            track.overall_index, track.title
+        );
+    }
+    for &local_index in &matching.extra {
+        println!(
+            "leaving '{}' untouched (no matching track)",
+            file_display_name(&local[local_index].path)
+        );
+    }
+
+    if dry_run {
+        println!("dry run: no files were changed");
+        return Ok(());
+    }
+
+    for &(track_index, local_index) in &matching.pairs {
+        let file = &local[local_index].path;
+        let tags = track_metadata_tags(
+            &album,
+            &album.tracks[track_index],
+            total_tracks,
+            &lowercase_extension(file),
+            TagOptions {
+                compilation,
+                date_source,
+                ..TagOptions::default()
+            },
+        );
+        write_tags(file, &tags)?;
+    }
+    println!(
+        "retagged {} file{}",
+        matching.pairs.len(),
+        if matching.pairs.len() == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+fn match_tracks_by_order(track_count: usize, file_count: usize) -> TrackMatching {
+    let paired = track_count.min(file_count);
+    TrackMatching {
+        pairs: (0..paired).map(|index| (index, index)).collect(),
+        missing: (paired..track_count).collect(),
+        extra: (paired..file_count).collect(),
+    }
+}
+
+struct LocalTrack {
+    path: PathBuf,
+    tags: AudioTags,
+}
+
+fn read_local_tracks(dir: &Path) -> Result<Vec<LocalTrack>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && AUDIO_EXTENSIONS.contains(&lowercase_extension(&path).as_str()) {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(AppError::Message(format!(
+            "no audio files found in {}",
+            dir.display()
+        )));
+    }
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|path| {
+            let tags = probe_tags(&path)?;
+            Ok(LocalTrack { path, tags })
+        })
+        .collect()
+}
+
+// Uses --mbid or --release when given, otherwise the album tags on the files.
+fn resolve_local_release(
+    client: &MusicBrainzClient,
+    mbid: Option<&str>,
+    release: Option<&str>,
+    local: &[LocalTrack],
+) -> Result<Album> {
+    if let Some(raw) = mbid {
+        let id = parse_release_mbid(raw).ok_or_else(|| {
+            AppError::Message(format!(
+                "'{}' is not a MusicBrainz release ID or release URL",
+                raw
+            ))
+        })?;
+        return client.fetch_release(&id);
+    }
+
+    let query = match release {
+        Some(query) => query.to_string(),
+        None => local
+            .iter()
+            .find_map(|file| {
+                let album = file.tags.album.as_deref()?;
+                let artist = file
+                    .tags
+                    .album_artist
+                    .as_deref()
+                    .or(file.tags.artist.as_deref())?;
+                Some(format!("{} - {}", artist, album))
+            })
+            .ok_or_else(|| {
+                AppError::Message(
+                    "the files have no artist/album tags; pass --mbid or --release".to_string(),
+                )
+            })?,
+    };
+    println!("searching MusicBrainz for album '{}'", query);
+    client.find_album(&query)?.ok_or(AppError::AlbumNotFound {
+        backend: "MusicBrainz",
+        query,
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct TrackMatching {
+    // (index into the release's tracks, index into the local files)
+    pairs: Vec<(usize, usize)>,
+    missing: Vec<usize>,
+    extra: Vec<usize>,
+}
+
+// Files are matched by track number (tag first, then filename prefix) and the
+// rest by title, so renamed or untagged files still line up with the release.
+fn match_local_tracks(album: &Album, local: &[LocalTrack]) -> TrackMatching {
+    let mut claimed: Vec<Option<usize>> = vec![None; album.tracks.len()];
+    let mut unmatched = Vec::new();
+
+    for (local_index, file) in local.iter().enumerate() {
+        match numbered_track_index(album, file) {
+            Some(track_index) if claimed[track_index].is_none() => {
+                claimed[track_index] = Some(local_index);
+            }
+            _ => unmatched.push(local_index),
+        }
+    }
+
+    let mut extra = Vec::new();
+    for local_index in unmatched {
+        let file = &local[local_index];
+        let found = album
+            .tracks
+            .iter()
+            .enumerate()
+            .position(|(track_index, track)| {
+                claimed[track_index].is_none() && local_title_matches(file, &track.title)
+            });
+        match found {
+            Some(track_index) => claimed[track_index] = Some(local_index),
+            None => extra.push(local_index),
+        }
+    }
+
+    let mut matching = TrackMatching {
+        extra,
+        ..TrackMatching::default()
+    };
+    for (track_index, local_index) in claimed.into_iter().enumerate() {
+        match local_index {
+            Some(local_index) => matching.pairs.push((track_index, local_index)),
+            None => matching.missing.push(track_index),
+        }
+    }
+    matching
+}
+
+fn numbered_track_index(album: &Album, file: &LocalTrack) -> Option<usize> {
+    let by_disc_position = |disc: u32, position: u32| {
+        album
+            .tracks
+            .iter()
+            .position(|track| track.disc == disc && track.position == position)
+    };
+
+    if let Some(number) = file.tags.track {
+        // bippi tags the overall index, so prefer it when the disc agrees.
+        let overall = album
+            .tracks
+            .iter()
+            .position(|track| track.overall_index == number as usize)
+            .filter(|&index| {
+                file.tags
+                    .disc
+                    .is_none_or(|disc| album.tracks[index].disc == disc)
+            });
+        return overall.or_else(|| by_disc_position(file.tags.disc?, number));
+    }
+
+    let stem = file.path.file_stem()?.to_str()?;
+    let (prefix, _) = stem.split_once(" - ")?;
+    match prefix.split_once('-') {
+        Some((disc, position)) => {
+            by_disc_position(disc.trim().parse().ok()?, position.trim().parse().ok()?)
+        }
+        None => {
+            let number: usize = prefix.trim().parse().ok()?;
+            album
+                .tracks
+                .iter()
+                .position(|track| track.overall_index == number)
+        }
+    }
+}
+
+fn local_title_matches(file: &LocalTrack, expected: &str) -> bool {
+    let stem = file
+        .path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    file.tags
+        .title
+        .iter()
+        .chain(std::iter::once(&stem))
+        .any(|candidate| titles_match(expected, candidate))
+}
+
+// Word-boundary containment on normalized titles, so "Battery (Remastered)" or
+// "03 - Battery" match "Battery" but "Someone" does not match "One".
+fn titles_match(expected: &str, found: &str) -> bool {
+    let expected = normalize_title(expected);
+    let found = normalize_title(found);
+    if expected.is_empty() || found.is_empty() {
+        return false;
+    }
+    let padded_found = format!(" {} ", found);
+    let padded_expected = format!(" {} ", expected);
+    padded_found.contains(&padded_expected) || padded_expected.contains(&padded_found)
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn file_display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn format_duration(secs: f64) -> String {
+    let total = secs.round() as u64;
+    format!("{}:{:02}", total / 60, total % 60)
+}
+
+fn alias_archive_path(name: &str) -> Result<PathBuf> {
+    let mut base = dirs::config_dir().ok_or(AppError::MissingConfigDir)?;
+    base.push(APP_NAME);
+    base.push(ARCHIVE_DIRNAME);
+    base.push(format!("{}.txt", sanitize_filename(name)));
+    Ok(base)
+}
+
+fn format_yyyymmdd(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{year:04}{month:02}{day:02}")
+}
+
+// Converts days since 1970-01-01 to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn handle_alias(command: AliasCommand, config: &mut AppConfig) -> Result<bool> {
+    match command {
+        AliasCommand::Add(args) => {
+            let mbid = match &args.mbid {
+                Some(raw) => Some(parse_release_mbid(raw).ok_or_else(|| {
+                    AppError::Message(format!(
+                        "'{}' is not a MusicBrainz release ID or release URL",
+                        raw
+                    ))
+                })?),
+                None => None,
+            };
+            if let Some(existing) = config.aliases.get(&args.name) {
+                if args.no_overwrite {
+                    return Err(AppError::Message(format!(
+                        "alias '{}' already exists; remove it first or drop --no-overwrite",
+                        args.name
+                    )));
+                }
+                if !args.force {
+                    eprintln!(
+                        "{} replacing alias '{}' (was {}); pass --force to skip this warning",
+                        Tone::Warning.paint("warning:"),
+                        args.name,
+                        alias_target_description(existing)
+                    );
+                }
+            }
+            let entry = AliasEntry {
+                url: args.url,
+                album: args.album || mbid.is_some(),
+                last_synced: None,
+                mbid,
+                track_urls: Vec::new(),
+            };
+            let existed = config.aliases.insert(args.name.clone(), entry).is_some();
+            if existed {
+                println!("updated alias '{}'", args.name);
+            } else {
+                println!("created alias '{}'", args.name);
+            }
+            Ok(true)
+        }
+        AliasCommand::Remove(args) => {
+            if config.aliases.remove(&args.name).is_some() {
+                println!("removed alias '{}'", args.name);
+                Ok(true)
+            } else {
+                Err(AppError::Message(format!(
+                    "alias '{}' not found",
+                    args.name
+                )))
+            }
+        }
+        AliasCommand::List => {
+            if config.aliases.is_empty() {
+                println!("no aliases defined yet");
+            } else {
+                for (name, entry) in &config.aliases {
+                    let target = alias_target_description(entry);
+                    let mut notes = Vec::new();
+                    if entry.album {
+                        notes.push("album".to_string());
+                    }
+                    if let (Some(_), Some(mbid)) = (&entry.url, &entry.mbid) {
+                        notes.push(format!("release {}", mbid));
+                    }
+                    if !entry.track_urls.is_empty() {
+                        notes.push(format!("{} track URLs stored", entry.track_urls.len()));
+                    }
+                    if let Some(date) = &entry.last_synced {
+                        notes.push(format!("synced {}", date));
+                    }
+                    let name = Tone::Info.paint(name);
+                    if notes.is_empty() {
+                        println!("{} -> {}", name, target);
+                    } else {
+                        println!("{} -> {} ({})", name, target, notes.join(", "));
+                    }
+                }
+            }
+            Ok(false)
+        }
+        AliasCommand::Test(args) => {
+            handle_alias_test(args, config)?;
+            Ok(false)
+        }
+    }
+}
+
+fn handle_alias_test(args: AliasTestArgs, config: &AppConfig) -> Result<()> {
+    let Some(name) = args.name else {
+        return test_all_aliases(config);
+    };
+    let Some(entry) = config.aliases.get(&name) else {
+        return Err(AppError::Message(format!("alias '{}' not found", name)));
+    };
+    match probe_alias(entry)? {
+        AliasHealth::Alive { items } => {
+            println!(
+                "alias '{}' resolves: {}",
+                name,
+                describe_alias_items(items, entry)
+            );
+            Ok(())
+        }
+        AliasHealth::NoUrl => {
+            println!(
+                "alias '{}' has no URL to test ({})",
+                name,
+                alias_target_description(entry)
+            );
+            Ok(())
+        }
+        AliasHealth::Dead(reason) => Err(AppError::Message(format!(
+            "alias '{}' no longer resolves: {}",
+            name, reason
+        ))),
+    }
+}
+
+fn test_all_aliases(config: &AppConfig) -> Result<()> {
+    if config.aliases.is_empty() {
+        println!("no aliases defined yet");
+        return Ok(());
+    }
+    let width = config
+        .aliases
+        .keys()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut dead = 0;
+    for (name, entry) in &config.aliases {
+        let (status, detail) = match probe_alias(entry)? {
+            AliasHealth::Alive { items } => (
+                Tone::Success.paint(format!("{:<4}", "ok")),
+                describe_alias_items(items, entry),
+            ),
+            AliasHealth::NoUrl => (
+                Tone::Info.paint(format!("{:<4}", "skip")),
+                format!("no URL ({})", alias_target_description(entry)),
+            ),
+            AliasHealth::Dead(reason) => {
+                dead += 1;
+                (Tone::Error.paint(format!("{:<4}", "dead")), reason)
+            }
+        };
+        println!("{:<width$}  {}  {}", name, status, detail, width = width);
+    }
+    if dead > 0 {
+        return Err(AppError::Message(format!(
+            "{} of {} aliases no longer resolve",
+            dead,
+            config.aliases.len()
+        )));
+    }
+    Ok(())
+}
+
+fn describe_alias_items(items: usize, entry: &AliasEntry) -> String {
+    format!(
+        "{} item{} at {}",
+        items,
+        if items == 1 { "" } else { "s" },
+        alias_target_description(entry)
+    )
+}
+
+#[derive(Debug, PartialEq)]
+enum AliasHealth {
+    Alive { items: usize },
+    Dead(String),
+    // Aliases pinned to a MusicBrainz release alone have nothing for yt-dlp to check.
+    NoUrl,
+}
+
+fn probe_alias(entry: &AliasEntry) -> Result<AliasHealth> {
+    let Some(url) = &entry.url else {
+        return Ok(AliasHealth::NoUrl);
+    };
+    let output = Command::new("yt-dlp")
+        .arg("--simulate")
+        .arg("--flat-playlist")
+        .arg("--no-warnings")
+        .arg("-J")
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(map_yt_dlp_error)?;
+    Ok(parse_alias_probe(
+        output.status.success(),
+        &output.stdout,
+        &String::from_utf8_lossy(&output.stderr),
+    ))
+}
+
+fn parse_alias_probe(success: bool, stdout: &[u8], stderr: &str) -> AliasHealth {
+    if !success {
+        let reason = describe_availability_error(stderr)
+            .or_else(|| {
+                stderr
+                    .lines()
+                    .rev()
+                    .find_map(|line| line.trim().strip_prefix("ERROR:"))
+                    .map(|line| line.trim().to_string())
+            })
+            .unwrap_or_else(|| "yt-dlp could not resolve the URL".to_string());
+        return AliasHealth::Dead(reason);
+    }
+    match serde_json::from_slice::<serde_json::Value>(stdout) {
+        Ok(info) => {
+            let items = info
+                .get("entries")
+                .and_then(|entries| entries.as_array())
+                .map_or(1, Vec::len);
+            if items == 0 {
+                AliasHealth::Dead("the playlist is empty".to_string())
+            } else {
+                AliasHealth::Alive { items }
+            }
+        }
+        Err(_) => AliasHealth::Dead("yt-dlp returned no usable information".to_string()),
+    }
+}
+
+fn alias_target_description(entry: &AliasEntry) -> String {
+    match (&entry.url, &entry.mbid) {
+        (Some(url), _) => url.clone(),
+        (None, Some(mbid)) => format!("MusicBrainz release {}", mbid),
+        (None, None) => "(no target)".to_string(),
+    }
+}
+
+fn handle_config(command: ConfigCommand, config: &mut AppConfig) -> Result<bool> {
+    match command {
+        ConfigCommand::SetDest(args) => {
+            let absolute = ensure_absolute(&args.path)?;
+            ensure_directory_path(&absolute, "destination")?;
+            if let Some(parent) = absolute.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if !absolute.exists() {
+                fs::create_dir_all(&absolute)?;
+            }
+            config.default_destination = Some(absolute.clone());
+            println!("default destination set to {}", absolute.display());
+            Ok(true)
+        }
+        ConfigCommand::Show(args) => {
+            match &config.default_destination {
+                Some(path) => print_setting("default destination", path.display()),
+                None => print_setting("default destination", "not set"),
+            }
+            for (format, path) in &config.format_destinations {
+                print_setting(&format!("{} destination", format), path.display());
+            }
+            match &config.tmp_dir {
+                Some(dir) => print_setting("temp dir", dir.display()),
+                None => print_setting("temp dir", "destination"),
+            }
+            print_setting(
+                "impersonate",
+                config.impersonate.as_deref().unwrap_or("none"),
+            );
+            if config.extractor_args.is_empty() {
+                print_setting("extractor args", "none");
+            } else {
+                let specs = config
+                    .extractor_args
+                    .iter()
+                    .map(|spec| display_extractor_args(spec, args.reveal))
+                    .collect::<Vec<_>>();
+                print_setting("extractor args", specs.join(", "));
+            }
+            print_setting(
+                "po token",
+                display_secret(config.po_token.as_deref(), args.reveal),
+            );
+            for kind in [TemplateKind::Single, TemplateKind::Album] {
+                let label = format!("{} template", kind.name());
+                match config.custom_template(kind) {
+                    Some(template) => print_setting(&label, template),
+                    None => {
+                        print_setting(&label, format_args!("{} (default)", config.template(kind)))
+                    }
+                }
+            }
+            match config.min_free_space {
+                Some(size) => print_setting("minimum free space", format_size(size as f64)),
+                None => print_setting(
+                    "minimum free space",
+                    format_args!("{} (default)", format_size(DEFAULT_MIN_FREE_SPACE as f64)),
+                ),
+            }
+            if config.aliases.is_empty() {
+                print_setting("aliases", "none");
+            } else {
+                print_setting("aliases", config.aliases.len());
+            }
+            print_setting("search", config.search_site().name());
+            match config.album_keyword.as_deref() {
+                Some("") => print_setting("album keyword", "none"),
+                Some(keyword) => print_setting("album keyword", keyword),
+                None => print_setting(
+                    "album keyword",
+                    format_args!("{} (default)", DEFAULT_ALBUM_KEYWORD),
+                ),
+            }
+            if config.blocklist.is_empty() {
+                print_setting("blocklist", "none");
+            } else {
+                print_setting("blocklist", config.blocklist.join(", "));
+            }
+            if config.allowlist.is_empty() {
+                print_setting("allowlist", "none");
+            } else {
+                print_setting("allowlist", config.allowlist.join(", "));
+            }
+            if config.title_noise.is_empty() {
+                print_setting("title noise", "none");
+            } else {
+                print_setting("title noise", config.title_noise.join(", "));
+            }
+            if config.version_excludes.is_empty() {
+                print_setting("version excludes", "none");
+            } else {
+                print_setting("version excludes", config.version_excludes.join(", "));
+            }
+            print_setting("strip album suffix", config.strip_album_suffix);
+            if config.album_suffixes.is_empty() {
+                print_setting("album suffixes", "none");
+            } else {
+                print_setting("album suffixes", config.album_suffixes.join(", "));
+            }
+            match &config.musicbrainz.user_agent {
+                Some(user_agent) => print_setting("musicbrainz user agent", user_agent),
+                None => print_setting(
+                    "musicbrainz user agent",
+                    format_args!("{} (default)", default_user_agent()),
+                ),
+            }
+            match config.musicbrainz.timeout_secs {
+                Some(secs) => print_setting("musicbrainz timeout", format_args!("{}s", secs)),
+                None => print_setting(
+                    "musicbrainz timeout",
+                    format_args!("{}s (default)", DEFAULT_MUSICBRAINZ_TIMEOUT_SECS),
+                ),
+            }
+            match &config.musicbrainz.base_url {
+                Some(url) => print_setting("musicbrainz url", url),
+                None => print_setting(
+                    "musicbrainz url",
+                    format_args!("{} (default)", MUSICBRAINZ_BASE_URL),
+                ),
+            }
+            print_setting(
+                "musicbrainz token",
+                display_secret(config.musicbrainz.token.as_deref(), args.reveal),
+            );
+            print_setting(
+                "musicbrainz locale",
+                config
+                    .musicbrainz
+                    .locale
+                    .as_deref()
+                    .unwrap_or("not set (credited names)"),
+            );
+            Ok(false)
+        }
+        ConfigCommand::ClearDest => {
+            if config.default_destination.take().is_some() {
+                println!("cleared default destination");
+                Ok(true)
+            } else {
+                println!("default destination was already unset");
+                Ok(false)
+            }
+        }
+        ConfigCommand::SetFormatDest(args) => {
+            let format = args.format.trim().to_ascii_lowercase();
+            if format.is_empty() {
+                return Err(AppError::Message("format must not be empty".to_string()));
+            }
+            let absolute = ensure_absolute(&args.path)?;
+            ensure_directory_path(&absolute, "destination")?;
+            fs::create_dir_all(&absolute)?;
+            println!(
+                "{} downloads will be saved to {}",
+                format,
+                absolute.display()
+            );
+            config.format_destinations.insert(format, absolute);
+            Ok(true)
+        }
+        ConfigCommand::ClearFormatDest(args) => {
+            let format = args.format.trim().to_ascii_lowercase();
+            if config.format_destinations.remove(&format).is_some() {
+                println!("cleared the {} destination", format);
+                Ok(true)
+            } else {
+                println!("no destination was set for {}", format);
+                Ok(false)
+            }
+        }
+        ConfigCommand::SetMinFree(args) => {
+            let size = parse_size(&args.size)?;
+            config.min_free_space = Some(size);
+            println!(
+                "album downloads will keep at least {} free",
+                format_size(size as f64)
+            );
+            Ok(true)
+        }
+        ConfigCommand::SetTmpDir(args) => {
+            let dir = validate_tmp_dir(&args.path)?;
+            println!("intermediate files will be kept in {}", dir.display());
+            config.tmp_dir = Some(dir);
+            Ok(true)
+        }
+        ConfigCommand::SetTemplate(args) => {
+            OutputTemplate::parse(&args.template)?;
+            println!(
+                "{} downloads will be named '{}'",
+                args.kind.name(),
+                args.template
+            );
+            *config.template_slot(args.kind) = Some(args.template);
+            Ok(true)
+        }
+        ConfigCommand::ClearTemplate(args) => {
+            if config.template_slot(args.kind).take().is_some() {
+                println!(
+                    "{} template reset to '{}'",
+                    args.kind.name(),
+                    config.template(args.kind)
+                );
+                Ok(true)
+            } else {
+                println!("{} template was already the default", args.kind.name());
+                Ok(false)
+            }
+        }
+        ConfigCommand::SetAlbumKeyword(args) => {
+            let keyword = args.keyword.trim().to_string();
+            if keyword.is_empty() {
+                println!("album searches will not add a keyword");
+            } else {
+                println!("album searches will add '{}'", keyword);
+            }
+            config.album_keyword = Some(keyword);
+            Ok(true)
+        }
+        ConfigCommand::SetStripAlbumSuffix(args) => {
+            config.strip_album_suffix = args.enabled;
+            if args.enabled {
+                println!("album titles will lose edition suffixes like '(Deluxe Edition)'");
+            } else {
+                println!("album titles will be kept as they are");
+            }
+            Ok(true)
+        }
+        ConfigCommand::SetSearch(args) => {
+            config.prefer_ytmusic = args.site == SearchSite::YouTubeMusic;
+            println!("track searches will use {}", args.site.name());
+            Ok(true)
+        }
+        ConfigCommand::SetImpersonate(args) => {
+            let target = validate_impersonate_target(args.target)?;
+            check_impersonate_support(&target)?;
+            println!("downloads will impersonate {}", target);
+            config.impersonate = Some(target);
+            Ok(true)
+        }
+        ConfigCommand::ExtractorArgs { command } => {
+            if let TermListCommand::Add(args) = &command {
+                parse_extractor_args(&args.term)?;
+            }
+            handle_term_list(command, &mut config.extractor_args, "extractor args list")
+        }
+        ConfigCommand::SetPoToken(args) => {
+            po_token_extractor_args(&args.token)?;
+            config.po_token = Some(args.token.trim().to_string());
+            println!("downloads will send the PO token to YouTube");
+            Ok(true)
+        }
+        ConfigCommand::ClearPoToken => {
+            if config.po_token.take().is_some() {
+                println!("cleared the PO token");
+                Ok(true)
+            } else {
+                println!("PO token was already unset");
+                Ok(false)
+            }
+        }
+        ConfigCommand::ClearImpersonate => {
+            if config.impersonate.take().is_some() {
+                println!("downloads will no longer impersonate a browser");
+                Ok(true)
+            } else {
+                println!("impersonation was already off");
+                Ok(false)
+            }
+        }
+        ConfigCommand::ClearTmpDir => {
+            if config.tmp_dir.take().is_some() {
+                println!("cleared the temp dir");
+                Ok(true)
+            } else {
+                println!("temp dir was already unset");
+                Ok(false)
+            }
+        }
+        ConfigCommand::Blocklist { command } => {
+            handle_term_list(command, &mut config.blocklist, "blocklist")
+        }
+        ConfigCommand::Allowlist { command } => {
+            handle_term_list(command, &mut config.allowlist, "allowlist")
+        }
+        ConfigCommand::TitleNoise { command } => {
+            handle_term_list(command, &mut config.title_noise, "title noise list")
+        }
+        ConfigCommand::AlbumSuffixes { command } => {
+            handle_term_list(command, &mut config.album_suffixes, "album suffix list")
+        }
+        ConfigCommand::VersionExcludes { command } => handle_term_list(
+            command,
+            &mut config.version_excludes,
+            "version exclude list",
+        ),
+        ConfigCommand::Musicbrainz { command } => {
+            handle_musicbrainz_config(command, &mut config.musicbrainz)
+        }
+        ConfigCommand::Reset(args) => {
+            let mut changed = false;
+            for field in &args.fields {
+                changed |= config.reset_field(field.trim())?;
+            }
+            Ok(changed)
+        }
+    }
+}
+
+// Credentials only show whether they are set unless the user passes --reveal.
+fn display_secret(value: Option<&str>, reveal: bool) -> String {
+    match value {
+        Some(value) if reveal => value.to_string(),
+        Some(_) => "***set***".to_string(),
+        None => "not set".to_string(),
+    }
+}
+
+// A po_token inside an extractor args spec is a credential like any other.
+fn display_extractor_args(spec: &str, reveal: bool) -> String {
+    let Some((extractor, args)) = spec.split_once(':').filter(|_| !reveal) else {
+        return spec.to_string();
+    };
+    let args = args
+        .split(';')
+        .map(|arg| match arg.split_once('=') {
+            Some((key, value)) if key.trim() == "po_token" => {
+                format!("{}={}", key, display_secret(Some(value), false))
+            }
+            _ => arg.to_string(),
+        })
+        .collect::<Vec<_>>();
+    format!("{}:{}", extractor, args.join(";"))
+}
+
+fn handle_musicbrainz_config(
+    command: MusicBrainzConfigCommand,
+    config: &mut MusicBrainzConfig,
+) -> Result<bool> {
+    match command {
+        MusicBrainzConfigCommand::Set(args) => {
+            match args.key {
+                MusicBrainzKey::UserAgent => {
+                    config.user_agent = Some(validate_user_agent(&args.value)?);
+                }
+                MusicBrainzKey::Timeout => {
+                    let secs = args.value.trim().parse().map_err(|_| {
+                        AppError::Message(format!(
+                            "timeout must be a whole number of seconds, got '{}'",
+                            args.value
+                        ))
+                    })?;
+                    config.timeout_secs = Some(validate_mb_timeout(secs)?);
+                }
+                MusicBrainzKey::Url => {
+                    config.base_url = Some(validate_mb_url(&args.value)?);
+                }
+                MusicBrainzKey::Token => {
+                    config.token = Some(validate_mb_token(&args.value)?);
+                }
+                MusicBrainzKey::Locale => {
+                    config.locale = Some(validate_mb_locale(&args.value)?);
+                }
+            }
+            if matches!(args.key, MusicBrainzKey::Token) {
+                println!("set musicbrainz {}", args.key.name());
+            } else {
+                println!(
+                    "set musicbrainz {} to '{}'",
+                    args.key.name(),
+                    args.value.trim()
+                );
+            }
+            Ok(true)
+        }
+        MusicBrainzConfigCommand::Unset(args) => {
+            let was_set = match args.key {
+                MusicBrainzKey::UserAgent => config.user_agent.take().is_some(),
+                MusicBrainzKey::Timeout => config.timeout_secs.take().is_some(),
+                MusicBrainzKey::Url => config.base_url.take().is_some(),
+                MusicBrainzKey::Token => config.token.take().is_some(),
+                MusicBrainzKey::Locale => config.locale.take().is_some(),
+            };
+            if was_set {
+                println!("reset musicbrainz {} to the default", args.key.name());
+                Ok(true)
+            } else {
+                println!("musicbrainz {} was already the default", args.key.name());
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn print_setting(label: &str, value: impl std::fmt::Display) {
+    println!("{}: {}", Tone::Info.paint(label), value);
+}
+
+fn handle_term_list(
+    command: TermListCommand,
+    terms: &mut Vec<String>,
+    label: &str,
+) -> Result<bool> {
+    match command {
+        TermListCommand::Add(args) => {
+            let term = args.term.trim().to_string();
+            if term.is_empty() {
+                return Err(AppError::Message(format!(
+                    "{} term must not be empty",
+                    label
+                )));
+            }
+            if terms
+                .iter()
+                .any(|existing| existing.eq_ignore_ascii_case(&term))
+            {
+                println!("'{}' is already on the {}", term, label);
+                return Ok(false);
+            }
+            println!("added '{}' to the {}", term, label);
+            terms.push(term);
+            Ok(true)
+        }
+        TermListCommand::Remove(args) => {
+            let before = terms.len();
+            terms.retain(|existing| !existing.eq_ignore_ascii_case(args.term.trim()));
+            if terms.len() == before {
+                Err(AppError::Message(format!(
+                    "'{}' is not on the {}",
+                    args.term, label
+                )))
+            } else {
+                println!("removed '{}' from the {}", args.term, label);
+                Ok(true)
+            }
+        }
+        TermListCommand::List => {
+            if terms.is_empty() {
+                println!("{} is empty", label);
+            } else {
+                for term in terms.iter() {
+                    println!("{}", term);
+                }
+            }
+            Ok(false)
+        }
+    }
+}
+
+// --dest wins, then the destination configured for the requested format, then
+// the default destination.
+fn resolve_destination(dest: Option<&Path>, config: &AppConfig, format: &str) -> Result<PathBuf> {
+    if let Some(dest) = dest {
+        ensure_absolute(dest)
+    } else if let Some(format_dest) = config.format_destinations.get(format) {
+        Ok(format_dest.clone())
+    } else if let Some(config_dest) = &config.default_destination {
+        Ok(config_dest.clone())
+    } else {
+        Ok(std::env::current_dir()?)
+    }
+}
+
+// create_dir_all on an existing file fails with a bare "File exists".
+fn ensure_directory_path(path: &Path, label: &str) -> Result<()> {
+    if path.exists() && !path.is_dir() {
+        return Err(AppError::Message(format!(
+            "{} {} is a file, not a directory",
+            label,
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn ensure_absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AppConfig {
+    #[serde(default)]
+    default_destination: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    format_destinations: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    aliases: BTreeMap<String, AliasEntry>,
+    #[serde(default)]
+    blocklist: Vec<String>,
+    #[serde(default)]
+    allowlist: Vec<String>,
+    #[serde(default)]
+    musicbrainz: MusicBrainzConfig,
+    #[serde(default = "default_title_noise")]
+    title_noise: Vec<String>,
+    #[serde(default = "default_version_excludes")]
+    version_excludes: Vec<String>,
+    #[serde(default)]
+    strip_album_suffix: bool,
+    #[serde(default = "default_album_suffixes")]
+    album_suffixes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_free_space: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tmp_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    single_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    album_template: Option<String>,
+    #[serde(default)]
+    prefer_ytmusic: bool,
+    // An empty keyword turns the suffix off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    album_keyword: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    impersonate: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extractor_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    po_token: Option<String>,
+    // Fields that failed to load, with the reason; they hold their defaults.
+    #[serde(skip)]
+    invalid_fields: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MusicBrainzConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+}
+
+impl AppConfig {
+    fn min_free_space(&self) -> u64 {
+        self.min_free_space.unwrap_or(DEFAULT_MIN_FREE_SPACE)
+    }
+
+    fn album_keyword(&self) -> &str {
+        self.album_keyword
+            .as_deref()
+            .unwrap_or(DEFAULT_ALBUM_KEYWORD)
+    }
+
+    fn search_site(&self) -> SearchSite {
+        if self.prefer_ytmusic {
+            SearchSite::YouTubeMusic
+        } else {
+            SearchSite::YouTube
+        }
+    }
+
+    fn template(&self, kind: TemplateKind) -> &str {
+        let default = match kind {
+            TemplateKind::Single => DEFAULT_SINGLE_TEMPLATE,
+            TemplateKind::Album => DEFAULT_ALBUM_TEMPLATE,
+        };
+        self.custom_template(kind).unwrap_or(default)
+    }
+
+    fn custom_template(&self, kind: TemplateKind) -> Option<&str> {
+        match kind {
+            TemplateKind::Single => self.single_template.as_deref(),
+            TemplateKind::Album => self.album_template.as_deref(),
+        }
+    }
+
+    fn template_slot(&mut self, kind: TemplateKind) -> &mut Option<String> {
+        match kind {
+            TemplateKind::Single => &mut self.single_template,
+            TemplateKind::Album => &mut self.album_template,
+        }
+    }
+
+    fn load() -> Result<Self> {
+        let config = Self::load_lenient()?;
+        config.ensure_valid(&config_file_path()?)?;
+        Ok(config)
+    }
+
+    // Used by `config`, the command that repairs a hand-edited file: fields with
+    // the wrong type keep their defaults and are listed in `invalid_fields`.
+    fn load_lenient() -> Result<Self> {
+        let path = config_file_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(&path)?;
+        if data.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut config = Self::from_json_lenient(&path, &data)?;
+        if config.default_destination.is_none() && !config.is_invalid("default_destination") {
+            config.default_destination = default_music_dir();
+        }
+        Ok(config)
+    }
+
+    // Broken JSON is always an error; a field with the wrong type is dropped and
+    // recorded so the caller decides whether to fail or carry on without it.
+    fn from_json_lenient(path: &Path, data: &[u8]) -> Result<Self> {
+        let invalid = |detail: String| AppError::InvalidConfig {
+            path: path.to_path_buf(),
+            detail,
+        };
+        let value: serde_json::Value =
+            serde_json::from_slice(data).map_err(|err| invalid(err.to_string()))?;
+        let err = match serde_json::from_value(value.clone()) {
+            Ok(config) => return Ok(config),
+            Err(err) => err,
+        };
+        let serde_json::Value::Object(mut fields) = value else {
+            return Err(invalid(err.to_string()));
+        };
+
+        let mut broken: Vec<(String, String)> = Vec::new();
+        for (key, field) in fields.iter_mut() {
+            if key == "aliases"
+                && let Some(aliases) = field.as_object_mut()
+            {
+                aliases.retain(|name, entry| {
+                    match serde_json::from_value::<AliasEntry>(entry.clone()) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            broken.push((format!("aliases.{}", name), err.to_string()));
+                            false
+                        }
+                    }
+                });
+                continue;
+            }
+            let single = serde_json::json!({ key.as_str(): field });
+            if let Err(err) = serde_json::from_value::<Self>(single) {
+                broken.push((key.clone(), err.to_string()));
+            }
+        }
+        if broken.is_empty() {
+            return Err(invalid(err.to_string()));
+        }
+        fields.retain(|key, _| !broken.iter().any(|(name, _)| name == key));
+        let mut config: Self = serde_json::from_value(serde_json::Value::Object(fields))
+            .map_err(|err| invalid(err.to_string()))?;
+        config.invalid_fields = broken;
+        Ok(config)
+    }
+
+    fn is_invalid(&self, field: &str) -> bool {
+        self.invalid_fields.iter().any(|(name, _)| name == field)
+    }
+
+    // Dropping the bad entries would lose them on the next save, so commands
+    // other than `config` fail instead, naming each field to fix or reset.
+    fn ensure_valid(&self, path: &Path) -> Result<()> {
+        if self.invalid_fields.is_empty() {
+            return Ok(());
+        }
+        let fields = self
+            .invalid_fields
+            .iter()
+            .map(|(name, detail)| format!("'{}' ({})", name, detail))
+            .collect::<Vec<_>>();
+        let names = self
+            .invalid_fields
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Err(AppError::InvalidConfig {
+            path: path.to_path_buf(),
+            detail: format!(
+                "invalid {}; fix {} or run `{} config reset {}` to use the default",
+                fields.join(", "),
+                if fields.len() == 1 { "it" } else { "them" },
+                APP_NAME,
+                names
+            ),
+        })
+    }
+
+    // Removing a field from the stored JSON is what resets it: serde fills
+    // in its default on the way back.
+    fn reset_field(&mut self, field: &str) -> Result<bool> {
+        let was_invalid = self.is_invalid(field);
+        self.invalid_fields.retain(|(name, _)| name != field);
+        let removed = if let Some(alias) = field.strip_prefix("aliases.") {
+            self.aliases.remove(alias).is_some()
+        } else {
+            let invalid_fields = std::mem::take(&mut self.invalid_fields);
+            let mut value = serde_json::to_value(&*self)?;
+            let removed = value
+                .as_object_mut()
+                .and_then(|fields| fields.remove(field))
+                .is_some();
+            *self = serde_json::from_value(value)?;
+            self.invalid_fields = invalid_fields;
+            removed
+        };
+        if was_invalid || removed {
+            println!("reset '{}' to the default", field);
+            Ok(true)
+        } else {
+            println!("'{}' is not set in the config", field);
+            Ok(false)
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = config_file_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(self)?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Keep stored credentials out of reach of other local users: a new file
+        // is created private, an existing one is tightened before anything is written.
+        #[cfg(unix)]
+        if self.has_secrets() {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        #[cfg(unix)]
+        if self.has_secrets() {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(&json)?;
+        Ok(())
+    }
+
+    fn has_secrets(&self) -> bool {
+        self.musicbrainz.token.is_some()
+            || self.po_token.is_some()
+            || self
+                .extractor_args
+                .iter()
+                .any(|spec| spec.contains("po_token"))
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            default_destination: default_music_dir(),
+            format_destinations: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            blocklist: Vec::new(),
+            allowlist: Vec::new(),
+            musicbrainz: MusicBrainzConfig::default(),
+            title_noise: default_title_noise(),
+            version_excludes: default_version_excludes(),
+            strip_album_suffix: false,
+            album_suffixes: default_album_suffixes(),
+            min_free_space: None,
+            tmp_dir: None,
+            single_template: None,
+            album_template: None,
+            prefer_ytmusic: false,
+            album_keyword: None,
+            impersonate: None,
+            extractor_args: Vec::new(),
+            po_token: None,
+            invalid_fields: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AliasEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default)]
+    album: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_synced: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mbid: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    track_urls: Vec<String>,
+}
+
+struct ConfigLock {
+    _file: fs::File,
+}
+
+impl ConfigLock {
+    fn acquire() -> Result<Self> {
+        let path = config_lock_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+
+        let deadline = Instant::now() + CONFIG_LOCK_TIMEOUT;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                    if Instant::now() >= deadline {
+                        return Err(AppError::Message(format!(
+                            "another bippi process is modifying the config (lock held on {}); try again shortly",
+                            path.display()
+                        )));
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(err) => return Err(AppError::Io(err)),
+            }
+        }
+    }
+}
+
+fn config_lock_path() -> Result<PathBuf> {
+    let mut base = dirs::config_dir().ok_or(AppError::MissingConfigDir)?;
+    base.push(APP_NAME);
+    base.push(CONFIG_LOCK_FILENAME);
+    Ok(base)
+}
+
+fn config_file_path() -> Result<PathBuf> {
+    let mut base = dirs::config_dir().ok_or(AppError::MissingConfigDir)?;
+    base.push(APP_NAME);
+    base.push(CONFIG_FILENAME);
+    Ok(base)
+}
+
+fn default_title_noise() -> Vec<String> {
+    DEFAULT_TITLE_NOISE
+        .iter()
+        .map(|term| term.to_string())
+        .collect()
+}
+
+fn default_version_excludes() -> Vec<String> {
+    DEFAULT_VERSION_EXCLUDES
+        .iter()
+        .map(|term| term.to_string())
+        .collect()
+}
+
+fn default_album_suffixes() -> Vec<String> {
+    DEFAULT_ALBUM_SUFFIXES
+        .iter()
+        .map(|term| term.to_string())
+        .collect()
+}
+
+fn default_music_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join("music"))
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about = "Download music from YouTube and other sources",
+    propagate_version = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Skip MusicBrainz and other metadata lookups; album downloads then need a URL or alias
+    #[arg(long, global = true)]
+    offline: bool,
+    /// User agent sent to MusicBrainz, ideally with contact info (overrides the config)
+    #[arg(long, global = true, value_name = "UA")]
+    user_agent: Option<String>,
+    /// Seconds to wait for each MusicBrainz response [default: 15]
+    #[arg(long, global = true, value_name = "SECS")]
+    mb_timeout: Option<u64>,
+    /// MusicBrainz web service root to query instead of musicbrainz.org (e.g. a local mirror)
+    #[arg(long, global = true, value_name = "URL")]
+    mb_url: Option<String>,
+    /// Name artists by their MusicBrainz alias in this locale, e.g. en or ja (overrides the config)
+    #[arg(long, global = true, value_name = "LOCALE")]
+    mb_locale: Option<String>,
+    /// Print plain text without colors (also honors NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Download a single track using a URL, alias, or search
+    Single(DownloadArgs),
+    /// Download an entire album/playlist
+    Album(DownloadArgs),
+    /// Manage human-friendly aliases for URLs
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+    /// Configure default download settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Download only items added to an alias's playlist since its last sync
+    Sync(SyncArgs),
+    /// Check downloaded files against a MusicBrainz release
+    Verify(VerifyArgs),
+    /// Rewrite the tags of existing files from a MusicBrainz release (no re-download)
+    Retag(RetagArgs),
+    /// Delete partial and intermediate files left behind by interrupted downloads
+    /// (files changed in the last 10 minutes are kept, as a download may be running)
+    Clean(CleanArgs),
+    /// Delete audio files whose names and title tags match no track of a MusicBrainz release
+    Prune(PruneArgs),
+    /// Update yt-dlp with whichever tool installed it (yt-dlp -U, pip, pipx or Homebrew)
+    UpdateYtdlp,
+    /// Print bippi's version (with --full, also yt-dlp's and ffmpeg's for bug reports)
+    Version(VersionArgs),
+}
+
+#[derive(Args, Debug)]
+struct VersionArgs {
+    /// Also report the yt-dlp and ffmpeg versions in use and the platform
+    #[arg(long)]
+    full: bool,
+}
+
+#[derive(Args, Debug)]
+struct CleanArgs {
+    /// Directory to clean, including subfolders [default: the default destination]
+    dir: Option<PathBuf>,
+    /// List the files that would be deleted without deleting them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct PruneArgs {
+    /// Directory containing the album's audio files
+    dir: PathBuf,
+    /// MusicBrainz release ID (or release URL) whose track names to keep
+    #[arg(long, value_name = "MBID", conflicts_with = "release")]
+    mbid: Option<String>,
+    /// Release to look up as "Artist - Album" instead of reading the files' tags
+    #[arg(long, value_name = "QUERY")]
+    release: Option<String>,
+    /// File name template the album was downloaded with [default: the configured album template]
+    #[arg(long, value_name = "TEMPLATE")]
+    output_template: Option<String>,
+    /// The album was downloaded with --flat-numbering
+    #[arg(long)]
+    flat_numbering: bool,
+    /// The album was downloaded with --no-sanitize
+    #[arg(long)]
+    no_sanitize: bool,
+    /// List the files that would be deleted without deleting them
+    #[arg(long)]
+    dry_run: bool,
+    /// Delete without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Directory containing the album's audio files
+    dir: PathBuf,
+    /// MusicBrainz release ID (or release URL) to check against
+    #[arg(long, value_name = "MBID", conflicts_with = "release")]
+    mbid: Option<String>,
+    /// Release to look up as "Artist - Album" instead of reading the files' tags
+    #[arg(long, value_name = "QUERY")]
+    release: Option<String>,
+    /// Allowed difference in seconds between a file and the MusicBrainz track length
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_VERIFY_TOLERANCE_SECS)]
+    tolerance: f64,
+}
+
+#[derive(Args, Debug)]
+struct RetagArgs {
+    /// Directory containing the album's audio files
+    dir: PathBuf,
+    /// MusicBrainz release ID (or release URL) to take the tags from
+    #[arg(long, value_name = "MBID", conflicts_with = "release")]
+    mbid: Option<String>,
+    /// Release to look up as "Artist - Album" instead of reading the files' tags
+    #[arg(long, value_name = "QUERY")]
+    release: Option<String>,
+    /// Pair files with tracks in sorted filename order instead of by number and title
+    #[arg(long)]
+    by_order: bool,
+    /// Show which file gets which track's tags without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Tag as a various-artists compilation with each track's own artist
+    #[arg(long)]
+    compilation: bool,
+    /// Which MusicBrainz date goes into the date tag
+    #[arg(long, value_enum, default_value_t = DateSource::Release)]
+    date_source: DateSource,
+}
+
+#[derive(Args, Debug)]
+struct SyncArgs {
+    #[command(flatten)]
+    download: DownloadArgs,
+    /// Ignore the stored sync date and re-check every item (archived items are still skipped)
+    #[arg(long)]
+    full: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+struct DownloadArgs {
+    /// URL, alias name, or free-form search query ('-' reads one target per line from stdin)
+    #[arg(value_name = "TARGET", num_args = 1..)]
+    target: Vec<String>,
+    /// Destination directory for the downloaded audio
+    #[arg(short, long)]
+    dest: Option<PathBuf>,
+    /// Audio format (mp3, m4a, flac ...)
+    #[arg(short, long, default_value = "mp3")]
+    format: String,
+    /// Formats to retry with, in order, when yt-dlp can't fetch or convert to --format (e.g. m4a,mp3); not applied to playlist downloads
+    #[arg(long, value_name = "FORMATS", value_delimiter = ',')]
+    format_fallback: Vec<String>,
+    /// Only download items uploaded on or after this date (YYYYMMDD or now-7days)
+    #[arg(long, value_name = "DATE")]
+    since: Option<String>,
+    /// Like --since, with the date taken from this file's mtime; touched after a successful run
+    #[arg(long, value_name = "PATH", conflicts_with = "since")]
+    since_file: Option<PathBuf>,
+    /// Record downloaded IDs in this file and skip anything already listed
+    #[arg(long, value_name = "FILE")]
+    download_archive: Option<PathBuf>,
+    /// Download playlists in reverse order (oldest-first); track numbers follow the new order
+    #[arg(long)]
+    reverse: bool,
+    /// yt-dlp format sort spec used to pick the source stream (e.g. acodec:flac,abr)
+    #[arg(long, value_name = "SPEC")]
+    format_sort: Option<String>,
+    /// Ask yt-dlp to bypass geographic restrictions with a faked X-Forwarded-For header
+    #[arg(long)]
+    geo_bypass: bool,
+    /// Two-letter country code to pretend to be in when bypassing geo restrictions
+    #[arg(long, value_name = "CC")]
+    geo_bypass_country: Option<String>,
+    /// Use a browser's cookies, e.g. firefox, "chrome:Profile 1" or "firefox::Personal"
+    #[arg(long, value_name = "BROWSER[:PROFILE][::CONTAINER]")]
+    cookies_from_browser: Option<String>,
+    /// Mimic a browser's TLS fingerprint, e.g. chrome or safari (needs a curl_cffi-enabled yt-dlp)
+    #[arg(long, value_name = "TARGET")]
+    impersonate: Option<String>,
+    /// Don't impersonate a browser even if the config sets a default
+    #[arg(long, conflicts_with = "impersonate")]
+    no_impersonate: bool,
+    /// Pass extractor arguments to yt-dlp, e.g. "youtube:player_client=web" (repeatable)
+    #[arg(long, value_name = "SPEC")]
+    extractor_args: Vec<String>,
+    /// YouTube PO token for bot checks; shorthand for --extractor-args "youtube:po_token=web.gvs+TOKEN"
+    #[arg(long, value_name = "TOKEN")]
+    po_token: Option<String>,
+    /// Minimum title similarity (0-1) for a YouTube result to be used for a MusicBrainz track
+    #[arg(long, value_name = "SCORE", default_value_t = DEFAULT_MATCH_THRESHOLD)]
+    match_threshold: f64,
+    /// Hide yt-dlp's own output and only show its errors when a download fails
+    #[arg(long)]
+    quiet_ytdlp: bool,
+    /// Don't embed the source's metadata (title, uploader, playlist fields) in the audio file
+    #[arg(long)]
+    no_embed_metadata: bool,
+    /// Print MusicBrainz album progress as JSON lines on stdout; other output goes to stderr
+    #[arg(long, conflicts_with = "stdout")]
+    progress_json: bool,
+    /// Skip playlist/search items shorter than this many seconds
+    #[arg(long, value_name = "SECS")]
+    min_duration: Option<u64>,
+    /// Skip playlist/search items longer than this many seconds
+    #[arg(long, value_name = "SECS")]
+    max_duration: Option<u64>,
+    /// Skip items whose download would be larger than this (e.g. 50M, 1.5G)
+    #[arg(long, value_name = "SIZE")]
+    max_filesize: Option<String>,
+    /// Only accept search results from allowlisted uploaders
+    #[arg(long)]
+    strict_allowlist: bool,
+    /// Always download the whole playlist, even from a watch URL with a list= param
+    #[arg(long, conflicts_with = "no_playlist")]
+    yes_playlist: bool,
+    /// Only download the single video, even when the URL references a playlist
+    #[arg(long)]
+    no_playlist: bool,
+    /// For watch URLs with a list= param, download only the referenced playlist
+    #[arg(long, conflicts_with_all = ["video_only", "no_playlist"])]
+    playlist_only: bool,
+    /// For watch URLs with a list= param, download only the referenced video
+    #[arg(long, conflicts_with = "yes_playlist")]
+    video_only: bool,
+    /// Allow downloading a YouTube mix (RD... list) as a playlist, capped at --mix-limit items
+    #[arg(long)]
+    allow_mix: bool,
+    /// How many items of a mix to download with --allow-mix
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MIX_LIMIT, requires = "allow_mix")]
+    mix_limit: u32,
+    /// Keep the source video file next to the extracted audio
+    #[arg(long)]
+    keep_video: bool,
+    /// Save subtitles/captions in these languages as sidecar files (e.g. en or en,de)
+    #[arg(long, value_name = "LANGS")]
+    subtitles: Option<String>,
+    /// Convert fetched subtitles to an .lrc lyrics file next to each track
+    #[arg(long, requires = "subtitles")]
+    lyrics_from_subs: bool,
+    /// Save each item's thumbnail as a separate image file (not embedded)
+    #[arg(long)]
+    write_thumbnail: bool,
+    /// Tag a MusicBrainz album as a various-artists compilation with per-track artists
+    #[arg(long)]
+    compilation: bool,
+    /// Which MusicBrainz date goes into the date tag of album tracks
+    #[arg(long, value_enum, default_value_t = DateSource::Release)]
+    date_source: DateSource,
+    /// Skip MusicBrainz album tracks whose title repeats one already downloaded this run
+    #[arg(long)]
+    dedupe: bool,
+    /// Start partial downloads over instead of resuming them
+    #[arg(long)]
+    no_continue: bool,
+    /// Like --no-continue, and delete leftover partial files (except ones changed in the last 10 minutes) under the destination and temp dir first
+    #[arg(long)]
+    restart: bool,
+    /// Keep yt-dlp's .part and intermediate files here instead of in the destination
+    #[arg(long, value_name = "PATH")]
+    tmp_dir: Option<PathBuf>,
+    /// File name template for this run, e.g. "{track} - {artist} - {title}" (see README)
+    #[arg(long, value_name = "TEMPLATE")]
+    output_template: Option<String>,
+    /// Number album tracks and playlist items from N, e.g. to continue a split album
+    #[arg(long, value_name = "N", conflicts_with = "track_number")]
+    numbering_start: Option<u32>,
+    /// Fail instead of downloading when a search finds fewer than N results (1-10)
+    #[arg(long, value_name = "N")]
+    min_results: Option<usize>,
+    /// Where album searches look up the release's track list
+    #[arg(long, value_enum, value_name = "SOURCE", default_value_t = MetadataSource::Musicbrainz)]
+    metadata_source: MetadataSource,
+    /// Fall back to a YouTube album search when MusicBrainz errors or times out, not only when it finds nothing
+    #[arg(long, conflicts_with = "mb_only")]
+    continue_on_mb_error: bool,
+    /// Fail instead of falling back to YouTube when MusicBrainz finds no release for an album
+    #[arg(long)]
+    mb_only: bool,
+    /// After a successful album download, also pack its tracks and cover art into this zip file
+    #[arg(long, value_name = "PATH")]
+    zip: Option<PathBuf>,
+    /// Remove the loose files once they are in the --zip archive
+    #[arg(long, requires = "zip")]
+    zip_only: bool,
+    /// Show a desktop notification when the download finishes or fails
+    #[arg(long)]
+    notify: bool,
+    /// Resolve and extract everything with yt-dlp --simulate, without writing any files
+    #[arg(long, conflicts_with_all = ["stdout", "zip", "restart", "summary", "write_m3u"])]
+    simulate: bool,
+    /// Stop after downloading this many items (handy for trying out an alias or a huge playlist)
+    #[arg(long, value_name = "N")]
+    max_downloads: Option<u32>,
+    /// Search YouTube Music's songs instead of YouTube for tracks
+    #[arg(long, conflicts_with = "no_ytmusic")]
+    ytmusic: bool,
+    /// Search regular YouTube even if the config prefers YouTube Music
+    #[arg(long)]
+    no_ytmusic: bool,
+    /// Drop edition suffixes like "(Deluxe Edition)" from album titles in folder names and tags
+    #[arg(long, conflicts_with = "no_strip_album_suffix")]
+    strip_album_suffix: bool,
+    /// Keep album titles as they are even if the config strips suffixes
+    #[arg(long)]
+    no_strip_album_suffix: bool,
+    /// Download only this disc of a multi-disc MusicBrainz release
+    #[arg(long, value_name = "N")]
+    disc: Option<u32>,
+    /// Number multi-disc releases 01..N across all discs instead of disc-track (01-03)
+    #[arg(long)]
+    flat_numbering: bool,
+    /// Keep MusicBrainz titles as-is in file names except '/' and '\' (needs a filesystem allowing :, ?, * etc.)
+    #[arg(long)]
+    no_sanitize: bool,
+    /// Shorten file names so the full path stays within this many characters
+    #[arg(long, value_name = "LEN", default_value_t = DEFAULT_MAX_PATH_LENGTH)]
+    limit_filename_length: usize,
+    /// Print the number of files, their size, the elapsed time and the destination at the end
+    #[arg(long)]
+    summary: bool,
+    /// List the top YouTube results for a single-track search and pick one
+    #[arg(long)]
+    interactive: bool,
+    /// List the top MusicBrainz releases for an album search and pick one
+    #[arg(long)]
+    choose: bool,
+    /// Strip noise like "(Official Audio)" or "[HD]" from MusicBrainz track titles
+    #[arg(long)]
+    clean_titles: bool,
+    /// Avoid live, remix and cover uploads when matching album tracks on YouTube
+    #[arg(long)]
+    prefer_album_version: bool,
+    /// Search album tracks credited "(feat. X)" only as written, not also as "ft. X" and without X
+    #[arg(long)]
+    no_feat_variants: bool,
+    /// Normalize the casing of MusicBrainz track titles before naming and tagging
+    #[arg(long, value_enum, default_value_t = TitleCase::Keep)]
+    case: TitleCase,
+    /// Write the MusicBrainz release URL into each track's comment tag
+    #[arg(long)]
+    embed_source_url: bool,
+    /// Save each item's yt-dlp .info.json (upload date, view count, ...) next to the audio
+    #[arg(long)]
+    write_info_json: bool,
+    /// Save each item's video description as a .description file next to the audio
+    #[arg(long)]
+    write_description: bool,
+    /// Save the playlist's title, uploader and items as a .playlist.json manifest in the destination
+    #[arg(long)]
+    write_playlist_metadata: bool,
+    /// After a MusicBrainz album download, write an .m3u8 playlist of its tracks in track order
+    #[arg(long, conflicts_with = "zip_only")]
+    write_m3u: bool,
+    /// Convert saved thumbnails to this image format
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        requires = "write_thumbnail",
+        value_parser = ["jpg", "png", "webp"]
+    )]
+    convert_thumbnails: Option<String>,
+    /// Normalize each track's loudness with ffmpeg's two-pass loudnorm (EBU R128)
+    #[arg(long)]
+    normalize: bool,
+    /// Apply a single gain to every track so the whole album hits the target loudness
+    #[arg(long)]
+    normalize_album: bool,
+    /// Target integrated loudness in LUFS for normalization [default: -14]
+    #[arg(long, value_name = "LUFS", allow_negative_numbers = true)]
+    normalize_target: Option<f64>,
+    /// Write ReplayGain track/album tags without re-encoding the audio
+    #[arg(long)]
+    replaygain: bool,
+    /// Only write album ReplayGain tags (and R128 for Opus), measured in one pass over all tracks
+    #[arg(long, conflicts_with = "replaygain")]
+    album_gain_only: bool,
+    /// Strip leading and trailing silence from each track with ffmpeg
+    #[arg(long)]
+    trim_silence: bool,
+    /// Level in dB below which audio counts as silence when trimming [default: -60]
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    silence_threshold: Option<f64>,
+    /// Fade each track in over this many seconds
+    #[arg(long, value_name = "SECS")]
+    fade_in: Option<f64>,
+    /// Fade each track out over this many seconds
+    #[arg(long, value_name = "SECS")]
+    fade_out: Option<f64>,
+    /// Skip the free disk space check before album downloads
+    #[arg(long)]
+    no_space_check: bool,
+    /// Print the playlist's total duration and an estimated download size before downloading
+    #[arg(long)]
+    estimate: bool,
+    /// Save playlists and MusicBrainz albums in a folder named after the playlist or album
+    #[arg(long, conflicts_with_all = ["flatten", "track_number"])]
+    nest: bool,
+    /// With --nest, save the album art (or the first track's thumbnail) in the album folder
+    #[arg(long, requires = "nest")]
+    thumbnail_as_folder_jpg: bool,
+    /// File name for --thumbnail-as-folder-jpg, e.g. folder.jpg
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "cover.jpg",
+        requires = "thumbnail_as_folder_jpg"
+    )]
+    cover_name: String,
+    /// Save everything straight into the destination folder (default)
+    #[arg(long)]
+    flatten: bool,
+    /// Prefix the file name with this zero-padded number and tag it as the track number (single only)
+    #[arg(long, value_name = "N")]
+    track_number: Option<u32>,
+    /// Look the target up on MusicBrainz and write its tags onto --file instead of downloading
+    #[arg(long, requires = "file")]
+    write_tags_only: bool,
+    /// Existing audio file for --write-tags-only
+    #[arg(long, value_name = "PATH", requires = "write_tags_only")]
+    file: Option<PathBuf>,
+    /// Stream the best audio stream to stdout instead of saving a file (single only)
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "dest", "format", "format_fallback", "yes_playlist", "playlist_only", "keep_video",
+            "subtitles", "write_thumbnail", "write_info_json", "write_description", "normalize",
+            "normalize_album", "replaygain", "album_gain_only", "trim_silence", "fade_in", "fade_out",
+            "track_number", "nest", "estimate", "max_filesize", "summary", "tmp_dir", "restart",
+            "output_template", "limit_filename_length", "zip", "write_playlist_metadata",
+            "write_m3u",
+        ]
+    )]
+    stdout: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum MetadataSource {
+    /// MusicBrainz, or the mirror set with --mb-url
+    Musicbrainz,
+}
+
+impl MetadataSource {
+    fn resolver(self, musicbrainz: &MusicBrainzSettings) -> Result<Box<dyn MetadataResolver>> {
+        match self {
+            MetadataSource::Musicbrainz => Ok(Box::new(MusicBrainzClient::new(musicbrainz)?)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum SearchSite {
+    /// Regular YouTube search
+    #[value(name = "youtube")]
+    YouTube,
+    /// YouTube Music's song results, which favor official audio
+    #[value(name = "ytmusic")]
+    YouTubeMusic,
+}
+
+impl SearchSite {
+    fn name(self) -> &'static str {
+        match self {
+            SearchSite::YouTube => "YouTube",
+            SearchSite::YouTubeMusic => "YouTube Music",
+        }
+    }
+
+    // YouTube Music only lists songs, so the "audio" and "-music video" hints
+    // regular YouTube needs would only narrow its results.
+    fn query_terms(self, query: &str) -> String {
+        match self {
+            SearchSite::YouTube => build_search_terms(query),
+            SearchSite::YouTubeMusic => query.trim().to_string(),
+        }
+    }
+
+    // yt-dlp has no search prefix for YouTube Music; its search page URL with the
+    // "songs" section selected lists tracks rather than videos.
+    fn search_target(self, terms: &str, count: usize) -> String {
+        match self {
+            SearchSite::YouTube => format!("ytsearch{}:{}", count, terms),
+            SearchSite::YouTubeMusic => {
+                format!("https://music.youtube.com/search?q={}#songs", encode(terms))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum DateSource {
+    /// The date of this release (reissues and remasters get their own year)
+    #[default]
+    Release,
+    /// The first release date of the release group
+    Original,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum TitleCase {
+    /// Leave titles as they are
+    Keep,
+    /// Capitalize words, keeping small words like "of" and "the" lower-case
+    Title,
+    /// Lower-case everything
+    Lower,
+    /// Upper-case everything
+    Upper,
+}
+
+impl TitleCase {
+    fn apply(self, title: &str) -> String {
+        match self {
+            TitleCase::Keep => title.to_string(),
+            TitleCase::Title => title_case(title),
+            TitleCase::Lower => title.to_lowercase(),
+            TitleCase::Upper => title.to_uppercase(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasCommand {
+    /// Create or update an alias mapped to a URL
+    Add(AliasAddArgs),
+    /// Remove an alias
+    Remove(AliasRemoveArgs),
+    /// List all aliases
+    List,
+    /// Check that an alias's URL still resolves, without downloading
+    Test(AliasTestArgs),
+}
+
+#[derive(Args, Debug)]
+struct AliasTestArgs {
+    /// Alias name to test
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    name: Option<String>,
+    /// Test every alias and print a status table
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Args, Debug)]
+struct AliasAddArgs {
+    /// Short name for the alias (e.g. "focus")
+    name: String,
+    /// URL that the alias resolves to
+    #[arg(required_unless_present = "mbid")]
+    url: Option<String>,
+    /// Mark the alias as an album/playlist
+    #[arg(long)]
+    album: bool,
+    /// MusicBrainz release ID (or release URL) to download instead of searching
+    #[arg(long, value_name = "MBID")]
+    mbid: Option<String>,
+    /// Fail instead of replacing an existing alias with the same name
+    #[arg(long, conflicts_with = "force")]
+    no_overwrite: bool,
+    /// Replace an existing alias without a warning
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args, Debug)]
+struct AliasRemoveArgs {
+    /// Alias name to remove
+    name: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Set the default download destination directory
+    SetDest(ConfigSetDestArgs),
+    /// Show the current configuration
+    Show(ConfigShowArgs),
+    /// Clear the default download destination
+    ClearDest,
+    /// Save downloads of one format to their own directory
+    SetFormatDest(ConfigSetFormatDestArgs),
+    /// Remove the directory set for a format
+    ClearFormatDest(ConfigClearFormatDestArgs),
+    /// Set how much disk space must stay free after an album download
+    SetMinFree(ConfigSetMinFreeArgs),
+    /// Keep yt-dlp's partial and intermediate files in this directory by default
+    SetTmpDir(ConfigSetTmpDirArgs),
+    /// Keep intermediate files in the destination again
+    ClearTmpDir,
+    /// Make yt-dlp impersonate a browser by default (needs curl_cffi)
+    SetImpersonate(ConfigSetImpersonateArgs),
+    /// Stop impersonating a browser by default
+    ClearImpersonate,
+    /// Manage yt-dlp --extractor-args specs passed to every download
+    ExtractorArgs {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Send this YouTube PO token with every download
+    SetPoToken(ConfigSetPoTokenArgs),
+    /// Stop sending a PO token
+    ClearPoToken,
+    /// Set the file name template for single tracks or album tracks
+    SetTemplate(ConfigSetTemplateArgs),
+    /// Reset a file name template to its default
+    ClearTemplate(ConfigClearTemplateArgs),
+    /// Choose where track searches look by default
+    SetSearch(ConfigSetSearchArgs),
+    /// Set the word added to YouTube album playlist searches ("" for none)
+    SetAlbumKeyword(ConfigSetAlbumKeywordArgs),
+    /// Manage terms that exclude search results by title or uploader
+    Blocklist {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Manage trusted uploaders whose search results are preferred
+    Allowlist {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Manage the title noise terms removed by --clean-titles
+    TitleNoise {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Manage the version terms (live, remix, ...) avoided by --prefer-album-version
+    VersionExcludes {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Strip edition suffixes like "(Deluxe Edition)" from album titles by default
+    SetStripAlbumSuffix(ConfigSetStripAlbumSuffixArgs),
+    /// Manage the edition words that --strip-album-suffix looks for
+    AlbumSuffixes {
+        #[command(subcommand)]
+        command: TermListCommand,
+    },
+    /// Configure how bippi talks to MusicBrainz
+    Musicbrainz {
+        #[command(subcommand)]
+        command: MusicBrainzConfigCommand,
+    },
+    /// Reset config fields to their defaults, e.g. ones that no longer load
+    Reset(ConfigResetArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum MusicBrainzConfigCommand {
+    /// Set a MusicBrainz setting
+    Set(MusicBrainzSetArgs),
+    /// Reset a MusicBrainz setting to its default
+    Unset(MusicBrainzUnsetArgs),
+}
+
+#[derive(Args, Debug)]
+struct MusicBrainzSetArgs {
+    /// Setting to change
+    key: MusicBrainzKey,
+    /// New value
+    value: String,
+}
+
+#[derive(Args, Debug)]
+struct MusicBrainzUnsetArgs {
+    /// Setting to reset
+    key: MusicBrainzKey,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum MusicBrainzKey {
+    /// User agent sent with every request
+    UserAgent,
+    /// Request timeout in seconds
+    Timeout,
+    /// Web service root of the MusicBrainz server or mirror
+    Url,
+    /// OAuth token sent as a bearer Authorization header; allows faster requests
+    Token,
+    /// Locale whose artist aliases replace credited names, e.g. en or ja
+    Locale,
+}
+
+impl MusicBrainzKey {
+    fn name(self) -> &'static str {
+        match self {
+            MusicBrainzKey::UserAgent => "user-agent",
+            MusicBrainzKey::Timeout => "timeout",
+            MusicBrainzKey::Url => "url",
+            MusicBrainzKey::Token => "token",
+            MusicBrainzKey::Locale => "locale",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct ConfigShowArgs {
+    /// Print credentials such as the MusicBrainz token or PO token instead of masking them
+    #[arg(long)]
+    reveal: bool,
+}
+
+#[derive(Args, Debug)]
+struct ConfigResetArgs {
+    /// Field names as they appear in config.json (aliases.<NAME> for one alias)
+    #[arg(required = true)]
+    fields: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetDestArgs {
+    /// Directory path where downloads should be saved by default
+    path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetFormatDestArgs {
+    /// Audio format the directory is used for (e.g. flac)
+    format: String,
+    /// Directory path where downloads in that format should be saved
+    path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetPoTokenArgs {
+    /// PO token, optionally prefixed with its CLIENT.CONTEXT (e.g. mweb.gvs+TOKEN)
+    token: String,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetImpersonateArgs {
+    /// Impersonation target such as chrome or safari
+    target: String,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetTmpDirArgs {
+    /// Directory on fast local storage for intermediate files
+    path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetAlbumKeywordArgs {
+    /// Keyword such as "album" or "álbum"; an empty string adds nothing
+    keyword: String,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetStripAlbumSuffixArgs {
+    /// true to strip the suffixes, false to keep album titles as they are
+    #[arg(action = clap::ArgAction::Set)]
+    enabled: bool,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetSearchArgs {
+    /// Site to search for tracks
+    site: SearchSite,
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetTemplateArgs {
+    /// Which downloads the template names
+    kind: TemplateKind,
+    /// Template with {title}, {artist}, {album}, {track} and {disc} placeholders
+    template: String,
+}
+
+#[derive(Args, Debug)]
+struct ConfigClearTemplateArgs {
+    /// Which template to reset
+    kind: TemplateKind,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum TemplateKind {
+    /// Tracks saved by `single` without --track-number
+    Single,
+    /// MusicBrainz album tracks, playlist items and singles with --track-number
+    Album,
+}
+
+impl TemplateKind {
+    fn name(self) -> &'static str {
+        match self {
+            TemplateKind::Single => "single",
+            TemplateKind::Album => "album",
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct ConfigSetMinFreeArgs {
+    /// Size with an optional K, M, G or T suffix (e.g. 2G; 0 only checks the estimate)
+    size: String,
+}
+
+#[derive(Args, Debug)]
+struct ConfigClearFormatDestArgs {
+    /// Audio format whose directory should be removed
+    format: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum TermListCommand {
+    /// Add a term (case-insensitive substring match)
+    Add(TermArgs),
+    /// Remove a term
+    Remove(TermArgs),
+    /// List all terms
+    List,
+}
+
+#[derive(Args, Debug)]
+struct TermArgs {
+    /// Term to match (e.g. "8D audio" or a channel name)
+    term: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_artist_album() {
+        assert_eq!(
+            split_artist_album("Metallica - Master of Puppets"),
+            Some(("Metallica".to_string(), "Master of Puppets".to_string()))
+        );
+        assert_eq!(
+            split_artist_album("Foo Fighters - The Colour and the Shape"),
+            Some(("Foo Fighters".to_string(), "The Colour and the Shape".to_string()))
+        );
+        assert_eq!(split_artist_album("NoDelimiterHere"), None);
+        assert_eq!(split_artist_album("- OnlyAlbum"), None);
+        assert_eq!(split_artist_album("OnlyArtist -"), None);
+    }
+
+    #[test]
+    fn test_split_artist_song() {
+        assert_eq!(
+            split_artist_song("Metallica - Nothing Else Matters"),
+            Some(("Metallica".to_string(), "Nothing Else Matters".to_string()))
+        );
+        assert_eq!(
+            split_artist_song("Foo Fighters - Everlong"),
+            Some(("Foo Fighters".to_string(), "Everlong".to_string()))
+        );
+        assert_eq!(split_artist_song("JustASongTitle"), None);
+    }
+
+    #[test]
+    fn test_looks_like_url() {
+        assert!(looks_like_url("https://www.youtube.com/watch?v=123"));
+        assert!(looks_like_url("http://example.com"));
+        assert!(looks_like_url("ytsearch:something"));
+        assert!(looks_like_url("www.youtube.com"));
+        assert!(!looks_like_url("just a search query"));
+        assert!(!looks_like_url("Metallica - Nothing Else Matters"));
+    }
+
+    #[test]
+    fn test_looks_like_playlist() {
+        assert!(looks_like_playlist("https://www.youtube.com/playlist?list=PLxxx"));
+        assert!(looks_like_playlist("https://www.youtube.com/watch?v=123&list=PLyyy"));
+        assert!(!looks_like_playlist("https://www.youtube.com/watch?v=123"));
+    }
+
+    #[test]
+    fn test_parse_watch_list_url() {
+        let watch =
+            parse_watch_list_url("https://www.youtube.com/watch?v=abc123&list=PLxyz").unwrap();
+        assert_eq!(watch.video_id, "abc123");
+        assert_eq!(watch.list_id, "PLxyz");
+        assert_eq!(watch.video_url(), "https://www.youtube.com/watch?v=abc123");
+        assert_eq!(
+            watch.playlist_url(),
+            "https://www.youtube.com/playlist?list=PLxyz"
+        );
+
+        let watch =
+            parse_watch_list_url("https://youtube.com/watch?list=RDabc123&v=abc123&index=2")
+                .unwrap();
+        assert_eq!(watch.video_id, "abc123");
+        assert!(is_mix_playlist_id(&watch.list_id));
+
+        let watch = parse_watch_list_url("https://youtu.be/abc123?list=OLAK5uy_xyz").unwrap();
+        assert_eq!(watch.video_id, "abc123");
+        assert_eq!(watch.list_id, "OLAK5uy_xyz");
+
+        assert_eq!(
+            parse_watch_list_url("https://www.youtube.com/watch?v=abc123"),
+            None
+        );
+        assert_eq!(
+            parse_watch_list_url("https://www.youtube.com/playlist?list=PLxyz"),
+            None
+        );
+        assert_eq!(
+            parse_watch_list_url("https://example.com/watch?v=abc&list=PLxyz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_validate_cookies_from_browser() {
+        let valid = |spec: &str| validate_cookies_from_browser(Some(spec.to_string())).unwrap();
+        assert_eq!(validate_cookies_from_browser(None).unwrap(), None);
+        assert_eq!(valid(" Firefox ").as_deref(), Some("firefox"));
+        assert_eq!(
+            valid("chrome:Profile 1").as_deref(),
+            Some("chrome:Profile 1")
+        );
+        assert_eq!(
+            valid("firefox:default-release::Personal").as_deref(),
+            Some("firefox:default-release::Personal")
+        );
+        assert_eq!(valid("firefox::Work").as_deref(), Some("firefox::Work"));
+        assert_eq!(
+            valid("chromium+kwallet6:C:\\Users\\me\\Chromium").as_deref(),
+            Some("chromium+KWALLET6:C:\\Users\\me\\Chromium")
+        );
+
+        for bad in [
+            "",
+            "netscape",
+            "chrome+wallet",
+            "firefox:",
+            "firefox::",
+            "chrome::Work",
+        ] {
+            assert!(
+                validate_cookies_from_browser(Some(bad.to_string())).is_err(),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_impersonate_target() {
+        assert_eq!(
+            validate_impersonate_target(" Chrome-124 ".into()).unwrap(),
+            "chrome-124"
+        );
+        assert_eq!(
+            validate_impersonate_target("safari:ios".into()).unwrap(),
+            "safari:ios"
+        );
+        assert!(validate_impersonate_target("".into()).is_err());
+        assert!(validate_impersonate_target("chrome --flag".into()).is_err());
+
+        let unavailable = "ERROR: Impersonate target \"chrome\" is not available. Use --list-impersonate-targets to see available targets.";
+        assert!(
+            describe_impersonate_error("chrome", unavailable)
+                .unwrap()
+                .contains("curl_cffi")
+        );
+        let old = "yt-dlp: error: no such option: --impersonate";
+        assert!(
+            describe_impersonate_error("chrome", old)
+                .unwrap()
+                .contains("predates")
+        );
+        let no_url = "yt-dlp: error: You must provide at least one URL.";
+        assert_eq!(describe_impersonate_error("chrome", no_url), None);
+    }
+
+    #[test]
+    fn test_build_extractor_args() {
+        let defaults = vec!["youtube:player_client=web".to_string()];
+        let extra = vec![
+            "generic:impersonate".to_string(),
+            " YouTube:lang=de ".to_string(),
+        ];
+        assert_eq!(
+            build_extractor_args(&defaults, &extra, Some("abc")).unwrap(),
+            [
+                "youtube:player_client=web;lang=de;po_token=web.gvs+abc",
+                "generic:impersonate",
+            ]
+        );
+        assert_eq!(
+            build_extractor_args(&[], &[], Some("mweb.gvs+xyz")).unwrap(),
+            ["youtube:po_token=mweb.gvs+xyz"]
+        );
+        assert!(build_extractor_args(&[], &[], None).unwrap().is_empty());
+
+        for bad in ["youtube", "youtube:", ":lang=de", "you tube:lang=de"] {
+            assert!(
+                build_extractor_args(&[], &[bad.to_string()], None).is_err(),
+                "{bad}"
+            );
+        }
+        assert!(build_extractor_args(&[], &[], Some(" ")).is_err());
+    }
+
+    #[test]
+    fn test_validate_country_code() {
+        assert_eq!(
+            validate_country_code(Some("us".to_string())).unwrap(),
+            Some("US".to_string())
+        );
+        assert!(validate_country_code(Some("USA".to_string())).is_err());
+        assert!(validate_country_code(Some("1A".to_string())).is_err());
+        assert_eq!(validate_country_code(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_describe_availability_error() {
+        let stderr = "ERROR: [youtube] abc123: The uploader has not made this video available in your country";
+        assert!(
+            describe_availability_error(stderr)
+                .unwrap()
+                .contains("region-blocked")
+        );
+        assert!(
+            describe_availability_error("ERROR: [youtube] abc123: Video unavailable").is_none()
+        );
+    }
+
+    #[test]
+    fn test_parse_stdin_targets() {
+        let input = "https://youtu.be/abc\n\n  # liked songs\n  Metallica - Battery  \r\n";
+        assert_eq!(
+            parse_stdin_targets(input),
+            ["https://youtu.be/abc", "Metallica - Battery"]
+        );
+        assert!(parse_stdin_targets("\n# nothing\n").is_empty());
+    }
+
+    #[test]
+    fn test_output_template_ytdlp_file_name() {
+        let single = OutputTemplate::parse(DEFAULT_SINGLE_TEMPLATE).unwrap();
+        let album = OutputTemplate::parse(DEFAULT_ALBUM_TEMPLATE).unwrap();
+        assert_eq!(single.ytdlp_file_name(None).unwrap(), "%(title)s.%(ext)s");
+        assert_eq!(
+            album.ytdlp_file_name(Some("03")).unwrap(),
+            "03 - %(title)s.%(ext)s"
+        );
+        assert_eq!(
+            album
+                .ytdlp_file_name(Some(&playlist_index_field(false, 0)))
+                .unwrap(),
+            "%(playlist_index)02d - %(title)s.%(ext)s"
+        );
+        assert!(album.ytdlp_file_name(None).is_err());
+        let playlist = OutputTemplate::parse(DEFAULT_PLAYLIST_TEMPLATE).unwrap();
+        assert_eq!(
+            playlist
+                .ytdlp_file_name(Some(&playlist_index_field(false, 0)))
+                .unwrap(),
+            "%(title)s.%(ext)s"
+        );
+        assert_eq!(
+            playlist_index_field(true, 11),
+            "%(playlist_autonumber+11)02d"
+        );
+
+        let custom = OutputTemplate::parse("{artist} - {title} (100%)").unwrap();
+        assert_eq!(
+            custom.ytdlp_file_name(None).unwrap(),
+            "%(artist,uploader|Unknown Artist)s - %(title)s (100%%).%(ext)s"
+        );
+        assert!(OutputTemplate::parse("{track} - {name}").is_err());
+        assert!(OutputTemplate::parse("{track - {title}").is_err());
+        assert!(OutputTemplate::parse("{album}/{title}").is_err());
+        assert!(OutputTemplate::parse("  ").is_err());
+    }
+
+    #[test]
+    fn test_describe_release_candidate() {
+        let candidate: MbReleaseSearchEntry = serde_json::from_value(serde_json::json!({
+            "id": "b84ee12a-09ef-421b-82de-0441a926375b",
+            "title": "Master of Puppets",
+            "date": "1986-03-03",
+            "country": "US",
+            "artist-credit": [{ "name": "Metallica" }],
+            "track-count": 8,
+            "media": [{ "format": "CD", "track-count": 8 }]
+        }))
+        .unwrap();
+        assert_eq!(
+            describe_release_candidate(&candidate),
+            "Metallica - Master of Puppets (1986-03-03, US, CD, 8 tracks)"
+        );
+        assert_eq!(describe_uncertain_match(&candidate), None);
+
+        let fuzzy: MbReleaseSearchEntry = serde_json::from_value(serde_json::json!({
+            "id": "b84ee12a-09ef-421b-82de-0441a926375b",
+            "title": "Master of Puppets (Live)",
+            "score": 72
+        }))
+        .unwrap();
+        let warning = describe_uncertain_match(&fuzzy).unwrap();
+        assert!(warning.starts_with(
+            "uncertain match (MusicBrainz score 72): Unknown Artist - Master of Puppets (Live)"
+        ));
+        assert!(warning.contains("--choose"));
+    }
+
+    #[test]
+    fn test_metadata_resolver() {
+        struct Fixed;
+        impl MetadataResolver for Fixed {
+            fn name(&self) -> &'static str {
+                "fixed"
+            }
+            fn find_album(&self, query: &str) -> Result<Option<Album>> {
+                Ok((query == "known").then(|| Album {
+                    title: "Known".to_string(),
+                    artist: "Artist".to_string(),
+                    release_date: None,
+                    original_date: None,
+                    disambiguation: None,
+                    cover_url: None,
+                    source_url: None,
+                    total_discs: 1,
+                    track_total: 0,
+                    tracks: Vec::new(),
+                }))
+            }
+            fn find_recording(&self, _query: &str) -> Result<Option<Album>> {
+                Ok(None)
+            }
+        }
+
+        assert!(Fixed.choose_album("known").unwrap().is_some());
+        assert!(Fixed.choose_album("other").unwrap().is_none());
+
+        let settings = MusicBrainzSettings {
+            offline: false,
+            user_agent: "bippi-test".to_string(),
+            timeout_secs: 1,
+            base_url: "http://127.0.0.1:1/ws/2".to_string(),
+            token: None,
+            locale: None,
+        };
+        let resolver = MetadataSource::Musicbrainz.resolver(&settings).unwrap();
+        assert_eq!(resolver.name(), "MusicBrainz");
+    }
+
+    #[test]
+    fn test_best_release_candidate() {
+        let candidates = |releases: serde_json::Value| -> Vec<MbReleaseSearchEntry> {
+            serde_json::from_value(releases).unwrap()
+        };
+        let tied = candidates(serde_json::json!([
+            { "id": "deluxe", "score": 100, "disambiguation": "deluxe edition" },
+            { "id": "standard", "score": 100, "disambiguation": "" },
+            { "id": "other", "score": 90 }
+        ]));
+        assert_eq!(best_release_candidate(&tied).unwrap().id, "standard");
+
+        let ranked = candidates(serde_json::json!([
+            {
+                "id": "explicit",
+                "score": 100,
+                "title": "Master of Puppets",
+                "date": "2017",
+                "disambiguation": "explicit"
+            },
+            { "id": "standard", "score": 95 }
+        ]));
+        assert_eq!(best_release_candidate(&ranked).unwrap().id, "explicit");
+        assert_eq!(
+            describe_release_candidate(&ranked[0]),
+            "Unknown Artist - Master of Puppets [explicit] (2017)"
+        );
+        assert!(best_release_candidate(&[]).is_none());
+    }
+
+    #[test]
+    fn test_parse_alias_probe() {
+        assert_eq!(
+            parse_alias_probe(true, br#"{"entries": [{"id": "a"}, {"id": "b"}]}"#, ""),
+            AliasHealth::Alive { items: 2 }
+        );
+        assert_eq!(
+            parse_alias_probe(true, br#"{"id": "a", "title": "Battery"}"#, ""),
+            AliasHealth::Alive { items: 1 }
+        );
+        assert_eq!(
+            parse_alias_probe(true, br#"{"entries": []}"#, ""),
+            AliasHealth::Dead("the playlist is empty".to_string())
+        );
+        assert_eq!(
+            parse_alias_probe(
+                false,
+                b"",
+                "WARNING: retrying\nERROR: [youtube:tab] PLxxx: The playlist does not exist.\n"
+            ),
+            AliasHealth::Dead("[youtube:tab] PLxxx: The playlist does not exist.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("\n", 3), Some(0));
+        assert_eq!(parse_selection(" 2 \n", 3), Some(1));
+        assert_eq!(parse_selection("3", 3), Some(2));
+        assert_eq!(parse_selection("0", 3), None);
+        assert_eq!(parse_selection("4", 3), None);
+        assert_eq!(parse_selection("two", 3), None);
+    }
+
+    #[test]
+    fn test_is_partial_download() {
+        assert!(is_partial_download(Path::new("/music/Battery.webm.part")));
+        assert!(is_partial_download(Path::new(
+            "/music/Battery.webm.part-Frag12"
+        )));
+        assert!(is_partial_download(Path::new("/music/Battery.webm.ytdl")));
+        assert!(is_partial_download(Path::new("/music/Battery.temp.mp3")));
+        assert!(is_partial_download(Path::new("/music/Battery.mp3.tmp")));
+        assert!(!is_partial_download(Path::new("/music/Temple.mp3")));
+        assert!(!is_partial_download(Path::new("/music/Battery.mp3")));
+        assert!(!is_partial_download(Path::new("/music/Counterpart.mp3")));
+    }
+
+    #[test]
+    fn test_validate_cover_name() {
+        assert_eq!(validate_cover_name(" folder.jpg ").unwrap(), "folder.jpg");
+        assert_eq!(validate_cover_name("Cover.JPEG").unwrap(), "Cover.JPEG");
+        for bad in [
+            "cover.png",
+            "art/cover.jpg",
+            "..\\cover.jpg",
+            "cover",
+            ".jpg",
+        ] {
+            assert!(validate_cover_name(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_album_zip() {
+        let root = std::env::temp_dir().join(format!("bippi-zip-test-{}", std::process::id()));
+        let album = root.join("Master of Puppets");
+        fs::create_dir_all(&album).unwrap();
+        let tracks = vec![album.join("02 - Orion.mp3"), album.join("01 - Battery.mp3")];
+        for (index, track) in tracks.iter().enumerate() {
+            fs::write(track, vec![index as u8; 64]).unwrap();
+        }
+        // Only art written by this run goes into the archive (and is deleted).
+        let covers = vec![album.join("cover.jpg")];
+        fs::write(&covers[0], b"jpg").unwrap();
+        fs::write(album.join("folder.jpg"), b"jpg").unwrap();
+        fs::write(album.join("notes.txt"), b"txt").unwrap();
+
+        let path = root.join("album.zip");
+        let zip = AlbumZip {
+            path: &path,
+            delete_loose: true,
+        };
+        zip.write(&root, &tracks, &covers).unwrap();
+
+        let archive = zip::ZipArchive::new(fs::File::open(&path).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(
+            names,
+            [
+                "Master of Puppets/02 - Orion.mp3",
+                "Master of Puppets/01 - Battery.mp3",
+                "Master of Puppets/cover.jpg",
+            ]
+        );
+        assert!(!tracks[0].exists());
+        assert!(!covers[0].exists());
+        assert!(album.join("folder.jpg").exists());
+        assert!(album.join("notes.txt").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_album_m3u() {
+        let track =
+            |position: u32, title: &str, artist: Option<&str>, duration: Option<f64>| Track {
+                title: title.to_string(),
+                artist: artist.map(str::to_string),
+                disc: 1,
+                position,
+                overall_index: position as usize,
+                duration,
+            };
+        let album = Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 2,
+            tracks: vec![
+                track(1, "Battery", None, Some(312.4)),
+                track(2, "Orion", Some("Metallica & Cliff"), None),
+            ],
+        };
+        let root = Path::new("/music");
+        let battery = root.join("01 - Battery.mp3");
+        let orion = root.join("CD1").join("02 - Orion.mp3");
+        let entries = [
+            (&album.tracks[0], battery.as_path()),
+            (&album.tracks[1], orion.as_path()),
+        ];
+        assert_eq!(
+            album_m3u(&album, &entries, root),
+            "#EXTM3U\n\
+             #EXTINF:312,Metallica - Battery\n01 - Battery.mp3\n\
+             #EXTINF:-1,Metallica & Cliff - Orion\nCD1/02 - Orion.mp3\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_tmp_dir() {
+        let base = std::env::temp_dir().join(format!("bippi-tmp-dir-test-{}", std::process::id()));
+        let dir = base.join("nested");
+        assert_eq!(validate_tmp_dir(&dir).unwrap(), dir);
+        assert!(dir.is_dir());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let file = base.join("file");
+        fs::write(&file, b"").unwrap();
+        assert!(validate_tmp_dir(&file).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("50M").unwrap(), 50 * 1024 * 1024);
+        assert_eq!(parse_size("1.5g").unwrap(), 3 * 512 * 1024 * 1024);
+        assert_eq!(parse_size("2048").unwrap(), 2048);
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("-5M").is_err());
+
+        assert_eq!(
+            validate_max_filesize(Some(" 50M ".to_string())).unwrap(),
+            Some("50M".to_string())
+        );
+        assert!(validate_max_filesize(Some("0".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_describe_notification() {
+        let files = vec![
+            PathBuf::from("/music/Album/01 - One.mp3"),
+            PathBuf::from("/music/Album/02 - Two.mp3"),
+        ];
+        assert_eq!(
+            describe_notification(&Ok(files)),
+            (
+                "bippi: download finished".to_string(),
+                "2 tracks saved to /music/Album".to_string()
+            )
+        );
+        assert_eq!(
+            describe_notification(&Ok(Vec::new())).1,
+            "no new tracks were saved"
+        );
+        let failed = describe_notification(&Err(AppError::YtDlpFailed(1)));
+        assert_eq!(failed.0, "bippi: download failed");
+        assert_eq!(failed.1, "yt-dlp exited with status 1");
+    }
+
+    #[test]
+    fn test_describe_summary() {
+        let summary = DownloadSummary {
+            destination: PathBuf::from("/music"),
+            files: 2,
+            bytes: 15 * 1024 * 1024,
+            elapsed: Duration::from_secs(95),
+            formats: vec!["flac".to_string(), "m4a".to_string()],
+        };
+        assert_eq!(
+            summary.describe(),
+            "2 files, 15 MB in 1:35 as flac, m4a -> /music"
+        );
+
+        let dir = std::env::temp_dir().join(format!("bippi-summary-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let produced = vec![
+            dir.join("01. A.mp3"),
+            dir.join("02. B.MP3"),
+            dir.join("03. C.opus"),
+        ];
+        for (file, size) in produced.iter().zip([3, 4, 5]) {
+            fs::write(file, vec![0u8; size]).unwrap();
+        }
+        let summary = DownloadSummary::from_files(&dir, &produced, SystemTime::now()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.bytes, 12);
+        assert_eq!(summary.formats, ["mp3", "opus"]);
+    }
+
+    #[test]
+    fn test_describe_estimate() {
+        let playlist = serde_json::json!({
+            "entries": [{ "duration": 4000.0 }, { "duration": 3200 }, { "title": "no duration" }]
+        });
+        let estimate = PlaylistEstimate::from_flat_playlist(&playlist);
+        assert_eq!(
+            estimate,
+            PlaylistEstimate {
+                tracks: 3,
+                missing_durations: 1,
+                seconds: 7200.0,
+            }
+        );
+        assert_eq!(
+            describe_estimate(&estimate, "mp3"),
+            "at least ~112 MB estimated for 3 tracks (120:00 of audio); 1 without a known duration"
+        );
+        assert_eq!(format_size(1_234_000_000.0), "1.1 GB");
+        assert_eq!(format_size(500.0 * 1024.0 * 1024.0), "500 MB");
+        assert_eq!(format_size(800.0), "0.8 KB");
+    }
+
+    #[test]
+    fn test_playlist_manifest() {
+        let playlist = serde_json::json!({
+            "id": "PLabc",
+            "title": "Road Trip: Mix",
+            "channel": "Someone",
+            "entries": [
+                { "title": "One", "url": "https://www.youtube.com/watch?v=one" },
+                { "title": "Two", "webpage_url": "https://www.youtube.com/watch?v=two" },
+            ]
+        });
+        let url = "https://www.youtube.com/playlist?list=PLabc";
+        let manifest = PlaylistManifest::from_flat_playlist(url, &playlist);
+        assert_eq!(manifest.url, url);
+        assert_eq!(manifest.uploader.as_deref(), Some("Someone"));
+        assert_eq!(manifest.item_count, 2);
+        assert_eq!(
+            manifest.items[1].url.as_deref(),
+            Some("https://www.youtube.com/watch?v=two")
+        );
+        assert_eq!(
+            manifest.file_name(),
+            format!("{}.playlist.json", sanitize_filename("Road Trip: Mix"))
+        );
+
+        let untitled = PlaylistManifest::from_flat_playlist(url, &serde_json::json!({}));
+        assert_eq!(untitled.item_count, 0);
+        assert_eq!(untitled.file_name(), "playlist.playlist.json");
+    }
+
+    #[test]
+    fn test_since_file() {
+        let path = std::env::temp_dir().join(format!("bippi-since-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(since_file_date(&path).unwrap(), None);
+
+        // 2024-03-01 12:00 UTC
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_294_400);
+        touch_since_file(&path, time).unwrap();
+        assert_eq!(since_file_date(&path).unwrap().as_deref(), Some("20240301"));
+        fs::write(&path, b"notes").unwrap();
+        touch_since_file(&path, time + Duration::from_secs(86_400)).unwrap();
+        assert_eq!(since_file_date(&path).unwrap().as_deref(), Some("20240302"));
+        assert_eq!(fs::read(&path).unwrap(), b"notes");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ensure_directory_path() {
+        let base = std::env::temp_dir().join(format!("bippi-dest-test-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let file = base.join("song.mp3");
+        fs::write(&file, b"").unwrap();
+
+        assert!(ensure_directory_path(&base, "destination").is_ok());
+        assert!(ensure_directory_path(&base.join("missing"), "destination").is_ok());
+        match ensure_directory_path(&file, "destination") {
+            Err(AppError::Message(message)) => assert!(message.contains("is a file")),
+            other => panic!("expected a message error, got {:?}", other),
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_destination() {
+        let mut config = AppConfig {
+            default_destination: Some(PathBuf::from("/music")),
+            ..AppConfig::default()
+        };
+        config
+            .format_destinations
+            .insert("flac".to_string(), PathBuf::from("/audio/lossless"));
+
+        let resolve = |dest: Option<&str>, format| {
+            resolve_destination(dest.map(Path::new), &config, format).unwrap()
+        };
+        assert_eq!(resolve(None, "flac"), PathBuf::from("/audio/lossless"));
+        assert_eq!(resolve(None, "mp3"), PathBuf::from("/music"));
+        assert_eq!(resolve(Some("/tmp/out"), "flac"), PathBuf::from("/tmp/out"));
+    }
+
+    #[test]
+    fn test_config_from_json_names_fields() {
+        let path = Path::new("/tmp/config.json");
+        let data = br#"{
+            "blocklist": "8d audio",
+            "allowlist": ["ArtistVEVO"],
+            "aliases": {
+                "good": { "url": "https://youtu.be/abc", "album": false },
+                "bad": { "url": 42 }
+            }
+        }"#;
+        let mut config = AppConfig::from_json_lenient(path, data).unwrap();
+        assert!(config.is_invalid("blocklist"));
+        assert!(config.is_invalid("aliases.bad"));
+        assert!(config.blocklist.is_empty());
+        assert_eq!(config.allowlist, vec!["ArtistVEVO".to_string()]);
+        assert!(config.aliases.contains_key("good"));
+        assert!(!config.aliases.contains_key("bad"));
+        match config.ensure_valid(path) {
+            Err(AppError::InvalidConfig {
+                path: err_path,
+                detail,
+            }) => {
+                assert_eq!(err_path, path);
+                assert!(detail.contains("'blocklist'"));
+                assert!(detail.contains("'aliases.bad'"));
+                assert!(detail.contains("config reset aliases.bad blocklist"));
+                assert!(!detail.contains("allowlist"));
+                assert!(!detail.contains("aliases.good"));
+            }
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+
+        assert!(config.reset_field("blocklist").unwrap());
+        assert!(config.reset_field("aliases.bad").unwrap());
+        assert!(config.ensure_valid(path).is_ok());
+        assert_eq!(config.allowlist, vec!["ArtistVEVO".to_string()]);
+
+        match AppConfig::from_json_lenient(path, b"{ \"blocklist\": [,] }") {
+            Err(AppError::InvalidConfig {
+                path: err_path,
+                detail,
+            }) => {
+                assert_eq!(err_path, path);
+                assert!(detail.contains("line 1"));
+            }
+            other => panic!("expected InvalidConfig, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("Normal Title"), "Normal Title");
+        assert_eq!(sanitize_filename("Title/With\\Slashes"), "Title_With_Slashes");
+        assert_eq!(sanitize_filename("Title:With*Special?Chars"), "Title_With_Special_Chars");
+        assert_eq!(sanitize_filename("  Trimmed  "), "Trimmed");
+        assert_eq!(sanitize_filename("...dots..."), "dots");
+        assert_eq!(sanitize_filename(""), "track");
+    }
+
+    #[test]
+    fn test_raw_filename() {
+        assert_eq!(
+            raw_filename("What? \"Live\" <1991>: *"),
+            "What? \"Live\" <1991>: *"
+        );
+        assert_eq!(raw_filename("../AC/DC\\Back\0"), "_AC_DC_Back_");
+        assert_eq!(raw_filename(".."), "track");
+    }
+
+    #[test]
+    fn test_build_single_search_query() {
+        let query = build_single_search_query("Metallica - Nothing Else Matters");
+        assert!(query.starts_with("ytsearch1:"));
+        assert!(query.contains("Metallica"));
+        assert!(query.contains("Nothing Else Matters"));
+        assert!(query.contains("audio"));
+        assert!(query.contains("-\"music video\""));
+
+        let query2 = build_single_search_query("some audio track");
+        assert!(!query2.contains("audio audio"));
+    }
+
+    #[test]
+    fn test_max_downloads_reached() {
+        let options = YtDlpOptions {
+            max_downloads: Some(3),
+            ..Default::default()
+        };
+        let capped = base_yt_dlp_command("mp3", "%(title)s.%(ext)s", &options);
+        let args: Vec<_> = capped.get_args().collect();
+        assert!(args.windows(2).any(|pair| pair == ["--max-downloads", "3"]));
+        assert!(max_downloads_reached(&capped, Some(101)));
+        assert!(!max_downloads_reached(&capped, Some(1)));
+
+        let uncapped = base_yt_dlp_command("mp3", "%(title)s.%(ext)s", &YtDlpOptions::default());
+        assert!(!max_downloads_reached(&uncapped, Some(101)));
+        assert!(validate_max_downloads(Some(0)).is_err());
+        assert_eq!(validate_max_downloads(Some(5)).unwrap(), Some(5));
+    }
+
+    #[test]
+    fn test_detect_ytdlp_install() {
+        assert_eq!(
+            detect_ytdlp_install(Path::new("/usr/local/bin/yt-dlp"), ""),
+            YtDlpInstall::Standalone
+        );
+        assert_eq!(
+            detect_ytdlp_install(
+                Path::new("/home/me/.local/share/pipx/venvs/yt-dlp/bin/yt-dlp"),
+                "#!/home/me/.local/share/pipx/venvs/yt-dlp/bin/python"
+            ),
+            YtDlpInstall::Pipx
+        );
+        assert_eq!(
+            detect_ytdlp_install(
+                Path::new("/opt/homebrew/Cellar/yt-dlp/2024.08.06/bin/yt-dlp"),
+                ""
+            ),
+            YtDlpInstall::Homebrew
+        );
+        assert_eq!(
+            detect_ytdlp_install(
+                Path::new("/home/me/.local/bin/yt-dlp"),
+                "#!/usr/bin/python3\n"
+            ),
+            YtDlpInstall::Pip("/usr/bin/python3".to_string())
+        );
+        assert_eq!(
+            detect_ytdlp_install(
+                Path::new("/home/me/.local/bin/yt-dlp"),
+                "#!/usr/bin/env python3"
+            ),
+            YtDlpInstall::Pip("python3".to_string())
+        );
+        assert_eq!(
+            detect_ytdlp_install(Path::new("/usr/bin/yt-dlp"), "#!/usr/bin/python3"),
+            YtDlpInstall::System
+        );
+        assert_eq!(
+            detect_ytdlp_install(
+                Path::new("/usr/lib/python3/dist-packages/yt_dlp/__main__.py"),
+                ""
+            ),
+            YtDlpInstall::System
+        );
+        assert_eq!(
+            describe_command(
+                &YtDlpInstall::Pip("python3".to_string())
+                    .update_command()
+                    .unwrap()
+            ),
+            "python3 -m pip install -U yt-dlp"
+        );
+        assert!(YtDlpInstall::System.update_command().is_none());
+    }
+
+    #[test]
+    fn test_first_output_line() {
+        assert_eq!(
+            first_output_line(b"ffmpeg version 6.1.1 Copyright (c) 2000-2023\nbuilt with gcc 13\n")
+                .as_deref(),
+            Some("ffmpeg version 6.1.1 Copyright (c) 2000-2023")
+        );
+        assert_eq!(
+            first_output_line(b"\n2024.08.06\n").as_deref(),
+            Some("2024.08.06")
+        );
+        assert_eq!(first_output_line(b""), None);
+    }
+
+    #[test]
+    fn test_check_min_results() {
+        assert!(check_min_results(0, None, "q").is_ok());
+        assert!(check_min_results(3, Some(3), "q").is_ok());
+        let err = check_min_results(1, Some(3), "batery").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "only 1 search result for 'batery' (--min-results 3); try rewording the query"
+        );
+    }
+
+    #[test]
+    fn test_album_search_terms() {
+        assert_eq!(
+            album_search_terms("Metallica - Master of Puppets", DEFAULT_ALBUM_KEYWORD),
+            "Metallica - Master of Puppets album"
+        );
+        assert_eq!(
+            album_search_terms("Metallica Master of Puppets Full ALBUM", "album"),
+            "Metallica Master of Puppets Full ALBUM"
+        );
+        assert_eq!(
+            album_search_terms("Rosalía El Mal Querer", "álbum"),
+            "Rosalía El Mal Querer álbum"
+        );
+        assert_eq!(
+            album_search_terms("Rosalía El Mal Querer ÁLBUM", "álbum"),
+            "Rosalía El Mal Querer ÁLBUM"
+        );
+        assert_eq!(
+            album_search_terms(" Master of Puppets ", ""),
+            "Master of Puppets"
+        );
+        assert_eq!(
+            album_search_terms("Master of Puppets", "  "),
+            "Master of Puppets"
+        );
+    }
+
+    #[test]
+    fn test_search_site_target() {
+        assert_eq!(
+            SearchSite::YouTube.search_target("Metallica Battery", 5),
+            "ytsearch5:Metallica Battery"
+        );
+        assert_eq!(
+            SearchSite::YouTubeMusic.search_target("AC/DC & Friends", 5),
+            "https://music.youtube.com/search?q=AC%2FDC%20%26%20Friends#songs"
+        );
+    }
+
+    #[test]
+    fn test_parse_release_mbid() {
+        let id = "b84ee12a-09ef-421b-82de-0441a926375b";
+        assert_eq!(parse_release_mbid(id), Some(id.to_string()));
+        assert_eq!(
+            parse_release_mbid(&format!(
+                "https://musicbrainz.org/release/{}/",
+                id.to_uppercase()
+            )),
+            Some(id.to_string())
+        );
+        assert_eq!(parse_release_mbid("not-an-id"), None);
+        assert_eq!(
+            parse_release_mbid("b84ee12a09ef421b82de0441a926375bxxxx"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_probe_tags() {
+        let parsed = serde_json::json!({
+            "format": { "duration": "201.5", "tags": { "TITLE": "Battery", "track": "01/08" } },
+            "streams": [{ "tags": { "ARTIST": "Metallica", "DISC": "1" } }]
+        });
+        let tags = parse_probe_tags(&parsed);
+        assert_eq!(tags.title.as_deref(), Some("Battery"));
+        assert_eq!(tags.artist.as_deref(), Some("Metallica"));
+        assert_eq!(tags.track, Some(1));
+        assert_eq!(tags.disc, Some(1));
+        assert_eq!(tags.duration, Some(201.5));
+    }
+
+    #[test]
+    fn test_match_local_tracks() {
+        let track = |title: &str, overall_index| Track {
+            title: title.to_string(),
+            artist: None,
+            disc: 1,
+            position: overall_index as u32,
+            overall_index,
+            duration: None,
+        };
+        let album = Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 3,
+            tracks: vec![
+                track("Battery", 1),
+                track("Master of Puppets", 2),
+                track("One", 3),
+            ],
+        };
+        let local = |name: &str, track| LocalTrack {
+            path: PathBuf::from(name),
+            tags: AudioTags {
+                track,
+                ..AudioTags::default()
+            },
+        };
+        let files = vec![
+            local("Master of Puppets (Remastered).mp3", None),
+            local("whatever.mp3", Some(1)),
+            local("Someone Else.mp3", None),
+        ];
+
+        let matching = match_local_tracks(&album, &files);
+        assert_eq!(matching.pairs, vec![(0, 1), (1, 0)]);
+        assert_eq!(matching.missing, vec![2]);
+        assert_eq!(matching.extra, vec![2]);
+    }
+
+    #[test]
+    fn test_match_tracks_by_order() {
+        let matching = match_tracks_by_order(3, 2);
+        assert_eq!(matching.pairs, vec![(0, 0), (1, 1)]);
+        assert_eq!(matching.missing, vec![2]);
+        assert!(matching.extra.is_empty());
+    }
+
+    #[test]
+    fn test_title_case() {
+        let title = |text: &str| TitleCase::Title.apply(text);
+        assert_eq!(title("MASTER OF PUPPETS"), "Master of Puppets");
+        assert_eq!(title("the end of the world"), "The End of the World");
+        assert_eq!(title("LIVE AT THE BBC"), "Live at the BBC");
+        assert_eq!(title("ROCKY II: THE RETURN"), "Rocky II: The Return");
+        assert_eq!(title("song (the remix)"), "Song (The Remix)");
+        assert_eq!(
+            title("what are you waiting for"),
+            "What Are You Waiting For"
+        );
+        assert_eq!(title("self-titled track"), "Self-Titled Track");
+        assert_eq!(
+            title("NASA's song for McCartney"),
+            "NASA's Song for McCartney"
+        );
+        assert_eq!(title("DON'T STOP 'TIL 1999"), "Don't Stop 'Til 1999");
+        assert_eq!(title("MIX TAPE VOL. IV"), "Mix Tape Vol. IV");
+        assert_eq!(title("mix tape vol. iv"), "Mix Tape Vol. IV");
+        assert_eq!(TitleCase::Lower.apply("Live At The BBC"), "live at the bbc");
+        assert_eq!(TitleCase::Upper.apply("Battery"), "BATTERY");
+        assert_eq!(TitleCase::Keep.apply("iNTRO"), "iNTRO");
+    }
+
+    #[test]
+    fn test_clean_title() {
+        let noise = default_title_noise();
+        let clean = |title: &str| clean_title(title, &noise);
+        assert_eq!(clean("Battery (Official Audio)"), "Battery");
+        assert_eq!(clean("Battery [HD]"), "Battery");
+        assert_eq!(
+            clean("Battery (Remastered 2017) [Official Video]"),
+            "Battery"
+        );
+        assert_eq!(clean("Battery - 2017 Remaster"), "Battery");
+        assert_eq!(clean("Battery (Live)"), "Battery (Live)");
+        assert_eq!(clean("Shadows (Chad Remix)"), "Shadows (Chad Remix)");
+        assert_eq!(clean("Jump - Live"), "Jump - Live");
+        assert_eq!(clean("(Audio)"), "(Audio)");
+
+        let custom = vec!["live".to_string()];
+        assert_eq!(clean_title("Battery (Live)", &custom), "Battery");
+        assert_eq!(clean_title("Battery (HD)", &custom), "Battery (HD)");
+    }
+
+    #[test]
+    fn test_dedupe_title_key() {
+        let key = dedupe_title_key("Nothing Else Matters");
+        assert_eq!(key, "nothing else matters");
+        assert_eq!(dedupe_title_key("Nothing Else Matters (Live)"), key);
+        assert_eq!(dedupe_title_key("Nothing Else Matters feat. Someone"), key);
+        assert_eq!(dedupe_title_key("nothing else matters!"), key);
+        assert_ne!(dedupe_title_key("Nothing Else"), key);
+    }
+
+    #[test]
+    fn test_best_track_candidate() {
+        let result = |title: &str| SearchResult {
+            title: title.to_string(),
+            uploader: None,
+            url: format!("https://www.youtube.com/watch?v={}", title.len()),
+            duration: None,
+        };
+        let results = vec![
+            result("Metallica - Master of Puppets (Full Album)"),
+            result("Metallica - Battery (Remastered)"),
+            result("Battery Park walking tour"),
+        ];
+
+        let (best, score) = best_track_candidate("Metallica", "Battery", &results).unwrap();
+        assert_eq!(best.title, "Metallica - Battery (Remastered)");
+        assert!(score > 0.99);
+        assert!(track_match_score("Metallica", "Battery", "Battery Park walking tour") < score);
+        assert_eq!(strip_bracketed("One [Live] (2023)"), "One  ");
+    }
+
+    #[test]
+    fn test_unexpected_track_files() {
+        let track = |title: &str, overall_index| Track {
+            title: title.to_string(),
+            artist: None,
+            disc: 1,
+            position: overall_index as u32,
+            overall_index,
+            duration: None,
+        };
+        let release = || Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 2,
+            tracks: vec![track("Battery", 1), track("Master of Puppets", 2)],
+        };
+        let album = release();
+        let template = OutputTemplate::parse(DEFAULT_ALBUM_TEMPLATE).unwrap();
+        let local = |name: &str, title: Option<&str>| LocalTrack {
+            path: Path::new("/music").join(name),
+            tags: AudioTags {
+                title: title.map(str::to_string),
+                ..AudioTags::default()
+            },
+        };
+        let naming = FileNaming::Portable;
+        let files = [
+            local("01 - Battery.mp3", None),
+            local("02 - master of puppets.FLAC", None),
+            local("02 - Master Of Pupets.mp3", Some("Master Of Pupets")),
+            local("01 - Battery (Remastered).opus", None),
+            local("notes.txt", None),
+            local("cover.jpg", None),
+        ];
+        assert_eq!(
+            unexpected_track_files(&album, &files, &template, false, naming, 200),
+            [
+                Path::new("/music/02 - Master Of Pupets.mp3"),
+                Path::new("/music/01 - Battery (Remastered).opus"),
+            ]
+        );
+        let truncated = [local("02 - Master.mp3", None)];
+        let kept = unexpected_track_files(&album, &truncated, &template, false, naming, 11);
+        assert!(kept.is_empty());
+
+        // Downloaded with --numbering-start 12 --no-sanitize --case upper, then pruned
+        // with the defaults: the names differ, but the tags still match the tracks.
+        let mut upper = release();
+        recase_album_titles(&mut upper, TitleCase::Upper);
+        let downloaded: Vec<LocalTrack> = upper
+            .tracks
+            .iter()
+            .map(|track| {
+                let stem =
+                    template.track_stem(&upper, track, 11, false, FileNaming::Raw, usize::MAX);
+                local(&format!("{}.mp3", stem), Some(&track.title))
+            })
+            .collect();
+        assert_eq!(downloaded[0].path, Path::new("/music/12 - BATTERY.mp3"));
+        let kept = unexpected_track_files(&album, &downloaded, &template, false, naming, 200);
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn test_select_disc() {
+        let track = |disc, position, overall_index| Track {
+            title: format!("Track {overall_index}"),
+            artist: None,
+            disc,
+            position,
+            overall_index,
+            duration: None,
+        };
+        let box_set = || Album {
+            title: "Box Set".to_string(),
+            artist: "Artist".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 2,
+            track_total: 4,
+            tracks: vec![
+                track(1, 1, 1),
+                track(1, 2, 2),
+                track(2, 1, 3),
+                track(2, 2, 4),
+            ],
+        };
+
+        let mut album = box_set();
+        select_disc(&mut album, Some(2)).unwrap();
+        let kept: Vec<_> = album
+            .tracks
+            .iter()
+            .map(|track| (track.disc, track.position, track.overall_index))
+            .collect();
+        assert_eq!(kept, [(2, 1, 3), (2, 2, 4)]);
+        assert_eq!(album.track_total, 4);
+
+        let mut whole = box_set();
+        select_disc(&mut whole, None).unwrap();
+        assert_eq!(whole.tracks.len(), 4);
+        for disc in [0, 3] {
+            match select_disc(&mut box_set(), Some(disc)) {
+                Err(AppError::Message(message)) => assert!(message.contains("has 2 discs")),
+                other => panic!("expected a message error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_track_search_variants() {
+        let album = Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 0,
+            tracks: Vec::new(),
+        };
+        let track = Track {
+            title: "Battery".to_string(),
+            artist: None,
+            disc: 1,
+            position: 1,
+            overall_index: 1,
+            duration: None,
+        };
+        assert_eq!(
+            track_search_variants(&album, &track),
+            [
+                "Metallica Battery",
+                "Metallica Battery official audio",
+                "Metallica Battery lyrics",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_album_suffixes() {
+        let suffixes = default_album_suffixes();
+        let cases = [
+            ("Master of Puppets (Remastered)", "Master of Puppets"),
+            ("Rumours (2004 Remaster)", "Rumours"),
+            ("Nevermind (Super Deluxe Edition)", "Nevermind"),
+            ("OK Computer [Deluxe] (2017 Remaster)", "OK Computer"),
+            ("Thriller (25th Anniversary Edition)", "Thriller"),
+            ("Alive! (Live)", "Alive! (Live)"),
+            (
+                "Led Zeppelin IV (Remastered) - Bonus",
+                "Led Zeppelin IV (Remastered) - Bonus",
+            ),
+            ("(Deluxe)", "(Deluxe)"),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(strip_album_suffixes(title, &suffixes), expected, "{title}");
+        }
+        assert_eq!(
+            strip_album_suffixes("Blue (Remastered)", &[]),
+            "Blue (Remastered)"
+        );
+    }
+
+    #[test]
+    fn test_featured_title_variants() {
+        assert_eq!(
+            featured_title_variants("Stay (feat. Justin Bieber)"),
+            [
+                "Stay (feat. Justin Bieber)",
+                "Stay (ft. Justin Bieber)",
+                "Stay"
+            ]
+        );
+        assert_eq!(
+            featured_title_variants("Empire State of Mind Ft. Alicia Keys"),
+            [
+                "Empire State of Mind Ft. Alicia Keys",
+                "Empire State of Mind ft. Alicia Keys",
+                "Empire State of Mind feat. Alicia Keys",
+                "Empire State of Mind"
+            ]
+        );
+        assert_eq!(
+            featured_title_variants("Song [Featuring X & Y] (Remix)"),
+            [
+                "Song [Featuring X & Y] (Remix)",
+                "Song [ft. X & Y] (Remix)",
+                "Song [feat. X & Y] (Remix)",
+                "Song (Remix)"
+            ]
+        );
+        assert_eq!(
+            featured_title_variants("A Feat of Strength"),
+            ["A Feat of Strength"]
+        );
+        assert_eq!(featured_title_variants("Left (ft )"), ["Left (ft )"]);
+    }
+
+    #[test]
+    fn test_unwanted_versions() {
+        let excludes = default_version_excludes();
+        assert_eq!(
+            unwanted_versions("Battery", &excludes),
+            ["live", "remix", "cover"]
+        );
+        assert_eq!(
+            unwanted_versions("Battery (Live in Seattle)", &excludes),
+            ["remix", "cover"]
+        );
+        assert!(unwanted_versions("Battery", &[]).is_empty());
+
+        let unwanted = unwanted_versions("Battery", &excludes);
+        assert!(mentions_any("Metallica - Battery (Live 1989)", &unwanted));
+        assert!(mentions_any("Battery REMIX", &unwanted));
+        assert!(!mentions_any("Metallica - Battery (Remastered)", &unwanted));
+        assert!(!mentions_any("Metallica - Battery (Discovered)", &unwanted));
+    }
+
+    // Time only moves when a caller sleeps or the test advances it.
+    struct MockClock {
+        now: Mutex<Instant>,
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl MockClock {
+        fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+            self.advance(duration);
+        }
+    }
 
     #[test]
-    fn test_split_artist_album() {
+    fn test_rate_limiter_spaces_requests() {
+        let interval = Duration::from_secs(1);
+        let limiter = RateLimiter::new(MockClock {
+            now: Mutex::new(Instant::now()),
+            sleeps: Mutex::new(Vec::new()),
+        });
+
+        limiter.wait_for_turn(interval);
+        limiter.wait_for_turn(interval);
+        limiter.clock.advance(Duration::from_millis(400));
+        limiter.wait_for_turn(interval);
+        limiter.clock.advance(Duration::from_secs(3));
+        limiter.wait_for_turn(interval);
         assert_eq!(
-            split_artist_album("Metallica - Master of Puppets"),
-            Some(("Metallica".to_string(), "Master of Puppets".to_string()))
+            *limiter.clock.sleeps.lock().unwrap(),
+            [interval, Duration::from_millis(600)]
         );
+
+        // Threads sharing the limiter get one slot each, never two at once.
+        let limiter = std::sync::Arc::new(limiter);
+        let start = limiter.clock.now();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let limiter = limiter.clone();
+                std::thread::spawn(move || {
+                    limiter.wait_for_turn(interval);
+                    limiter.clock.now()
+                })
+            })
+            .collect();
+        let mut turns: Vec<Instant> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        turns.sort();
+        assert!(turns[0] >= start + interval);
+        for pair in turns.windows(2) {
+            assert!(pair[1] - pair[0] >= interval);
+        }
+    }
+
+    #[test]
+    fn test_validate_mb_url() {
         assert_eq!(
-            split_artist_album("Foo Fighters - The Colour and the Shape"),
-            Some(("Foo Fighters".to_string(), "The Colour and the Shape".to_string()))
+            validate_mb_url("http://localhost:5000/ws/2/").unwrap(),
+            "http://localhost:5000/ws/2"
         );
-        assert_eq!(split_artist_album("NoDelimiterHere"), None);
-        assert_eq!(split_artist_album("- OnlyAlbum"), None);
-        assert_eq!(split_artist_album("OnlyArtist -"), None);
+        assert!(validate_mb_url("localhost:5000").is_err());
+        assert!(validate_mb_url("ftp://example.com/ws/2").is_err());
     }
 
     #[test]
-    fn test_split_artist_song() {
+    fn test_display_secret() {
+        assert_eq!(display_secret(Some("abc"), false), "***set***");
+        assert_eq!(display_secret(Some("abc"), true), "abc");
+        assert_eq!(display_secret(None, true), "not set");
         assert_eq!(
-            split_artist_song("Metallica - Nothing Else Matters"),
-            Some(("Metallica".to_string(), "Nothing Else Matters".to_string()))
+            display_extractor_args("youtube:player_client=web;po_token=web.gvs+abc", false),
+            "youtube:player_client=web;po_token=***set***"
         );
         assert_eq!(
-            split_artist_song("Foo Fighters - Everlong"),
-            Some(("Foo Fighters".to_string(), "Everlong".to_string()))
+            display_extractor_args("youtube:po_token=web.gvs+abc", true),
+            "youtube:po_token=web.gvs+abc"
+        );
+        assert_eq!(
+            display_extractor_args("youtube:player_client=web", false),
+            "youtube:player_client=web"
         );
-        assert_eq!(split_artist_song("JustASongTitle"), None);
     }
 
     #[test]
-    fn test_looks_like_url() {
-        assert!(looks_like_url("https://www.youtube.com/watch?v=123"));
-        assert!(looks_like_url("http://example.com"));
-        assert!(looks_like_url("ytsearch:something"));
-        assert!(looks_like_url("www.youtube.com"));
-        assert!(!looks_like_url("just a search query"));
-        assert!(!looks_like_url("Metallica - Nothing Else Matters"));
+    fn test_track_metadata_tags_per_format() {
+        let album = Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: Some("1986-03-03".to_string()),
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 2,
+            track_total: 0,
+            tracks: Vec::new(),
+        };
+        let track = Track {
+            title: "Battery".to_string(),
+            artist: None,
+            disc: 1,
+            position: 1,
+            overall_index: 1,
+            duration: None,
+        };
+        let keys = |format: &str| {
+            track_metadata_tags(&album, &track, 8, format, TagOptions::default())
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        for format in ["mp3", "m4a"] {
+            let tags = keys(format);
+            assert_eq!(tags["track"], "01/8");
+            assert_eq!(tags["disc"], "1");
+            assert_eq!(tags["date"], "1986-03-03");
+            assert_eq!(tags["album_artist"], "Metallica");
+        }
+        for format in ["opus", "flac", "vorbis", "OGG"] {
+            let tags = keys(format);
+            assert_eq!(tags["TRACKNUMBER"], "1");
+            assert_eq!(tags["TRACKTOTAL"], "8");
+            assert_eq!(tags["DISCNUMBER"], "1");
+            assert_eq!(tags["DISCTOTAL"], "2");
+            assert_eq!(tags["DATE"], "1986-03-03");
+            assert_eq!(tags["ALBUMARTIST"], "Metallica");
+            assert!(!tags.contains_key("track"));
+        }
+
+        let guest = Track {
+            artist: Some("Dave Grohl".to_string()),
+            ..track
+        };
+        let tags: BTreeMap<_, _> =
+            track_metadata_tags(&album, &guest, 8, "flac", TagOptions::default())
+                .into_iter()
+                .collect();
+        assert_eq!(tags["ARTIST"], "Dave Grohl");
+        assert_eq!(tags["ALBUMARTIST"], "Metallica");
+
+        let compilation = TagOptions {
+            compilation: true,
+            ..TagOptions::default()
+        };
+        let tags: BTreeMap<_, _> = track_metadata_tags(&album, &guest, 8, "mp3", compilation)
+            .into_iter()
+            .collect();
+        assert_eq!(tags["artist"], "Dave Grohl");
+        assert_eq!(tags["album_artist"], "Various Artists");
+        assert_eq!(tags["compilation"], "1");
+
+        let original = TagOptions {
+            date_source: DateSource::Original,
+            ..TagOptions::default()
+        };
+        let date = |album: &Album| {
+            track_metadata_tags(album, &guest, 8, "mp3", original)
+                .into_iter()
+                .find(|(key, _)| key == "date")
+                .map(|(_, value)| value)
+        };
+        assert_eq!(date(&album).as_deref(), Some("1986-03-03"));
+        let reissue = Album {
+            release_date: Some("2017-11-10".to_string()),
+            original_date: Some("1986-03-03".to_string()),
+            ..album
+        };
+        assert_eq!(date(&reissue).as_deref(), Some("1986-03-03"));
+
+        let continued = TagOptions {
+            numbering_offset: 10,
+            ..TagOptions::default()
+        };
+        let tags: BTreeMap<_, _> = track_metadata_tags(&reissue, &guest, 8, "flac", continued)
+            .into_iter()
+            .collect();
+        assert_eq!(tags["TRACKNUMBER"], "11");
+        assert_eq!(tags["TRACKTOTAL"], "18");
+        assert!(!tags.contains_key("COMMENT"));
+
+        let linked = Album {
+            source_url: Some(format!("{MUSICBRAINZ_RELEASE_URL}/b84ee12a")),
+            ..reissue
+        };
+        let provenance = TagOptions {
+            embed_source_url: true,
+            ..TagOptions::default()
+        };
+        for (format, key) in [("mp3", "comment"), ("flac", "COMMENT")] {
+            let tags: BTreeMap<_, _> = track_metadata_tags(&linked, &guest, 8, format, provenance)
+                .into_iter()
+                .collect();
+            assert_eq!(tags[key], "https://musicbrainz.org/release/b84ee12a");
+        }
     }
 
     #[test]
-    fn test_looks_like_playlist() {
-        assert!(looks_like_playlist("https://www.youtube.com/playlist?list=PLxxx"));
-        assert!(looks_like_playlist("https://www.youtube.com/watch?v=123&list=PLyyy"));
-        assert!(!looks_like_playlist("https://www.youtube.com/watch?v=123"));
+    fn test_produced_files_escape_percent() {
+        let produced = ProducedFiles {
+            list: PathBuf::from("/tmp/100% done/list.txt"),
+        };
+        let mut command = Command::new("yt-dlp");
+        produced.record(&mut command);
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "--print-to-file",
+                "after_move:filepath",
+                "/tmp/100%% done/list.txt"
+            ]
+        );
     }
 
     #[test]
-    fn test_sanitize_filename() {
-        assert_eq!(sanitize_filename("Normal Title"), "Normal Title");
-        assert_eq!(sanitize_filename("Title/With\\Slashes"), "Title_With_Slashes");
-        assert_eq!(sanitize_filename("Title:With*Special?Chars"), "Title_With_Special_Chars");
-        assert_eq!(sanitize_filename("  Trimmed  "), "Trimmed");
-        assert_eq!(sanitize_filename("...dots..."), "dots");
-        assert_eq!(sanitize_filename(""), "track");
+    fn test_filename_budget_and_truncate_stem() {
+        let directory = Path::new("/music/Metallica");
+        assert_eq!(filename_budget(directory, 250).unwrap(), 250 - 16 - 1 - 5);
+        assert_eq!(filename_budget(directory, 30).unwrap(), MIN_FILENAME_LENGTH);
+
+        assert_eq!(truncate_stem("01 - Battery", 20), "01 - Battery");
+        assert_eq!(truncate_stem("01 - Orion (Instrumental)", 9), "01 - Orio");
+        assert_eq!(truncate_stem("01 - Orion. Live", 11), "01 - Orion");
+        assert_eq!(truncate_stem("01 - Ørjan Ødegård", 8), "01 - Ørj");
+
+        let track = Track {
+            title: "Orion (Instrumental)".to_string(),
+            artist: None,
+            disc: 1,
+            position: 8,
+            overall_index: 8,
+            duration: None,
+        };
+        let album = Album {
+            title: "Master of Puppets".to_string(),
+            artist: "Metallica".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 8,
+            tracks: Vec::new(),
+        };
+        let stem = |template: &str, max_length| {
+            OutputTemplate::parse(template).unwrap().track_stem(
+                &album,
+                &track,
+                0,
+                false,
+                FileNaming::Portable,
+                max_length,
+            )
+        };
+        assert_eq!(stem("{title} - {track}", 15), "Orion (Ins - 08");
+        assert_eq!(stem("{track} - {title}", 10), "08 - Orion");
+        // Too little room for the title to absorb the cut.
+        assert_eq!(stem("{title} - {album} {track}", 12), "Orion (Instr");
     }
 
     #[test]
-    fn test_build_single_search_query() {
-        let query = build_single_search_query("Metallica - Nothing Else Matters");
-        assert!(query.starts_with("ytsearch1:"));
-        assert!(query.contains("Metallica"));
-        assert!(query.contains("Nothing Else Matters"));
-        assert!(query.contains("audio"));
-        assert!(query.contains("-\"music video\""));
+    fn test_metadata_args_keep_special_characters() {
+        let title = r#"AC/DC "Live" & More"#;
+        let tags = vec![("title".to_string(), title.to_string())];
+        assert_eq!(
+            metadata_args(&tags),
+            vec!["-metadata".to_string(), format!("title={}", title)]
+        );
 
-        let query2 = build_single_search_query("some audio track");
-        assert!(!query2.contains("audio audio"));
+        let track = Track {
+            title: format!("{} 100%", title),
+            artist: None,
+            disc: 1,
+            position: 3,
+            overall_index: 3,
+            duration: None,
+        };
+        let mut album = Album {
+            title: "Live: 1991".to_string(),
+            artist: "AC/DC".to_string(),
+            release_date: None,
+            original_date: None,
+            disambiguation: None,
+            cover_url: None,
+            source_url: None,
+            total_discs: 1,
+            track_total: 0,
+            tracks: Vec::new(),
+        };
+        let template = OutputTemplate::parse(DEFAULT_ALBUM_TEMPLATE).unwrap();
+        let stem = template.track_stem(&album, &track, 0, false, FileNaming::Portable, usize::MAX);
+        assert_eq!(stem, "03 - AC_DC _Live_ & More 100%");
+        assert_eq!(
+            track_output_template(Path::new("/music"), &stem),
+            "/music/03 - AC_DC _Live_ & More 100%%.%(ext)s"
+        );
+        assert_eq!(
+            template.track_stem(&album, &track, 0, false, FileNaming::Raw, usize::MAX),
+            "03 - AC_DC \"Live\" & More 100%"
+        );
+
+        album.total_discs = 2;
+        let template = OutputTemplate::parse("{album} {disc}.{track} {artist}").unwrap();
+        assert_eq!(
+            template.track_stem(&album, &track, 0, false, FileNaming::Portable, usize::MAX),
+            "Live_ 1991 1.01-03 AC_DC"
+        );
+
+        let second_disc = Track {
+            disc: 2,
+            position: 1,
+            overall_index: 9,
+            ..track
+        };
+        let template = OutputTemplate::parse(DEFAULT_ALBUM_TEMPLATE).unwrap();
+        assert_eq!(
+            template.track_stem(
+                &album,
+                &second_disc,
+                0,
+                false,
+                FileNaming::Portable,
+                usize::MAX
+            ),
+            "02-01 - AC_DC _Live_ & More 100%"
+        );
+        assert_eq!(
+            template.track_stem(
+                &album,
+                &second_disc,
+                0,
+                true,
+                FileNaming::Portable,
+                usize::MAX
+            ),
+            "09 - AC_DC _Live_ & More 100%"
+        );
     }
 
     #[test]
@@ -1045,6 +10667,91 @@ mod tests {
         assert_eq!(query2, "just a query");
     }
 
+    #[test]
+    fn test_build_musicbrainz_alias_query() {
+        let ids = ["id-1".to_string(), "id-2".to_string()];
+        assert_eq!(
+            build_musicbrainz_alias_query("Hana", &ids),
+            "(release:\"Hana\" OR alias:\"Hana\") AND (arid:id-1 OR arid:id-2)"
+        );
+    }
+
+    #[test]
+    fn test_pick_recording_release() {
+        let releases: Vec<MbRecordingRelease> = serde_json::from_value(serde_json::json!([
+            { "id": "bootleg", "status": "Bootleg", "date": "1980",
+              "release-group": { "primary-type": "Album" } },
+            { "id": "single", "status": "Official", "date": "1983",
+              "release-group": { "primary-type": "Single" } },
+            { "id": "reissue", "status": "Official", "date": "2011",
+              "release-group": { "primary-type": "Album" } },
+            { "id": "album", "status": "Official", "date": "1984-03",
+              "release-group": { "primary-type": "Album" } },
+            { "id": "undated", "status": "Official",
+              "release-group": { "primary-type": "Album" } },
+        ]))
+        .unwrap();
+        assert_eq!(pick_recording_release(&releases).unwrap().id, "album");
+        assert_eq!(
+            pick_recording_release(&releases[..3]).unwrap().id,
+            "reissue"
+        );
+        assert_eq!(pick_recording_release(&releases[..2]).unwrap().id, "single");
+        assert!(pick_recording_release(&[]).is_none());
+
+        assert_eq!(
+            build_musicbrainz_recording_query("Van Halen - Jump"),
+            "recording:\"Jump\" AND artist:\"Van Halen\""
+        );
+    }
+
+    #[test]
+    fn test_localize_artist_credits() {
+        let mut detail: MbReleaseDetail = serde_json::from_value(serde_json::json!({
+            "title": "Hana",
+            "artist-credit": [{
+                "name": "宇多田ヒカル",
+                "artist": {
+                    "name": "宇多田ヒカル",
+                    "aliases": [
+                        { "name": "Utada", "locale": "en", "primary": false },
+                        { "name": "Hikaru Utada", "locale": "en_US", "primary": true },
+                        { "name": "ウタダ", "locale": "ja", "primary": false },
+                    ],
+                },
+            }],
+            "media": [{
+                "position": 1,
+                "tracks": [{
+                    "title": "Hana",
+                    "artist-credit": [{
+                        "name": "椎名林檎",
+                        "joinphrase": " & ",
+                        "artist": { "name": "椎名林檎" },
+                    }],
+                }],
+            }],
+        }))
+        .unwrap();
+        localize_artist_credits(&mut detail, "en");
+        assert_eq!(
+            detail.artist_credit[0].name.as_deref(),
+            Some("Hikaru Utada")
+        );
+        assert_eq!(
+            detail.media[0].tracks[0].artist_credit[0].name.as_deref(),
+            Some("椎名林檎")
+        );
+
+        let aliases = &detail.artist_credit[0].artist.as_ref().unwrap().aliases;
+        assert_eq!(locale_alias(aliases, "ja"), Some("ウタダ"));
+        assert_eq!(locale_alias(aliases, "de"), None);
+
+        assert_eq!(validate_mb_locale(" en-US ").unwrap(), "en_US");
+        assert!(validate_mb_locale("english").is_err());
+        assert!(validate_mb_locale("en_").is_err());
+    }
+
     #[test]
     fn test_normalize_playlist_url() {
         assert_eq!(
@@ -1065,6 +10772,310 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_playlist_url_from_entry_skips_mixes() {
+        let entry = |id: &str| serde_json::json!({ "id": id });
+        assert_eq!(
+            playlist_url_from_entry(&entry("PLabc")),
+            Some("https://www.youtube.com/playlist?list=PLabc".to_string())
+        );
+        assert_eq!(
+            playlist_url_from_entry(&entry("OLAK5uy_abc")),
+            Some("https://www.youtube.com/playlist?list=OLAK5uy_abc".to_string())
+        );
+        for id in ["RDdQw4w9WgXcQ", "RDMMabc", "RDAMPLabc"] {
+            assert_eq!(playlist_url_from_entry(&entry(id)), None, "{id}");
+        }
+        assert_eq!(
+            playlist_url_from_entry(&entry("RDCLAK5uy_abc")),
+            Some("https://www.youtube.com/playlist?list=RDCLAK5uy_abc".to_string())
+        );
+        assert!(!is_mix_playlist_id("RDCLAK5uy_abc"));
+        let mix_url = serde_json::json!({
+            "_type": "url",
+            "ie_key": "YoutubeTab",
+            "url": "https://www.youtube.com/watch?v=abc&list=RDabc",
+        });
+        assert_eq!(playlist_url_from_entry(&mix_url), None);
+        let mix_key = serde_json::json!({ "ie_key": "YoutubeMix", "url": "/playlist?list=xyz" });
+        assert_eq!(playlist_url_from_entry(&mix_key), None);
+
+        assert_eq!(
+            playlist_list_id("https://www.youtube.com/playlist?list=RDMMabc&index=2"),
+            Some("RDMMabc".to_string())
+        );
+        assert_eq!(
+            playlist_list_id("https://www.youtube.com/watch?v=abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_blocklist_match() {
+        let result = SearchResult {
+            title: "Song Title (8D Audio)".to_string(),
+            uploader: Some("Sped Up Nation".to_string()),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            duration: Some(185.0),
+        };
+        let blocklist = vec!["8d audio".to_string()];
+        assert_eq!(blocklist_match(&result, &blocklist), Some("8d audio"));
+
+        let blocklist = vec!["sped up".to_string()];
+        assert_eq!(blocklist_match(&result, &blocklist), Some("sped up"));
+
+        let blocklist = vec!["nightcore".to_string()];
+        assert_eq!(blocklist_match(&result, &blocklist), None);
+
+        assert_eq!(
+            describe_search_result(&result),
+            "Song Title (8D Audio) - Sped Up Nation [3:05]"
+        );
+    }
+
+    #[test]
+    fn test_select_search_result_prefers_allowlist() {
+        let result = |title: &str, uploader: &str| SearchResult {
+            title: title.to_string(),
+            uploader: Some(uploader.to_string()),
+            url: format!("https://www.youtube.com/watch?v={title}"),
+            duration: None,
+        };
+        let results = vec![
+            result("Song (sped up)", "Random Uploads"),
+            result("Song", "Fan Channel"),
+            result("Song (Official Audio)", "ArtistVEVO"),
+        ];
+        let mut config = AppConfig::default();
+        config.blocklist.push("sped up".to_string());
+
+        let picked = select_search_result(&results, &config, false).unwrap();
+        assert_eq!(picked.title, "Song");
+
+        config.allowlist.push("vevo".to_string());
+        let picked = select_search_result(&results, &config, true).unwrap();
+        assert_eq!(picked.title, "Song (Official Audio)");
+
+        config.allowlist = vec!["Label Records".to_string()];
+        assert!(select_search_result(&results, &config, true).is_none());
+        let picked = select_search_result(&results, &config, false).unwrap();
+        assert_eq!(picked.title, "Song");
+    }
+
+    #[test]
+    fn test_srt_to_lrc() {
+        let srt = "1\r\n00:00:12,340 --> 00:00:15,000\r\n<i>First line</i>\r\n\r\n2\r\n00:00:15,000 --> 00:00:17,000\r\nFirst line\r\n\r\n3\r\n01:02:03,456 --> 01:02:05,000\r\n{\\an8}Second\r\nline\r\n";
+        assert_eq!(
+            srt_to_lrc(srt),
+            "[00:12.34]First line\n[62:03.45]Second line\n"
+        );
+        assert_eq!(srt_to_lrc(""), "");
+    }
+
+    #[test]
+    fn test_parse_loudnorm_output() {
+        let stderr = r#"[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-23.54",
+	"input_tp" : "-5.12",
+	"input_lra" : "6.30",
+	"input_thresh" : "-33.90",
+	"output_i" : "-14.02",
+	"output_tp" : "-1.50",
+	"output_lra" : "5.10",
+	"output_thresh" : "-24.30",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.02"
+}
+"#;
+        let measured = parse_loudnorm_output(stderr).unwrap();
+        assert_eq!(measured.input_i, -23.54);
+        assert_eq!(measured.target_offset, 0.02);
+        assert!(parse_loudnorm_output("no json here").is_none());
+
+        let filter = loudnorm_filter(-14.0, Some(&measured));
+        assert!(filter.starts_with("loudnorm=I=-14:TP=-1.5:LRA=11:measured_I=-23.54"));
+        assert!(filter.ends_with("linear=true"));
+    }
+
+    #[test]
+    fn test_build_silence_trim() {
+        assert_eq!(build_silence_trim(false, None).unwrap(), None);
+        assert_eq!(build_silence_trim(true, None).unwrap(), Some(-60.0));
+        assert_eq!(build_silence_trim(true, Some(-45.0)).unwrap(), Some(-45.0));
+        assert!(build_silence_trim(true, Some(-10.0)).is_err());
+        assert!(build_silence_trim(false, Some(-45.0)).is_err());
+        assert!(silence_trim_filter(-60.0).contains("start_threshold=-60dB"));
+    }
+
+    #[test]
+    fn test_shaping_filters_order() {
+        let post = AudioPostProcess {
+            trim_silence: Some(-60.0),
+            fade_in: Some(2.0),
+            fade_out: Some(3.5),
+            ..Default::default()
+        };
+        let trim = silence_trim_filter(-60.0);
+        assert_eq!(
+            post.shaping_filters(),
+            vec![
+                trim.clone(),
+                "areverse".to_string(),
+                trim,
+                "afade=t=in:d=3.5".to_string(),
+                "areverse".to_string(),
+                "afade=t=in:d=2".to_string(),
+            ]
+        );
+
+        let fade_only = AudioPostProcess {
+            fade_in: Some(1.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            fade_only.shaping_filters(),
+            vec!["afade=t=in:d=1".to_string()]
+        );
+
+        assert!(validate_fade("--fade-in", Some(-1.0)).is_err());
+        assert_eq!(validate_fade("--fade-in", Some(0.0)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_replaygain_tags() {
+        let track = ReplayGainValues {
+            gain: -3.456,
+            peak: 0.98,
+        };
+        let album = ReplayGainValues {
+            gain: -4.0,
+            peak: 1.0,
+        };
+        let tags = replaygain_tags(Some(&track), Some(&album));
+        assert_eq!(
+            tags,
+            vec![
+                ("REPLAYGAIN_TRACK_GAIN".to_string(), "-3.46 dB".to_string()),
+                ("REPLAYGAIN_TRACK_PEAK".to_string(), "0.980000".to_string()),
+                ("REPLAYGAIN_ALBUM_GAIN".to_string(), "-4.00 dB".to_string()),
+                ("REPLAYGAIN_ALBUM_PEAK".to_string(), "1.000000".to_string()),
+            ]
+        );
+        assert!(replaygain_tags(None, None).is_empty());
+
+        assert_eq!(r128_gain(-23.0), "0");
+        assert_eq!(r128_gain(-14.0), "-2304");
+        assert_eq!(r128_gain(-30.5), "1920");
+        assert_eq!(r128_gain(-500.0), "32767");
+
+        assert_eq!(
+            album_loudness_graph(2, -18.0),
+            "[0:a:0]aresample=48000,aformat=sample_fmts=fltp:channel_layouts=stereo[a0];\
+             [1:a:0]aresample=48000,aformat=sample_fmts=fltp:channel_layouts=stereo[a1];\
+             [a0][a1]concat=n=2:v=0:a=1,loudnorm=I=-18:TP=-1.5:LRA=11:print_format=json"
+        );
+    }
+
+    #[test]
+    fn test_album_loudness() {
+        let loudness = album_loudness(&[(-10.0, 100.0), (-10.0, 300.0)]).unwrap();
+        assert!((loudness - -10.0).abs() < 1e-9);
+
+        // Loudness is averaged as energy, so the loud track dominates the long quiet one.
+        let loudness = album_loudness(&[(-10.0, 100.0), (-20.0, 300.0)]).unwrap();
+        assert!((loudness - -14.88).abs() < 0.01);
+
+        assert_eq!(album_loudness(&[(f64::NEG_INFINITY, 100.0)]), None);
+    }
+
+    #[test]
+    fn test_build_format_chain() {
+        let chain = build_format_chain(
+            "flac",
+            &["M4A".to_string(), "flac".to_string(), "mp3".to_string()],
+        )
+        .unwrap();
+        assert_eq!(chain, vec!["flac", "m4a", "mp3"]);
+        assert_eq!(
+            describe_format_chain(&chain),
+            "flac (falling back to m4a, mp3)"
+        );
+        assert_eq!(describe_format_chain(&chain[..1]), "flac");
+        assert!(build_format_chain("flac", &[" ".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_is_format_failure() {
+        assert!(is_format_failure(
+            "ERROR: Postprocessing: audio conversion failed: ..."
+        ));
+        assert!(is_format_failure(
+            "ERROR: [youtube] abc: Requested format is not available"
+        ));
+        assert!(!is_format_failure(
+            "ERROR: [youtube] abc: Video unavailable"
+        ));
+        assert!(!is_format_failure(
+            "ERROR: unable to download webpage: HTTP Error 403"
+        ));
+    }
+
+    #[test]
+    fn test_validate_download_args() {
+        let validate = |argv: &[&str]| {
+            let (args, mode) = match Cli::try_parse_from(argv).unwrap().command {
+                Commands::Single(args) => (args, DownloadMode::Single),
+                Commands::Album(args) => (args, DownloadMode::Album),
+                _ => unreachable!(),
+            };
+            validate_download_args(&args, mode)
+        };
+        assert!(validate(&["bippi", "album", "x", "--reverse"]).is_ok());
+        assert!(validate(&["bippi", "single", "x", "--reverse"]).is_err());
+        assert!(validate(&["bippi", "single", "x", "--no-sanitize"]).is_err());
+        assert!(validate(&["bippi", "album", "x", "--stdout"]).is_err());
+        assert!(validate(&["bippi", "album", "x", "--track-number", "3"]).is_err());
+        assert!(validate(&["bippi", "single", "x", "--track-number", "0"]).is_err());
+        assert!(validate(&["bippi", "album", "x", "--allow-mix", "--mix-limit", "0"]).is_err());
+        assert!(validate(&["bippi", "single", "x", "--track-number", "3"]).is_ok());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_validate_date_after() {
+        assert_eq!(
+            validate_date_after(Some("20240131".to_string())).unwrap(),
+            Some("20240131".to_string())
+        );
+        assert!(validate_date_after(Some("now-7days".to_string())).is_ok());
+        assert!(validate_date_after(Some("today-1week".to_string())).is_ok());
+        assert!(validate_date_after(Some("2024-01-31".to_string())).is_err());
+        assert!(validate_date_after(Some("yesterday".to_string())).is_err());
+        assert_eq!(validate_date_after(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_build_duration_filter() {
+        assert_eq!(build_duration_filter(None, None).unwrap(), None);
+        assert_eq!(
+            build_duration_filter(Some(60), None).unwrap(),
+            Some("duration >= 60".to_string())
+        );
+        assert_eq!(
+            build_duration_filter(Some(60), Some(600)).unwrap(),
+            Some("duration >= 60 & duration <= 600".to_string())
+        );
+        assert!(build_duration_filter(Some(600), Some(60)).is_err());
+    }
+
     #[test]
     fn test_format_artist_credit() {
         let credits = vec![
@@ -1084,4 +11095,94 @@ mod tests {
         let empty: Vec<MbArtistCredit> = vec![];
         assert_eq!(format_artist_credit(&empty), "");
     }
+
+    #[test]
+    fn test_convert_release_track_artists() {
+        let detail: MbReleaseDetail = serde_json::from_value(serde_json::json!({
+            "title": "Pulp Fiction",
+            "artist-credit": [{ "name": "Various Artists" }],
+            "media": [{
+                "position": 1,
+                "tracks": [
+                    {
+                        "position": 1,
+                        "title": "Misirlou",
+                        "artist-credit": [{ "name": "Dick Dale & His Del-Tones" }]
+                    },
+                    {
+                        "position": 2,
+                        "title": "Jungle Boogie",
+                        "recording": { "artist-credit": [{ "name": "Kool & the Gang" }] }
+                    },
+                    { "position": 3, "title": "Interlude" }
+                ]
+            }]
+        }))
+        .unwrap();
+        let album = convert_release_detail(detail).unwrap();
+        let artists: Vec<_> = album
+            .tracks
+            .iter()
+            .map(|track| track_artist(&album, track))
+            .collect();
+        assert_eq!(
+            artists,
+            [
+                "Dick Dale & His Del-Tones",
+                "Kool & the Gang",
+                "Various Artists"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_lookup_failure() {
+        assert!(is_lookup_failure(&AppError::MusicBrainzTimeout(30)));
+        assert!(is_lookup_failure(&AppError::EmptyRelease("id".to_string())));
+        assert!(is_lookup_failure(&AppError::MusicBrainzResponse(
+            "expected value at line 1 column 1".to_string()
+        )));
+        assert!(!is_lookup_failure(&AppError::Message(
+            "cancelled".to_string()
+        )));
+        let fallback = AppError::MetadataLookupFailed {
+            backend: "MusicBrainz",
+            detail: AppError::MusicBrainzTimeout(30).to_string(),
+        };
+        assert!(
+            fallback
+                .to_string()
+                .starts_with("MusicBrainz lookup failed: MusicBrainz did not answer within 30s")
+        );
+    }
+
+    #[test]
+    fn test_first_release_with_tracks_skips_empty_media() {
+        let candidates: Vec<MbReleaseSearchEntry> = serde_json::from_value(serde_json::json!([
+            { "id": "empty", "title": "Placeholder", "score": 100 },
+            { "id": "full", "title": "Master of Puppets", "score": 100 },
+        ]))
+        .unwrap();
+        let fetch = |id: &str| {
+            let media = if id == "full" {
+                serde_json::json!([{ "position": 1, "tracks": [{ "title": "Battery" }] }])
+            } else {
+                serde_json::json!([])
+            };
+            let detail: MbReleaseDetail = serde_json::from_value(
+                serde_json::json!({ "id": id, "title": id, "media": media }),
+            )
+            .unwrap();
+            convert_release_detail(detail)
+        };
+
+        let album = first_release_with_tracks(&candidates[0], &candidates, fetch).unwrap();
+        assert_eq!(album.title, "full");
+        assert_eq!(album.tracks.len(), 1);
+
+        match first_release_with_tracks(&candidates[0], &candidates[..1], fetch) {
+            Err(AppError::EmptyRelease(title)) => assert_eq!(title, "empty"),
+            other => panic!("expected an empty release error, got {:?}", other),
+        }
+    }
 }